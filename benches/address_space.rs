@@ -0,0 +1,59 @@
+//! Benchmarks of finding the memory behind an address: single
+//! accesses through `MemMap32`, an address space with many regions,
+//! and a fetch-decode-execute loop.
+//!
+//! Run with `cargo bench --bench address_space`.
+
+extern crate armor;
+extern crate criterion;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use armor::address::{AddressSpace, LeasableRegion, MemMap32, RandomAccessMemory, Region};
+use armor::assembler;
+use armor::computer::Computer;
+
+fn mem_map_accesses(c: &mut Criterion) {
+    let mut mm = MemMap32::new(vec![0; 0x100]);
+    c.bench_function("get32 from boot ROM", |b| b.iter(|| mm.get32(black_box(0x80), false)));
+    c.bench_function("get32 from low RAM", |b| b.iter(|| mm.get32(black_box(0x00100000), false)));
+    c.bench_function("get32 from DRAM", |b| b.iter(|| mm.get32(black_box(0x80000000), false)));
+    c.bench_function("put32 to DRAM", |b| b.iter(|| mm.put32(black_box(0x80000000), 1, false)));
+}
+
+fn many_regions(c: &mut Criterion) {
+    // 1024 RAM chips of 4KB each, touched in a scattered order.
+    let mut address_space = AddressSpace::from_range(0, 0x3fffff);
+    for i in 0..0x400 {
+        address_space.lease(Box::new(RandomAccessMemory::new(i * 0x1000, i * 0x1000 + 0xfff)));
+    }
+    let mut word = [0; 4];
+    let mut addr = 0;
+    c.bench_function("read word from one of 1024 regions", |b| b.iter(|| {
+        addr = (addr + 0x1234c) & 0x3ffffc;
+        address_space.read_cells_into(black_box(addr), &mut word)
+    }));
+}
+
+fn fetch_and_execute(c: &mut Criterion) {
+    let boot_code = assembler::assemble("
+            ldr r0, =0x80000000
+            mov r1, #0
+        loop:
+            ldr r2, [r0, r1, lsl #2]
+            add r2, r2, r1
+            str r2, [r0, r1, lsl #2]
+            add r1, r1, #1
+            and r1, r1, #0xff
+            b loop
+    ").unwrap();
+    let mut computer = Computer::new(boot_code);
+    c.bench_function("execute 1000 instructions", |b| b.iter(|| {
+        for _ in 0..1000 {
+            computer.execute_next_instruction().unwrap();
+        }
+    }));
+}
+
+criterion_group!(benches, mem_map_accesses, many_regions, fetch_and_execute);
+criterion_main!(benches);
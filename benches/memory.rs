@@ -0,0 +1,93 @@
+//! Compare the page-backed `RandomAccessMemory` with the hash map of
+//! cells it replaced, on a 16MB memset and reads of the result.
+//!
+//! Run with `cargo bench --bench memory`.
+
+extern crate armor;
+
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use armor::address::{Address, Addressable, Cell, RandomAccessMemory, Region};
+
+const DRAM_START: Address = 0x80000000;
+const DRAM_END: Address = 0xffffffff;
+const MEMSET_SIZE: usize = 0x1000000;
+
+/// RAM as it used to be: a hash map entry per cell written.
+struct HashMapMemory {
+    start: Address,
+    end: Address,
+    cells: HashMap<Address, Cell>,
+}
+
+static UNWRITTEN_CELL: Cell = 0;
+
+impl Addressable for HashMapMemory {
+    fn get(&self, addr: Address) -> Option<&Cell> {
+        match self.cells.get(&addr) {
+            Some(cell) => Some(cell),
+            None if self.contains_address(&addr) => Some(&UNWRITTEN_CELL),
+            None => None,
+        }
+    }
+
+    fn get_mut(&mut self, addr: Address) -> Option<&mut Cell> {
+        if !self.contains_address(&addr) {
+            return None;
+        }
+        Some(self.cells.entry(addr).or_insert(UNWRITTEN_CELL))
+    }
+}
+
+impl Region for HashMapMemory {
+    fn start(&self) -> Address {
+        self.start
+    }
+
+    fn end(&self) -> Address {
+        self.end
+    }
+}
+
+fn time<F: FnOnce()>(f: F) -> Duration {
+    let started = Instant::now();
+    f();
+    started.elapsed()
+}
+
+/// Fill 16MB a 4KB block at a time, then read it back a word at a
+/// time, and report how long each took.
+fn run<R: Region>(name: &str, ram: &mut R) {
+    let block = vec![0xa5; 0x1000];
+    let memset = time(|| {
+        for offset in (0..MEMSET_SIZE).step_by(block.len()) {
+            ram.write_cells(&block, DRAM_START + offset as Address).unwrap();
+        }
+    });
+
+    let mut sum = 0u64;
+    let mut word = [0; 4];
+    let reads = time(|| {
+        for offset in (0..MEMSET_SIZE).step_by(4) {
+            ram.read_cells_into(DRAM_START + offset as Address, &mut word).unwrap();
+            sum += word[0] as u64;
+        }
+    });
+    assert_eq!(sum, 0xa5 * MEMSET_SIZE as u64 / 4);
+
+    println!("{:<20} memset {:>10.1?}   word reads {:>10.1?}", name, memset, reads);
+}
+
+fn main() {
+    let mut pages = RandomAccessMemory::new(DRAM_START, DRAM_END);
+    run("RandomAccessMemory", &mut pages);
+    println!("{:<20} {} pages, ~{} MB", "", pages.allocated_pages(), pages.allocated_pages() * 0x1000 / 0x100000);
+
+    let mut hash_map = HashMapMemory { start: DRAM_START, end: DRAM_END, cells: HashMap::new() };
+    run("HashMap per cell", &mut hash_map);
+    // Each entry holds a key, a value and a control byte.
+    let bytes = hash_map.cells.capacity() * (mem::size_of::<(Address, Cell)>() + 1);
+    println!("{:<20} {} entries, ~{} MB", "", hash_map.cells.len(), bytes / 0x100000);
+}
@@ -4,6 +4,7 @@ use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io;
+use std::path::Path;
 use armor::address;
 use armor::address::{
     Region
@@ -13,6 +14,7 @@ use armor::registers::{
     ProgramStatusRegister,
     RegisterBank,
 };
+use armor::symbols::SymbolTable;
 
 type CommandHandler = &'static Fn(&[&str], &mut Computer);
 
@@ -123,26 +125,39 @@ Top-level commands
 }
 
 fn handle_step(_args: &[&str], computer: &mut Computer) {
-    computer.execute_next_instruction();
+    if let Err(err) = computer.execute_next_instruction() {
+        println!("{}", err);
+    }
 }
 
 fn handle_run(_args: &[&str], computer: &mut Computer) {
     println!("");
-    let mut prev_addr: Option<u32> = None;
+    let mut next_addr: Option<u32> = None;
     loop {
         // TODO: refactor code copied from handle_print_code.
         let pc_addr = computer.cpu.register_file.lookup(RegisterBank::R15).unwrap().bits;
-        assert!(pc_addr % 4 == 0);
-        if prev_addr.is_some() && pc_addr != prev_addr.unwrap() + 4 {
+        if next_addr.is_some() && pc_addr != next_addr.unwrap() {
             println!("");
         }
-        print!("\t0x{:08x}: ", pc_addr);
-        match computer.instruction_at(pc_addr as u64) {
+        let size = computer.instruction_size(pc_addr as address::Address);
+        print!("\t{}: ", code_address(computer, pc_addr as address::Address));
+        match computer.disassemble(pc_addr as u64) {
             Err(s) => println!("{}", s),
-            Ok(instr) => println!("{}", instr.as_str()),
+            Ok(text) => println!("{}", text),
+        }
+        if let Err(err) = computer.execute_next_instruction() {
+            println!("{}", err);
+            break;
         }
-        computer.execute_next_instruction();
-        prev_addr = Some(pc_addr);
+        next_addr = Some(pc_addr.wrapping_add(size));
+    }
+}
+
+/// Format an address like `0x00008010 <main+16>`.
+fn code_address(computer: &Computer, addr: address::Address) -> String {
+    match computer.symbols.describe(addr as u32) {
+        Some(name) => format!("0x{:08x} <{}>", addr, name),
+        None => format!("0x{:08x}", addr),
     }
 }
 
@@ -160,7 +175,6 @@ fn handle_print_registers(_args: &[&str], computer: &mut Computer) {
 
 fn handle_print_code(_args: &[&str], computer: &mut Computer) {
     let pc_addr = computer.cpu.register_file.lookup(RegisterBank::R15).unwrap().bits;
-    assert!(pc_addr % 4 == 0);
 
     let instrs_before_and_after = 5;
     let amount = instrs_before_and_after * 4;
@@ -183,13 +197,18 @@ fn handle_print_code(_args: &[&str], computer: &mut Computer) {
         } else {
             print!("\t ");
         }
-        print!(" 0x{:08x}: ", addr);
+        print!(" {}: ", code_address(computer, addr));
 
-        match computer.instruction_at(addr) {
+        match computer.disassemble(addr) {
             Err(s) => println!("{}", s),
-            Ok(instr) => println!("{:?}", instr),
+            Ok(text) => println!("{}", text),
         }
-        addr += 4;
+        // Thumb code before the PC may have been entered halfway
+        // through a 32-bit instruction, so resync on the PC rather
+        // than stepping over it.
+        let next = addr + computer.instruction_size(addr) as address::Address;
+        let pc = pc_addr as address::Address;
+        addr = if addr < pc && next > pc { pc } else { next };
     }
 }
 
@@ -239,6 +258,46 @@ fn load_boot_code(path: String) -> Result<Vec<u8>, io::Error> {
     Ok(buf)
 }
 
+/// Read symbols from an ELF file, or else from an `nm`-style map.
+fn load_symbols(path: String) -> SymbolTable {
+    let data = match load_boot_code(path) {
+        Ok(data) => data,
+        Err(_) => panic!("Unexpected error while loading symbols file"),
+    };
+    let symbols = if data.starts_with(b"\x7fELF") {
+        SymbolTable::from_elf(&data).map_err(|e| e.to_string())
+    } else {
+        SymbolTable::from_map(&String::from_utf8_lossy(&data)).map_err(|e| e.to_string())
+    };
+    match symbols {
+        Ok(symbols) => symbols,
+        Err(e) => panic!("Couldn't read symbols: {}", e),
+    }
+}
+
+/// Set up a computer from a boot file. ELF executables bring their
+/// own layout, byte order and symbols; Intel HEX and S-record images
+/// are recognized by their extension; anything else is a raw ROM
+/// image.
+fn load_computer(path: &str, boot_code: Vec<u8>) -> Computer {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let computer = if boot_code.starts_with(b"\x7fELF") {
+        Computer::from_elf(&boot_code).map_err(|e| e.to_string())
+    } else {
+        match &extension.to_lowercase()[..] {
+            "hex" | "ihex" | "ihx" =>
+                Computer::from_ihex(&String::from_utf8_lossy(&boot_code)).map_err(|e| e.to_string()),
+            "srec" | "s19" | "s28" | "s37" | "mot" =>
+                Computer::from_srec(&String::from_utf8_lossy(&boot_code)).map_err(|e| e.to_string()),
+            _ => Ok(Computer::new(boot_code)),
+        }
+    };
+    match computer {
+        Ok(computer) => computer,
+        Err(e) => panic!("Couldn't load boot file: {}", e),
+    }
+}
+
 fn main() {
     println!("
 ARMOR Debugging Interface
@@ -246,11 +305,14 @@ ARMOR Debugging Interface
 ");
 
     if let Some(boot_bin_file) = env::args().nth(1) {
-        // TODO: endianness
         println!("Loading boot file: {}", boot_bin_file);
-        match load_boot_code(boot_bin_file) {
+        match load_boot_code(boot_bin_file.clone()) {
             Ok(boot_code) => {
-                let mut computer = Computer::new(boot_code);
+                let mut computer = load_computer(&boot_bin_file, boot_code);
+                if let Some(symbols_file) = env::args().nth(2) {
+                    println!("Loading symbols: {}", symbols_file);
+                    computer.symbols = load_symbols(symbols_file);
+                }
                 debugger_repl(&mut computer).is_ok();
             },
             Err(_) => panic!("Unexpected error while loading boot code file"),
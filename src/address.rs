@@ -1,9 +1,13 @@
 #![allow(dead_code)]
 
 use std::{cmp, usize, mem};
-use std::collections::HashMap;
+use std::cell::RefCell;
 use std::ops::{Index, IndexMut};
 
+use elf;
+use elf::{ElfError, ElfFile};
+use error::EmulationError;
+
 /// Unique identifier for a location in an address space.
 pub type Address = u64;
 
@@ -67,43 +71,64 @@ pub trait Region : Addressable {
         self.contains_address(&(*region).start()) || self.contains_address(&(*region).end())
     }
 
-    fn write_cells(&mut self, data: &[Cell], addr: Address) {
-        assert!(addr >= self.start());
+    /// Write cells starting at an address. Nothing is written unless
+    /// every cell is writable, so a write that runs into ROM or
+    /// unmapped space has no partial effect.
+    fn write_cells(&mut self, data: &[Cell], addr: Address) -> Result<(), EmulationError> {
+        write_each_cell(self, data, addr)
+    }
 
-        if data.len() == 0 {
-            return;
-        }
+    fn read_cells(&self, low: Address, high: Address) -> Result<Vec<Cell>, EmulationError> {
+        assert!(low <= high);
+        let mut ret = vec![0; (high - low + 1) as usize];
+        self.read_cells_into(low, &mut ret)?;
+        Ok(ret)
+    }
 
-        // TODO: clean up using CellCount
-        let last_addr = addr + data.len() as u64 - 1;
-        assert!(last_addr <= self.end());
+    /// Fill a buffer with the cells starting at an address, failing
+    /// at the first one that can't be read. Regions override this to
+    /// read a whole span at once.
+    fn read_cells_into(&self, addr: Address, buf: &mut [Cell]) -> Result<(), EmulationError> {
+        read_each_cell(self, addr, buf)
+    }
+}
 
-        for i in addr..(last_addr + 1) {
-            match self.get_mut(i) {
-                Some(cell) => {
-                    assert!(i <= usize::MAX as u64);
-                    *cell = data[(i - addr) as usize];
-                }
-                None => panic!("No memory cell at address {:#0x}", i),
-            }
+/// `Region::read_cells_into` done a cell at a time, for spans that a
+/// region can't read at once.
+fn read_each_cell<R: Region + ?Sized>(region: &R, addr: Address, buf: &mut [Cell]) -> Result<(), EmulationError> {
+    for (i, cell) in buf.iter_mut().enumerate() {
+        let cell_addr = addr + i as Address;
+        match region.get(cell_addr) {
+            Some(&value) => *cell = value,
+            None => return Err(EmulationError::UnmappedAccess { addr: cell_addr, opcode: None }),
         }
     }
+    Ok(())
+}
 
-    fn read_cells(&self, low: Address, high: Address) -> Option<Vec<Cell>> {
-        assert!(low <= high);
-        let size = (high - low + 1) as usize;
-        let mut ret = Vec::with_capacity(size);
-        for i in low..(high + 1) {
-            match self.get(i) {
-                Some(&cell) => {
-                    assert!(i <= usize::MAX as u64);
-                    ret.push(cell);
-                }
-                None => return None,
-            }
+/// `Region::write_cells` done a cell at a time.
+fn write_each_cell<R: Region + ?Sized>(region: &mut R, data: &[Cell], addr: Address) -> Result<(), EmulationError> {
+    if data.len() == 0 {
+        return Ok(());
+    }
+
+    // TODO: clean up using CellCount
+    let last_addr = addr + data.len() as u64 - 1;
+    for i in addr..(last_addr + 1) {
+        if region.get_mut(i).is_none() {
+            return Err(if region.get(i).is_some() {
+                EmulationError::ReadOnlyWrite { addr: i, opcode: None }
+            } else {
+                EmulationError::UnmappedAccess { addr: i, opcode: None }
+            });
         }
-        Some(ret)
     }
+
+    for i in addr..(last_addr + 1) {
+        assert!(i <= usize::MAX as u64);
+        *region.get_mut(i).unwrap() = data[(i - addr) as usize];
+    }
+    Ok(())
 }
 
 /// A trait for a region of addressable space that can lease control
@@ -130,23 +155,33 @@ pub trait LeasableRegion : Region {
 pub struct AddressSpace {
     start: Address,
     end: Address,
+    /// Leased regions in order of address.
     mapped_regions: Vec<Box<Region>>,
+    /// The start and end of each leased region, so that lookups can
+    /// binary search without calling into the regions.
+    bounds: Vec<(Address, Address)>,
 }
 
 impl AddressSpace {
     fn new() -> AddressSpace {
-        AddressSpace {
-            start: 0x00000000,
-            end: 0xffffffff,
-            mapped_regions: vec![],
-        }
+        AddressSpace::from_range(0x00000000, 0xffffffff)
     }
 
-    fn from_range(a: Address, b: Address) -> AddressSpace {
+    pub fn from_range(a: Address, b: Address) -> AddressSpace {
         AddressSpace {
             start: cmp::min(a, b),
             end: cmp::max(a, b),
             mapped_regions: vec![],
+            bounds: vec![],
+        }
+    }
+
+    /// Index of the leased region containing an address.
+    fn index_at(&self, addr: Address) -> Option<usize> {
+        let after = self.bounds.partition_point(|&(start, _)| start <= addr);
+        match after.checked_sub(1) {
+            Some(i) if addr <= self.bounds[i].1 => Some(i),
+            _ => None,
         }
     }
 }
@@ -156,23 +191,26 @@ impl LeasableRegion for AddressSpace {
         if !self.contains_region(candidate) {
             return false;
         }
-        for subregion in self.mapped_regions.iter() {
-            if subregion.overlaps_region(candidate) {
-                return false;
-            }
-        }
-        return true;
+        // Only the regions either side of where the candidate would
+        // go can overlap it.
+        let i = self.bounds.partition_point(|&(start, _)| start <= candidate.start());
+        let overlaps_before = i > 0 && self.bounds[i - 1].1 >= candidate.start();
+        let overlaps_after = i < self.bounds.len() && self.bounds[i].0 <= candidate.end();
+        !overlaps_before && !overlaps_after
     }
 
     fn lease(&mut self, candidate: Box<Region>) -> Option<&mut Box<Region>> {
         if self.available_for_lease(&*candidate) {
-            self.mapped_regions.push(candidate);
-            self.mapped_regions.last_mut()
+            let i = self.bounds.partition_point(|&(start, _)| start <= candidate.start());
+            self.bounds.insert(i, (candidate.start(), candidate.end()));
+            self.mapped_regions.insert(i, candidate);
+            Some(&mut self.mapped_regions[i])
         } else {
             None
         }
     }
 
+    /// The leased regions, in order of address.
     fn leased_subregions(&self) -> &[Box<Region>] {
         &self.mapped_regions[..]
     }
@@ -182,15 +220,14 @@ impl LeasableRegion for AddressSpace {
     }
 
     fn leased_subregion_at(&self, addr: Address) -> Option<&Box<Region>> {
-        self.leased_subregions()
-            .iter()
-            .find(|ref r| r.contains_address(&addr))
+        self.index_at(addr).map(move |i| &self.mapped_regions[i])
     }
 
     fn leased_subregion_at_mut(&mut self, addr: Address) -> Option<&mut Box<Region>> {
-        self.leased_subregions_mut()
-            .iter_mut()
-            .find(|ref r| r.contains_address(&addr))
+        match self.index_at(addr) {
+            Some(i) => Some(&mut self.mapped_regions[i]),
+            None => None,
+        }
     }
 }
 
@@ -218,35 +255,113 @@ impl Region for AddressSpace {
     fn end(&self) -> Address {
         self.end
     }
+
+    /// Hand a span inside one subregion to that subregion, rather
+    /// than looking up each cell.
+    fn write_cells(&mut self, data: &[Cell], addr: Address) -> Result<(), EmulationError> {
+        if data.len() == 0 {
+            return Ok(());
+        }
+        let last = addr + data.len() as Address - 1;
+        match self.index_at(addr) {
+            Some(i) if last <= self.bounds[i].1 => self.mapped_regions[i].write_cells(data, addr),
+            _ => write_each_cell(self, data, addr),
+        }
+    }
+
+    fn read_cells_into(&self, addr: Address, buf: &mut [Cell]) -> Result<(), EmulationError> {
+        if buf.len() == 0 {
+            return Ok(());
+        }
+        let last = addr + buf.len() as Address - 1;
+        match self.index_at(addr) {
+            Some(i) if last <= self.bounds[i].1 => self.mapped_regions[i].read_cells_into(addr, buf),
+            _ => read_each_cell(self, addr, buf),
+        }
+    }
 }
 
 
-struct RandomAccessMemory {
+/// Cells in a page of RAM.
+const PAGE_SIZE: usize = 0x1000;
+
+/// Pages covered by each second-level page table.
+const TABLE_SIZE: usize = 0x400;
+
+/// RAM that allocates 4KB pages as they're first written, so that a
+/// large, sparsely used chip costs little. Pages are found through a
+/// two-level table indexed by offset from the start of the chip.
+pub struct RandomAccessMemory {
     start: Address,
     end: Address,
-    cells: HashMap<Address, Cell>,
+    tables: Vec<Option<Box<[Option<Box<[Cell]>>]>>>,
 }
 
 impl RandomAccessMemory {
-    fn new(a: Address, b: Address) -> RandomAccessMemory {
+    pub fn new(a: Address, b: Address) -> RandomAccessMemory {
+        let start = cmp::min(a, b);
+        let end = cmp::max(a, b);
+        let page_count = ((end - start) as usize / PAGE_SIZE) + 1;
+        let table_count = (page_count + TABLE_SIZE - 1) / TABLE_SIZE;
         RandomAccessMemory {
-            start: cmp::min(a, b),
-            end: cmp::max(a, b),
-            cells: HashMap::new(),
+            start: start,
+            end: end,
+            tables: (0..table_count).map(|_| None).collect(),
+        }
+    }
+
+    /// Split an address into the indices of its table, page and cell.
+    fn indices(&self, addr: Address) -> (usize, usize, usize) {
+        let offset = (addr - self.start) as usize;
+        let page = offset / PAGE_SIZE;
+        (page / TABLE_SIZE, page % TABLE_SIZE, offset % PAGE_SIZE)
+    }
+
+    /// The page holding an address, if it's been written.
+    fn page(&self, addr: Address) -> Option<&[Cell]> {
+        let (table, page, _) = self.indices(addr);
+        match self.tables[table] {
+            Some(ref pages) => pages[page].as_ref().map(|page| &page[..]),
+            None => None,
         }
     }
+
+    /// The page holding an address, allocating it if need be.
+    fn page_mut(&mut self, addr: Address) -> &mut [Cell] {
+        let (table, page, _) = self.indices(addr);
+        let pages = self.tables[table].get_or_insert_with(|| vec![None; TABLE_SIZE].into_boxed_slice());
+        pages[page].get_or_insert_with(|| vec![UNWRITTEN_CELL; PAGE_SIZE].into_boxed_slice())
+    }
+
+    /// Number of pages allocated so far.
+    pub fn allocated_pages(&self) -> usize {
+        self.tables.iter().filter_map(|table| table.as_ref())
+                   .map(|pages| pages.iter().filter(|page| page.is_some()).count())
+                   .sum()
+    }
 }
 
+/// Value read from RAM cells that haven't been written yet.
+static UNWRITTEN_CELL: Cell = 0;
+
 impl Addressable for RandomAccessMemory {
     fn get(&self, addr: Address) -> Option<&Cell> {
-        self.cells.get(&addr)
+        if !self.contains_address(&addr) {
+            return None;
+        }
+        let (_, _, index) = self.indices(addr);
+        match self.page(addr) {
+            Some(page) => Some(&page[index]),
+            None => Some(&UNWRITTEN_CELL),
+        }
     }
 
     fn get_mut(&mut self, addr: Address) -> Option<&mut Cell> {
-        if let None = self.cells.get(&addr) {
-            self.cells.insert(addr, Default::default());
+        if !self.contains_address(&addr) {
+            return None;
         }
-        self.cells.get_mut(&addr)
+        let (_, _, index) = self.indices(addr);
+        Some(&mut self.page_mut(addr)[index])
     }
 }
 
@@ -258,6 +373,42 @@ impl Region for RandomAccessMemory {
     fn end(&self) -> Address {
         self.end
     }
+
+    fn write_cells(&mut self, data: &[Cell], addr: Address) -> Result<(), EmulationError> {
+        let last = addr + data.len() as Address;
+        if data.len() == 0 || !self.contains_address(&addr) || !self.contains_address(&(last - 1)) {
+            return write_each_cell(self, data, addr);
+        }
+        // Copy a page at a time.
+        let mut done = 0;
+        while done < data.len() {
+            let (_, _, index) = self.indices(addr + done as Address);
+            let len = cmp::min(PAGE_SIZE - index, data.len() - done);
+            self.page_mut(addr + done as Address)[index..index + len].copy_from_slice(&data[done..done + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn read_cells_into(&self, addr: Address, buf: &mut [Cell]) -> Result<(), EmulationError> {
+        let last = addr + buf.len() as Address;
+        if buf.len() == 0 || !self.contains_address(&addr) || !self.contains_address(&(last - 1)) {
+            return read_each_cell(self, addr, buf);
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let (_, _, index) = self.indices(addr + done as Address);
+            let len = cmp::min(PAGE_SIZE - index, buf.len() - done);
+            match self.page(addr + done as Address) {
+                Some(page) => buf[done..done + len].copy_from_slice(&page[index..index + len]),
+                None => for cell in buf[done..done + len].iter_mut() {
+                    *cell = UNWRITTEN_CELL;
+                },
+            }
+            done += len;
+        }
+        Ok(())
+    }
 }
 
 struct ReadOnlyMemory {
@@ -283,6 +434,9 @@ impl ReadOnlyMemory {
 
 impl Addressable for ReadOnlyMemory {
     fn get(&self, addr: Address) -> Option<&Cell> {
+        if addr < self.start {
+            return None;
+        }
         let index = (addr - self.start) as usize;
         self.cells.get(index)
     }
@@ -300,8 +454,90 @@ impl Region for ReadOnlyMemory {
     fn end(&self) -> Address {
         self.end
     }
+
+    fn read_cells_into(&self, addr: Address, buf: &mut [Cell]) -> Result<(), EmulationError> {
+        if addr < self.start {
+            return read_each_cell(self, addr, buf);
+        }
+        let index = (addr - self.start) as usize;
+        match self.cells.get(index..index + buf.len()) {
+            Some(cells) => {
+                buf.copy_from_slice(cells);
+                Ok(())
+            },
+            None => read_each_cell(self, addr, buf),
+        }
+    }
 }
 
+/// Width of an access to a device.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessSize {
+    Byte,
+    Halfword,
+    Word,
+}
+
+impl AccessSize {
+    fn cell_count(self) -> CellCount {
+        match self {
+            AccessSize::Byte => 1,
+            AccessSize::Halfword => 2,
+            AccessSize::Word => 4,
+        }
+    }
+}
+
+/// A memory-mapped peripheral. Unlike memory, a device sees each
+/// access whole, so reading or writing a register can have side
+/// effects. Register values don't depend on the byte order of the
+/// access.
+pub trait Device {
+    /// Number of cells of address space the device's registers take.
+    fn size(&self) -> CellCount;
+
+    /// Read the register at an offset from the device's base address.
+    /// `None` means the device doesn't respond, which is a bus error.
+    fn read(&mut self, offset: u32, size: AccessSize) -> Option<u32>;
+
+    /// Write the register at an offset from the device's base
+    /// address. Narrow writes get the value in the low bits.
+    fn write(&mut self, offset: u32, size: AccessSize, value: u32) -> Option<()>;
+}
+
+struct MappedDevice {
+    start: Address,
+    end: Address,
+    // Reads can change a device's state, but go through `&MemMap32`.
+    device: RefCell<Box<dyn Device>>,
+}
+
+impl MappedDevice {
+    fn read(&self, addr: Address, size: AccessSize) -> Result<u32, EmulationError> {
+        let offset = (addr - self.start) as u32;
+        self.device.borrow_mut().read(offset, size)
+                                .ok_or(EmulationError::BusError { addr: addr, opcode: None })
+    }
+
+    fn write(&self, addr: Address, size: AccessSize, value: u32) -> Result<(), EmulationError> {
+        let offset = (addr - self.start) as u32;
+        self.device.borrow_mut().write(offset, size, value)
+                                .ok_or(EmulationError::BusError { addr: addr, opcode: None })
+    }
+}
+
+
+/// What fills the ROM & RAM & I/O region of a `MemMap32` outside the
+/// boot ROM.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LowMemory {
+    /// RAM, which only takes up host memory once it's written.
+    Ram,
+
+    /// Nothing, apart from any boot code that doesn't fit in the ROM,
+    /// so that wild pointers fault.
+    Unmapped,
+}
 
 /// "Principles of ARM Memory Maps" illustrates the 32-bit memory map
 /// as shown here:
@@ -316,69 +552,213 @@ impl Region for ReadOnlyMemory {
 /// 0GB  +-----------------+ 0
 pub struct MemMap32 {
     pub address_space: AddressSpace,
+    /// Sorted by start address, and never overlapping.
+    devices: Vec<MappedDevice>,
 }
 
 impl MemMap32 {
     /// Create a new 32-bit memory map, with the provided boot machine
-    /// code loaded into a ROM chip mapped to address 0x00000000.
+    /// code loaded into a ROM chip mapped to address 0x00000000. The
+    /// rest of the lowest 1GB is RAM, and code past the first 64KB
+    /// starts out there.
     pub fn new(boot_code: Vec<Cell>) -> MemMap32 {
+        MemMap32::with_low_memory(boot_code, LowMemory::Ram)
+    }
+
+    /// Like `new`, but choosing what fills the lowest 1GB past the
+    /// boot ROM.
+    pub fn with_low_memory(boot_code: Vec<Cell>, low_memory: LowMemory) -> MemMap32 {
         let mut rom_ram_io = AddressSpace::from_range(0x00000000, 0x3fffffff);
-        let mapped_io = AddressSpace::from_range(0x40000000, 0x7fffffff);
         let dram = RandomAccessMemory::new(0x80000000, 0xffffffff);
 
-        if boot_code.len() > 0x10000 {
-            let boot_rom = ReadOnlyMemory::new(0x00000000, 0x0000ffff, boot_code[..0x10000].to_vec());
-            rom_ram_io.lease(Box::new(boot_rom));
-
-            let page_size = 0x10000u64;
-            let mut page_num = 0;
-            loop {
-                page_num += 1;
-                let bytes_left: i64 = boot_code.len() as i64 - (page_num * page_size) as i64;
-                if bytes_left <= 0 {
-                    break;
+        let rom_size = cmp::min(boot_code.len(), 0x10000);
+        let boot_rom = ReadOnlyMemory::new(0x00000000, 0x0000ffff, boot_code[..rom_size].to_vec());
+        rom_ram_io.lease(Box::new(boot_rom));
+
+        let ram_end = match low_memory {
+            LowMemory::Ram => Some(0x3fffffff),
+            // Just the 64KB pages the rest of the code needs.
+            LowMemory::Unmapped if boot_code.len() > 0x10000 =>
+                Some(((boot_code.len() as Address + 0xffff) & !0xffff) - 1),
+            LowMemory::Unmapped => None,
+        };
+        if let Some(ram_end) = ram_end {
+            rom_ram_io.lease(Box::new(RandomAccessMemory::new(0x00010000, ram_end)));
+            rom_ram_io.write_cells(&boot_code[rom_size..], 0x10000).unwrap();
+        }
+
+        MemMap32::from_regions(rom_ram_io, dram)
+    }
+
+    /// Create a 32-bit memory map with RAM in place of the boot ROM,
+    /// throughout the lowest 1GB, for loading images that give the
+    /// address of each piece.
+    pub fn with_ram() -> MemMap32 {
+        let mut rom_ram_io = AddressSpace::from_range(0x00000000, 0x3fffffff);
+        rom_ram_io.lease(Box::new(RandomAccessMemory::new(0x00000000, 0x3fffffff)));
+        MemMap32::from_regions(rom_ram_io, RandomAccessMemory::new(0x80000000, 0xffffffff))
+    }
+
+    /// Create a memory map holding the loadable segments of an ELF
+    /// executable, each at its load address. Below 1GB, read-only
+    /// segments become ROM chips and writable ones RAM; segments in
+    /// DRAM are copied into it. Memory past the end of a segment's
+    /// contents in the file reads as zero, as `.bss` expects.
+    pub fn from_elf(file: &ElfFile) -> Result<MemMap32, ElfError> {
+        let mut rom_ram_io = AddressSpace::from_range(0x00000000, 0x3fffffff);
+        let mut dram = RandomAccessMemory::new(0x80000000, 0xffffffff);
+
+        let mut loaded: Vec<(Address, Address)> = vec![];
+        for header in file.program_headers()? {
+            if header.kind != elf::PT_LOAD || header.memsz == 0 {
+                continue;
+            }
+            let low = header.paddr as Address;
+            let high = low + header.memsz as Address - 1;
+            if high > 0xffffffff {
+                return Err(ElfError::Malformed("segment past the end of the address space"));
+            }
+            if loaded.iter().any(|&(start, end)| low <= end && start <= high) {
+                return Err(ElfError::Malformed("overlapping segments"));
+            }
+            loaded.push((low, high));
+
+            let data = file.segment_data(&header)?;
+            if dram.contains_address(&low) && dram.contains_address(&high) {
+                dram.write_cells(data, low).unwrap();
+            } else if rom_ram_io.contains_address(&low) && rom_ram_io.contains_address(&high) {
+                if header.flags & elf::PF_W != 0 {
+                    let mut ram = RandomAccessMemory::new(low, high);
+                    ram.write_cells(data, low).unwrap();
+                    rom_ram_io.lease(Box::new(ram));
                 } else {
-                    let low = page_num * page_size;
-                    let high = low + page_size - 1;
-
-                    // TODO: allocate remaining 64kb pages in this
-                    // region on demand in response to reads and
-                    // writes
-                    rom_ram_io.lease(
-                        Box::new(RandomAccessMemory::new(low, high)));
+                    let mut cells = data.to_vec();
+                    cells.resize(header.memsz as usize, 0);
+                    rom_ram_io.lease(Box::new(ReadOnlyMemory::new(low, high, cells)));
                 }
+            } else {
+                return Err(ElfError::Unsupported("segment outside ROM and RAM"));
             }
-            rom_ram_io.write_cells(&boot_code[0x10000..], 0x10000);
-        } else {
-            let boot_rom = ReadOnlyMemory::new(0x00000000, 0x0000ffff, boot_code);
-            rom_ram_io.lease(Box::new(boot_rom));
         }
 
+        Ok(MemMap32::from_regions(rom_ram_io, dram))
+    }
+
+    fn from_regions(rom_ram_io: AddressSpace, dram: RandomAccessMemory) -> MemMap32 {
+        let mapped_io = AddressSpace::from_range(0x40000000, 0x7fffffff);
+
         let mut map = AddressSpace::from_range(0x00000000, 0xffffffff);
         map.lease(Box::new(rom_ram_io));
         map.lease(Box::new(mapped_io));
         map.lease(Box::new(dram));
 
-        MemMap32 { address_space: map }
+        MemMap32 { address_space: map, devices: vec![] }
+    }
+
+    /// Attach a device at a base address, usually in the mapped I/O
+    /// window. It takes the place of any memory there for accesses
+    /// through this map's getters and setters. Returns false, leaving
+    /// the map unchanged, if it would overlap another device or run
+    /// past the end of the address space.
+    pub fn attach_device(&mut self, base: Address, device: Box<dyn Device>) -> bool {
+        let size = device.size();
+        if size == 0 || base + size - 1 > self.address_space.end() {
+            return false;
+        }
+        let end = base + size - 1;
+        let i = self.devices.partition_point(|mapped| mapped.start <= base);
+        let overlaps_before = i > 0 && self.devices[i - 1].end >= base;
+        let overlaps_after = i < self.devices.len() && self.devices[i].start <= end;
+        if overlaps_before || overlaps_after {
+            return false;
+        }
+        self.devices.insert(i, MappedDevice { start: base, end: end, device: RefCell::new(device) });
+        true
     }
 
-    pub fn get32(&self, addr: Address, big_endian: bool) -> Option<u32> {
+    /// Find the device an access goes to. An access that's only
+    /// partly inside a device is a bus error.
+    fn device_at(&self, addr: Address, size: AccessSize) -> Result<Option<&MappedDevice>, EmulationError> {
+        let last = addr + size.cell_count() - 1;
+        // Devices don't overlap, so only the last one starting at or
+        // before the end of the access can be touched by it.
+        let after = self.devices.partition_point(|mapped| mapped.start <= last);
+        let candidate = after.checked_sub(1).map(|i| &self.devices[i]).filter(|mapped| addr <= mapped.end);
+        match candidate {
+            Some(mapped) if addr < mapped.start || last > mapped.end =>
+                Err(EmulationError::BusError { addr: addr, opcode: None }),
+            found => Ok(found),
+        }
+    }
+
+    pub fn get32(&self, addr: Address, big_endian: bool) -> Result<u32, EmulationError> {
         debug_assert_eq!(1, mem::size_of::<Cell>());
-        match self.address_space.read_cells(addr, addr + 3) {
-            None => None,
-            Some(cells) => {
-                if big_endian {
-                    Some(((cells[0] as u32) << 24) + ((cells[1] as u32) << 16) +
-                         ((cells[2] as u32) << 8) + (cells[3] as u32))
-                } else {
-                    Some(((cells[3] as u32) << 24) + ((cells[2] as u32) << 16) +
-                         ((cells[1] as u32) << 8) + (cells[0] as u32))
-                }
-            }
+        if let Some(mapped) = self.device_at(addr, AccessSize::Word)? {
+            return mapped.read(addr, AccessSize::Word);
+        }
+        let mut cells = [0; 4];
+        self.address_space.read_cells_into(addr, &mut cells)?;
+        if big_endian {
+            Ok(((cells[0] as u32) << 24) + ((cells[1] as u32) << 16) +
+               ((cells[2] as u32) << 8) + (cells[3] as u32))
+        } else {
+            Ok(((cells[3] as u32) << 24) + ((cells[2] as u32) << 16) +
+               ((cells[1] as u32) << 8) + (cells[0] as u32))
         }
     }
 
-    // TODO: Support installing SoC I/O devices and mapped I/O.
+    pub fn get16(&self, addr: Address, big_endian: bool) -> Result<u16, EmulationError> {
+        if let Some(mapped) = self.device_at(addr, AccessSize::Halfword)? {
+            return mapped.read(addr, AccessSize::Halfword).map(|value| value as u16);
+        }
+        let mut cells = [0; 2];
+        self.address_space.read_cells_into(addr, &mut cells)?;
+        if big_endian {
+            Ok(((cells[0] as u16) << 8) + (cells[1] as u16))
+        } else {
+            Ok(((cells[1] as u16) << 8) + (cells[0] as u16))
+        }
+    }
+
+    pub fn put16(&mut self, addr: Address, val: u16, big_endian: bool) -> Result<(), EmulationError> {
+        if let Some(mapped) = self.device_at(addr, AccessSize::Halfword)? {
+            return mapped.write(addr, AccessSize::Halfword, val as u32);
+        }
+        let cells = if big_endian {
+            [(val >> 8) as Cell, val as Cell]
+        } else {
+            [val as Cell, (val >> 8) as Cell]
+        };
+        self.address_space.write_cells(&cells, addr)
+    }
+
+    pub fn put32(&mut self, addr: Address, val: u32, big_endian: bool) -> Result<(), EmulationError> {
+        if let Some(mapped) = self.device_at(addr, AccessSize::Word)? {
+            return mapped.write(addr, AccessSize::Word, val);
+        }
+        let cells = if big_endian {
+            [(val >> 24) as Cell, (val >> 16) as Cell, (val >> 8) as Cell, val as Cell]
+        } else {
+            [val as Cell, (val >> 8) as Cell, (val >> 16) as Cell, (val >> 24) as Cell]
+        };
+        self.address_space.write_cells(&cells, addr)
+    }
+
+    pub fn get8(&self, addr: Address) -> Result<u8, EmulationError> {
+        if let Some(mapped) = self.device_at(addr, AccessSize::Byte)? {
+            return mapped.read(addr, AccessSize::Byte).map(|value| value as u8);
+        }
+        let mut cell = [0];
+        self.address_space.read_cells_into(addr, &mut cell)?;
+        Ok(cell[0])
+    }
+
+    pub fn put8(&mut self, addr: Address, val: u8) -> Result<(), EmulationError> {
+        if let Some(mapped) = self.device_at(addr, AccessSize::Byte)? {
+            return mapped.write(addr, AccessSize::Byte, val as u32);
+        }
+        self.address_space.write_cells(&[val], addr)
+    }
 }
 
 
@@ -386,8 +766,14 @@ impl MemMap32 {
 
 #[cfg(test)]
 mod test {
-    use address::{Address, Cell, Addressable, Region, LeasableRegion, AddressSpace,
-                  RandomAccessMemory, MemMap32};
+    use address::{Address, Cell, CellCount, Addressable, Region, LeasableRegion, AddressSpace,
+                  RandomAccessMemory, ReadOnlyMemory, MemMap32, LowMemory, AccessSize, Device};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use elf;
+    use elf::{ElfError, ElfFile};
+    use elf::test::build_elf;
+    use error::EmulationError;
 
     #[test]
     fn lease_first_4k_of_address_space() {
@@ -465,6 +851,35 @@ mod test {
         assert!(address_space.leased_subregion_at(64).is_some());
     }
 
+    #[test]
+    fn look_up_among_many_regions() {
+        let mut address_space = AddressSpace::from_range(0, 0xffff);
+        // Lease a 256-cell chip every 1KB, from the top down.
+        for i in (0..0x40).rev() {
+            let start = i * 0x400;
+            assert!(address_space.lease(Box::new(RandomAccessMemory::new(start, start + 0xff))).is_some());
+        }
+        assert!(address_space.lease(Box::new(RandomAccessMemory::new(0x800, 0x8ff))).is_none());
+        assert_eq!(address_space.leased_subregions().len(), 0x40);
+        let starts: Vec<Address> = address_space.leased_subregions().iter().map(|r| r.start()).collect();
+        assert_eq!(starts, (0..0x40).map(|i| i * 0x400).collect::<Vec<_>>());
+
+        for addr in 0..0x10000 {
+            let region = address_space.leased_subregion_at(addr);
+            assert_eq!(region.map(|r| r.start()), if addr % 0x400 < 0x100 { Some(addr & !0x3ff) } else { None });
+        }
+
+        // A chip that would enclose, straddle or sit inside a leased
+        // one can't be leased.
+        assert!(!address_space.available_for_lease(&RandomAccessMemory::new(0x300, 0x500)));
+        assert!(!address_space.available_for_lease(&RandomAccessMemory::new(0x4ff, 0x7ff)));
+        assert!(!address_space.available_for_lease(&RandomAccessMemory::new(0x410, 0x420)));
+        assert!(address_space.available_for_lease(&RandomAccessMemory::new(0x100, 0x3ff)));
+        assert!(address_space.lease(Box::new(RandomAccessMemory::new(0x100, 0x3ff))).is_some());
+        assert_eq!(address_space.write_cells(&[1; 0x300], 0x80), Ok(()));
+        assert_eq!(address_space.read_cells(0x7f, 0x380), Ok([vec![0], vec![1; 0x300], vec![0]].concat()));
+    }
+
     #[test]
     fn build_deep_tree_of_ram_chips_and_write_to_all_cells() {
         let mut address_space = AddressSpace::new();
@@ -503,10 +918,10 @@ mod test {
         fn data() -> Vec<Cell> {
             (0..512).map(val_for_address).collect()
         }
-        address_space.write_cells(&data()[..], 0);
+        address_space.write_cells(&data()[..], 0).unwrap();
 
         let recorded_data = address_space.read_cells(0, 511);
-        assert!(recorded_data.is_some());
+        assert!(recorded_data.is_ok());
         assert_eq!(recorded_data.unwrap(), data());
     }
 
@@ -519,4 +934,271 @@ mod test {
         assert_eq!(boot_code, vec![0x01, 0x02, 0x03]);
         assert!(mm.address_space.get(3).is_none());
     }
+
+    #[test]
+    fn read_and_write_words_and_bytes() {
+        let mut mm = MemMap32::new(vec![0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(mm.get32(0, false), Ok(0x04030201));
+        assert_eq!(mm.get32(0, true), Ok(0x01020304));
+        assert_eq!(mm.get8(2), Ok(0x03));
+        assert_eq!(mm.put32(0, 0xdeadbeef, false),
+                   Err(EmulationError::ReadOnlyWrite { addr: 0, opcode: None }));
+        assert_eq!(mm.put8(1, 0xff),
+                   Err(EmulationError::ReadOnlyWrite { addr: 1, opcode: None }));
+        assert_eq!(mm.get32(0, false), Ok(0x04030201));
+
+        let ram = 0x80000000;
+        assert_eq!(mm.get32(ram, false), Ok(0));
+        assert_eq!(mm.put32(ram, 0xdeadbeef, false), Ok(()));
+        assert_eq!(mm.get32(ram, false), Ok(0xdeadbeef));
+        assert_eq!(mm.get32(ram, true), Ok(0xefbeadde));
+        assert_eq!(mm.get8(ram), Ok(0xef));
+        assert_eq!(mm.put8(ram + 3, 0x12), Ok(()));
+        assert_eq!(mm.get32(ram, false), Ok(0x12adbeef));
+        assert_eq!(mm.put32(ram + 4, 0x01020304, true), Ok(()));
+        assert_eq!(mm.get8(ram + 4), Ok(0x01));
+
+        assert_eq!(mm.get16(0, false), Ok(0x0201));
+        assert_eq!(mm.get16(2, true), Ok(0x0304));
+        assert_eq!(mm.put16(ram + 8, 0xabcd, false), Ok(()));
+        assert_eq!(mm.get16(ram + 8, true), Ok(0xcdab));
+        assert_eq!(mm.put16(ram + 10, 0xabcd, true), Ok(()));
+        assert_eq!(mm.get32(ram + 8, false), Ok(0xcdababcd));
+        assert!(mm.put16(2, 0, false).is_err());
+
+        assert_eq!(mm.get8(0x40000000),
+                   Err(EmulationError::UnmappedAccess { addr: 0x40000000, opcode: None }));
+        assert_eq!(mm.put32(0x40000000, 0, false),
+                   Err(EmulationError::UnmappedAccess { addr: 0x40000000, opcode: None }));
+        // Errors name the first cell that can't be read.
+        assert_eq!(mm.get32(0x3ffffffe, false),
+                   Err(EmulationError::UnmappedAccess { addr: 0x40000000, opcode: None }));
+        assert_eq!(mm.get32(2, false),
+                   Err(EmulationError::UnmappedAccess { addr: 4, opcode: None }));
+    }
+
+    #[test]
+    fn fill_low_memory_with_ram_or_nothing() {
+        let mut boot_code = vec![0; 0x10004];
+        boot_code[0x10000] = 0x12;
+
+        let mut mm = MemMap32::new(boot_code.clone());
+        assert_eq!(mm.get8(0x10000), Ok(0x12));
+        assert_eq!(mm.get32(0x00100000, false), Ok(0));
+        assert_eq!(mm.put32(0x3ffffffc, 0xdeadbeef, false), Ok(()));
+        assert_eq!(mm.get32(0x3ffffffc, false), Ok(0xdeadbeef));
+        assert_eq!(mm.put32(0x3ffffffe, 0, false),
+                   Err(EmulationError::UnmappedAccess { addr: 0x40000000, opcode: None }));
+        assert_eq!(mm.put8(0xffff, 0), Err(EmulationError::ReadOnlyWrite { addr: 0xffff, opcode: None }));
+
+        // Without RAM, only the pages that the boot code spills into
+        // are mapped.
+        let mut mm = MemMap32::with_low_memory(boot_code, LowMemory::Unmapped);
+        assert_eq!(mm.get8(0x10000), Ok(0x12));
+        assert_eq!(mm.put32(0x1fffc, 0xdeadbeef, false), Ok(()));
+        assert_eq!(mm.get32(0x1fffe, false), Err(EmulationError::UnmappedAccess { addr: 0x20000, opcode: None }));
+        assert_eq!(mm.put8(0x3ffffffc, 0),
+                   Err(EmulationError::UnmappedAccess { addr: 0x3ffffffc, opcode: None }));
+
+        let mm = MemMap32::with_low_memory(vec![1, 2, 3, 4], LowMemory::Unmapped);
+        assert_eq!(mm.get32(0, true), Ok(0x01020304));
+        assert_eq!(mm.get8(0x10000), Err(EmulationError::UnmappedAccess { addr: 0x10000, opcode: None }));
+    }
+
+    #[test]
+    fn load_elf_segments() {
+        let data = build_elf(0x8000, &[
+            (".text", 1, 0x8000, vec![1, 2, 3, 4]),
+            (".data", 1, 0x20000, vec![5, 6]),
+            (".bss", elf::SHT_NOBITS, 0x20002, vec![0; 0x1e]),
+            (".stack", elf::SHT_NOBITS, 0x80000000, vec![0; 0x100]),
+        ], &[]);
+        let mut mm = MemMap32::from_elf(&ElfFile::parse(&data).unwrap()).unwrap();
+        assert_eq!(mm.get32(0x8000, false), Ok(0x04030201));
+        assert_eq!(mm.put8(0x8000, 0),
+                   Err(EmulationError::ReadOnlyWrite { addr: 0x8000, opcode: None }));
+        assert_eq!(mm.get8(0x7fff), Err(EmulationError::UnmappedAccess { addr: 0x7fff, opcode: None }));
+        assert_eq!(mm.get8(0x8004), Err(EmulationError::UnmappedAccess { addr: 0x8004, opcode: None }));
+
+        assert_eq!(mm.get32(0x20000, false), Ok(0x0605));
+        assert_eq!(mm.get32(0x2001c, false), Ok(0));
+        assert_eq!(mm.put16(0x2001e, 0xffff, false), Ok(()));
+        assert_eq!(mm.get32(0x2001e, false),
+                   Err(EmulationError::UnmappedAccess { addr: 0x20020, opcode: None }));
+        assert_eq!(mm.get32(0x80000000, false), Ok(0));
+        assert_eq!(mm.get32(0x90000000, false), Ok(0));
+
+        // Segments must lie in ROM and RAM, without overlapping.
+        let data = build_elf(0, &[(".io", 1, 0x40000000, vec![0; 4])], &[]);
+        assert_eq!(MemMap32::from_elf(&ElfFile::parse(&data).unwrap()).err(),
+                   Some(ElfError::Unsupported("segment outside ROM and RAM")));
+        let data = build_elf(0, &[(".text", 1, 0x8000, vec![0; 8]), (".data", 1, 0x8004, vec![0; 4])], &[]);
+        assert_eq!(MemMap32::from_elf(&ElfFile::parse(&data).unwrap()).err(),
+                   Some(ElfError::Malformed("overlapping segments")));
+    }
+
+    #[test]
+    fn allocate_ram_pages_on_demand() {
+        let mut ram = RandomAccessMemory::new(0x80000000, 0xffffffff);
+        assert_eq!(ram.read_cells(0x80000ffe, 0x80001001), Ok(vec![0; 4]));
+        assert_eq!(ram.allocated_pages(), 0);
+
+        assert_eq!(ram.write_cells(&[1, 2, 3, 4], 0x80000ffe), Ok(()));
+        assert_eq!(ram.allocated_pages(), 2);
+        assert_eq!(ram.read_cells(0x80000ffd, 0x80001002), Ok(vec![0, 1, 2, 3, 4, 0]));
+        *ram.get_mut(0xffffffff).unwrap() = 5;
+        assert_eq!(ram.get(0xffffffff), Some(&5));
+        assert_eq!(ram.allocated_pages(), 3);
+
+        // A 16MB memset touches exactly the pages it covers.
+        let data = vec![0xa5; 0x1000000];
+        assert_eq!(ram.write_cells(&data, 0x90000800), Ok(()));
+        assert_eq!(ram.allocated_pages(), 3 + 0x1001);
+        assert_eq!(ram.read_cells(0x900007ff, 0x90000800), Ok(vec![0, 0xa5]));
+        assert_eq!(ram.read_cells(0x910007ff, 0x91000800), Ok(vec![0xa5, 0]));
+
+        let mut small = RandomAccessMemory::new(0x10, 0x1f);
+        assert_eq!(small.write_cells(&[0; 16], 0x10), Ok(()));
+        assert_eq!(small.get(0x20), None);
+        assert_eq!(small.write_cells(&[0; 2], 0x1f), Err(EmulationError::UnmappedAccess { addr: 0x20, opcode: None }));
+        assert_eq!(small.get(0x1f), Some(&0));
+    }
+
+    /// ROM at 0x000-0x0ff, then RAM chips at 0x100-0x1ff and
+    /// 0x200-0x2ff in two nested address spaces.
+    fn nested_memory_map() -> MemMap32 {
+        let mut low = AddressSpace::from_range(0, 0x1ff);
+        assert!(low.lease(Box::new(ReadOnlyMemory::new(0, 0xff, (0..0x100).map(|i| i as Cell).collect())))
+                   .is_some());
+        assert!(low.lease(Box::new(RandomAccessMemory::new(0x100, 0x1ff))).is_some());
+        let mut high = AddressSpace::from_range(0x200, 0x3ff);
+        assert!(high.lease(Box::new(RandomAccessMemory::new(0x200, 0x2ff))).is_some());
+
+        let mut address_space = AddressSpace::new();
+        assert!(address_space.lease(Box::new(low)).is_some());
+        assert!(address_space.lease(Box::new(high)).is_some());
+        MemMap32 { address_space: address_space, devices: vec![] }
+    }
+
+    #[test]
+    fn typed_accesses_on_nested_layouts() {
+        let mut mm = nested_memory_map();
+        assert_eq!(mm.get32(0x10, false), Ok(0x13121110));
+        assert_eq!(mm.get32(0x10, true), Ok(0x10111213));
+        assert_eq!(mm.get16(0xfe, true), Ok(0xfeff));
+
+        // Writes that run into ROM change nothing.
+        assert_eq!(mm.put32(0xfe, 0, false), Err(EmulationError::ReadOnlyWrite { addr: 0xfe, opcode: None }));
+        assert_eq!(mm.put32(0x100, 0xaabbccdd, true), Ok(()));
+        assert_eq!(mm.put16(0xff, 0, false), Err(EmulationError::ReadOnlyWrite { addr: 0xff, opcode: None }));
+        assert_eq!(mm.get32(0xfe, false), Ok(0xbbaafffe));
+
+        // Accesses can span regions, and even address spaces.
+        assert_eq!(mm.put32(0x1fe, 0x01020304, false), Ok(()));
+        assert_eq!(mm.get16(0x1fe, false), Ok(0x0304));
+        assert_eq!(mm.get16(0x200, true), Ok(0x0201));
+        assert_eq!(mm.put16(0x1ff, 0xeeff, true), Ok(()));
+        assert_eq!(mm.get32(0x1fe, true), Ok(0x04eeff01));
+        assert_eq!(mm.put8(0x2ff, 0x55), Ok(()));
+        assert_eq!(mm.put16(0x2ff, 0, false), Err(EmulationError::UnmappedAccess { addr: 0x300, opcode: None }));
+        assert_eq!(mm.get8(0x2ff), Ok(0x55));
+        assert_eq!(mm.get32(0x2fe, false), Err(EmulationError::UnmappedAccess { addr: 0x300, opcode: None }));
+
+        // Whole accesses agree with reading a byte at a time.
+        for addr in (0xf0..0x110).chain(0x1f0..0x210).chain(0x2f0..0x304) {
+            let bytes: Result<Vec<u8>, EmulationError> = (addr..addr + 4).map(|a| mm.get8(a)).collect();
+            match bytes {
+                Ok(b) => {
+                    assert_eq!(mm.get32(addr, false), Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])));
+                    assert_eq!(mm.get32(addr, true), Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])));
+                    assert_eq!(mm.get16(addr, true), Ok(u16::from_be_bytes([b[0], b[1]])));
+                },
+                Err(err) => assert_eq!(mm.get32(addr, false), Err(err)),
+            }
+        }
+
+        // Memory chips only hand out their own cells.
+        let mut ram = RandomAccessMemory::new(0x100, 0x1ff);
+        assert!(ram.get_mut(0x200).is_none());
+        assert_eq!(ram.write_cells(&[1, 2], 0x1ff), Err(EmulationError::UnmappedAccess { addr: 0x200, opcode: None }));
+        assert_eq!(ram.read_cells(0xff, 0x100), Err(EmulationError::UnmappedAccess { addr: 0xff, opcode: None }));
+        let rom = ReadOnlyMemory::new(0x100, 0x1ff, vec![1, 2]);
+        assert_eq!(rom.read_cells(0x100, 0x101), Ok(vec![1, 2]));
+        assert_eq!(rom.read_cells(0xff, 0x100), Err(EmulationError::UnmappedAccess { addr: 0xff, opcode: None }));
+        assert_eq!(rom.read_cells(0x101, 0x102), Err(EmulationError::UnmappedAccess { addr: 0x102, opcode: None }));
+    }
+
+    /// A device with a counter that goes up each time it's read and a
+    /// log of writes, which refuses accesses past its first word.
+    struct Counter {
+        count: u32,
+        writes: Rc<RefCell<Vec<(u32, AccessSize, u32)>>>,
+    }
+
+    impl Device for Counter {
+        fn size(&self) -> CellCount {
+            8
+        }
+
+        fn read(&mut self, offset: u32, _size: AccessSize) -> Option<u32> {
+            if offset >= 4 {
+                return None;
+            }
+            self.count += 1;
+            Some(self.count << (8 * offset))
+        }
+
+        fn write(&mut self, offset: u32, size: AccessSize, value: u32) -> Option<()> {
+            if offset >= 4 {
+                return None;
+            }
+            self.writes.borrow_mut().push((offset, size, value));
+            Some(())
+        }
+    }
+
+    #[test]
+    fn attach_devices() {
+        let mut mm = MemMap32::new(vec![]);
+        let writes = Rc::new(RefCell::new(vec![]));
+        let io = 0x40000000;
+        assert!(mm.attach_device(io, Box::new(Counter { count: 0, writes: writes.clone() })));
+        assert!(!mm.attach_device(io + 4, Box::new(Counter { count: 0, writes: writes.clone() })));
+        assert!(!mm.attach_device(0xfffffffc, Box::new(Counter { count: 0, writes: writes.clone() })));
+
+        assert_eq!(mm.get32(io, false), Ok(1));
+        assert_eq!(mm.get32(io, true), Ok(2));
+        assert_eq!(mm.get16(io + 1, false), Ok(0x300));
+        assert_eq!(mm.get8(io + 2), Ok(0));
+        assert_eq!(mm.put32(io, 0xdeadbeef, true), Ok(()));
+        assert_eq!(mm.put16(io + 2, 0xabcd, false), Ok(()));
+        assert_eq!(mm.put8(io + 3, 0x12), Ok(()));
+        assert_eq!(*writes.borrow(), vec![
+            (0, AccessSize::Word, 0xdeadbeef),
+            (2, AccessSize::Halfword, 0xabcd),
+            (3, AccessSize::Byte, 0x12),
+        ]);
+
+        // Accesses the device refuses, or that run off its end, are
+        // bus errors.
+        assert_eq!(mm.get32(io + 4, false), Err(EmulationError::BusError { addr: io + 4, opcode: None }));
+        assert_eq!(mm.put8(io + 7, 0), Err(EmulationError::BusError { addr: io + 7, opcode: None }));
+        assert_eq!(mm.get32(io + 6, false), Err(EmulationError::BusError { addr: io + 6, opcode: None }));
+        assert_eq!(mm.get32(io - 2, false), Err(EmulationError::BusError { addr: io - 2, opcode: None }));
+        assert_eq!(mm.get8(io + 8), Err(EmulationError::UnmappedAccess { addr: io + 8, opcode: None }));
+
+        // Devices can be attached in any order.
+        assert!(mm.attach_device(io - 8, Box::new(Counter { count: 0, writes: writes.clone() })));
+        assert!(!mm.attach_device(io - 4, Box::new(Counter { count: 0, writes: writes.clone() })));
+        assert_eq!(mm.get32(io - 8, false), Ok(1));
+        assert_eq!(mm.get32(io, false), Ok(5));
+        assert_eq!(mm.get32(io - 2, false), Err(EmulationError::BusError { addr: io - 2, opcode: None }));
+
+        // A device can sit on top of memory.
+        let ram = 0x80000000;
+        assert_eq!(mm.put32(ram, 0x11111111, false), Ok(()));
+        assert!(mm.attach_device(ram, Box::new(Counter { count: 0, writes: writes.clone() })));
+        assert_eq!(mm.get32(ram, false), Ok(1));
+        assert_eq!(mm.get32(ram + 8, false), Ok(0));
+    }
 }
@@ -0,0 +1,1301 @@
+//! A two-pass assembler for ARM code written in UAL syntax. Besides
+//! instructions and labels it understands `.word`, `.align` (to a
+//! power of two), `.org`, and `ldr rd, =value`, which loads constants
+//! that don't fit a MOV or MVN from a literal pool placed at the next
+//! `.ltorg` or `.pool`, or else at the end of the program. The image
+//! it makes starts at address 0, ready for `Computer::new`.
+
+use std::collections::HashMap;
+
+use address::Cell;
+use error::ParseError;
+use processor::{
+    AddressingOffset12,
+    AddressingOffset8,
+    BarrelShiftOp,
+    Condition,
+    CondInstr,
+    Decodable,
+    Encodable,
+    HalfwordOrSigned,
+    Instruction,
+    LoadStoreMultiple,
+    ShiftSize,
+    UncondInstr,
+    WordOrUnsignedByte,
+};
+use registers::RegisterBank;
+
+/// Assemble a program into a little-endian image.
+pub fn assemble(source: &str) -> Result<Vec<Cell>, ParseError> {
+    let mut assembler = Assembler::new();
+    for (i, line) in source.lines().enumerate() {
+        assembler.first_pass(i + 1, line)
+                 .map_err(|message| ParseError { line: i + 1, message: message })?;
+    }
+    assembler.place_literal_pool();
+    assembler.second_pass()
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Term {
+    Number(i64),
+    Label(String),
+}
+
+/// A sum of numbers and labels, each of which may be negated.
+#[derive(Clone, PartialEq, Debug)]
+struct Expr(Vec<(bool, Term)>);
+
+impl Expr {
+    fn parse(text: &str) -> Result<Expr, String> {
+        let text = text.trim();
+        let mut terms = vec![];
+        let mut rest = text;
+        let mut negated = false;
+        if rest.starts_with('-') {
+            negated = true;
+            rest = rest[1..].trim_start();
+        }
+        loop {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == '$'))
+                          .unwrap_or(rest.len());
+            let (token, after) = rest.split_at(end);
+            let term = if token.starts_with(|c: char| c.is_digit(10)) {
+                match parse_number(token) {
+                    Some(n) => Term::Number(n),
+                    None => return Err(format!("bad number `{}`", token)),
+                }
+            } else if is_label(token) {
+                Term::Label(token.to_owned())
+            } else {
+                return Err(format!("expected an expression, found `{}`", text));
+            };
+            terms.push((negated, term));
+
+            rest = after.trim_start();
+            if rest.is_empty() {
+                return Ok(Expr(terms));
+            }
+            negated = match rest.chars().next() {
+                Some('+') => false,
+                Some('-') => true,
+                _ => return Err(format!("expected an expression, found `{}`", text)),
+            };
+            rest = rest[1..].trim_start();
+        }
+    }
+
+    /// The value of an expression without labels.
+    fn constant(&self) -> Option<i64> {
+        self.evaluate(&HashMap::new()).ok()
+    }
+
+    fn evaluate(&self, labels: &HashMap<String, u32>) -> Result<i64, String> {
+        let mut value = 0i64;
+        for &(negated, ref term) in self.0.iter() {
+            let n = match *term {
+                Term::Number(n) => n,
+                Term::Label(ref name) => match labels.get(name) {
+                    Some(&addr) => addr as i64,
+                    None => return Err(format!("undefined label `{}`", name)),
+                },
+            };
+            value = if negated { value - n } else { value + n };
+        }
+        Ok(value)
+    }
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let token = token.to_lowercase();
+    let n = if let Some(digits) = token.strip_prefix("0x") {
+        u64::from_str_radix(digits, 16)
+    } else if let Some(digits) = token.strip_prefix("0b") {
+        u64::from_str_radix(digits, 2)
+    } else {
+        token.parse::<u64>()
+    };
+    match n {
+        Ok(n) if n <= u32::max_value() as u64 => Some(n as i64),
+        _ => None,
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '.' || c == '$') &&
+    name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+/// Narrow a value to a word, allowing negative numbers.
+fn word(value: i64) -> Result<u32, String> {
+    if value >= -(1 << 31) && value <= u32::max_value() as i64 {
+        Ok(value as u32)
+    } else {
+        Err(format!("{} doesn't fit in a word", value))
+    }
+}
+
+/// Split operands on the commas that aren't inside brackets or
+/// braces.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(text[start..i].trim().to_owned());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    let last = text[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last.to_owned());
+    }
+    operands
+}
+
+fn strip_comment(line: &str) -> &str {
+    let end = match (line.find('@'), line.find("//")) {
+        (Some(a), Some(b)) => a.min(b),
+        (Some(a), None) => a,
+        (None, Some(b)) => b,
+        (None, None) => line.len(),
+    };
+    &line[..end]
+}
+
+fn parse_register(text: &str) -> Result<RegisterBank, String> {
+    let code = match text.trim().to_lowercase().as_str() {
+        "sb" => 9,
+        "sl" => 10,
+        "fp" => 11,
+        "ip" => 12,
+        "sp" => 13,
+        "lr" => 14,
+        "pc" => 15,
+        name if name.starts_with('r') => match name[1..].parse::<u32>() {
+            Ok(n) if n < 16 && !name[1..].starts_with('+') => n,
+            _ => return Err(format!("expected a register, found `{}`", text)),
+        },
+        _ => return Err(format!("expected a register, found `{}`", text)),
+    };
+    Ok(RegisterBank::decode(code))
+}
+
+/// Parse a register list like `{r0-r3, lr}`.
+fn parse_register_list(text: &str) -> Result<Vec<RegisterBank>, String> {
+    let text = text.trim();
+    if !text.starts_with('{') || !text.ends_with('}') {
+        return Err(format!("expected a register list, found `{}`", text));
+    }
+    let mut regs = 0u32;
+    for item in text[1..text.len() - 1].split(',') {
+        let mut range = item.splitn(2, '-');
+        let first = RegisterBank::encode(parse_register(range.next().unwrap())?);
+        let last = match range.next() {
+            Some(reg) => RegisterBank::encode(parse_register(reg)?),
+            None => first,
+        };
+        if last < first {
+            return Err(format!("bad register range `{}`", item.trim()));
+        }
+        for i in first..(last + 1) {
+            regs |= 1 << i;
+        }
+    }
+    if regs == 0 {
+        return Err("empty register list".to_owned());
+    }
+    Ok((0..16).filter(|i| regs & (1 << i) != 0).map(RegisterBank::decode).collect())
+}
+
+fn parse_condition(text: &str) -> Option<Condition> {
+    let code = match text {
+        "eq" => 0,
+        "ne" => 1,
+        "cs" | "hs" => 2,
+        "cc" | "lo" => 3,
+        "mi" => 4,
+        "pl" => 5,
+        "vs" => 6,
+        "vc" => 7,
+        "hi" => 8,
+        "ls" => 9,
+        "ge" => 10,
+        "lt" => 11,
+        "gt" => 12,
+        "le" => 13,
+        "al" | "" => 14,
+        _ => return None,
+    };
+    Condition::decode(code)
+}
+
+/// The smallest rotation of a byte that makes `value`, as it's written
+/// in a data-processing instruction.
+fn rotated_immediate(value: u32) -> Option<BarrelShiftOp> {
+    (0..16).map(|rotate| (value.rotate_left(2 * rotate), rotate))
+           .find(|&(immed, _)| immed < 1 << 8)
+           .map(|(immed, rotate)| BarrelShiftOp::RotateImmed { immed: immed, rotate: rotate })
+}
+
+/// What can follow the base of a mnemonic, before its condition.
+#[derive(Clone, Copy, PartialEq)]
+enum Suffix {
+    Nothing,
+    /// The S that makes data-processing instructions set flags.
+    S,
+    /// The addressing mode of a block transfer.
+    Mode,
+}
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const MNEMONICS: [(&str, Suffix); 58] = [
+    ("adc", Suffix::S), ("add", Suffix::S), ("and", Suffix::S), ("bic", Suffix::S), ("eor", Suffix::S),
+    ("orr", Suffix::S), ("rsb", Suffix::S), ("rsc", Suffix::S), ("sbc", Suffix::S), ("sub", Suffix::S),
+    ("mov", Suffix::S), ("mvn", Suffix::S),
+    ("cmn", Suffix::Nothing), ("cmp", Suffix::Nothing), ("teq", Suffix::Nothing), ("tst", Suffix::Nothing),
+    ("asr", Suffix::S), ("lsl", Suffix::S), ("lsr", Suffix::S), ("ror", Suffix::S), ("rrx", Suffix::S),
+    ("mla", Suffix::S), ("mls", Suffix::Nothing), ("mul", Suffix::S),
+    ("smlal", Suffix::S), ("smull", Suffix::S), ("umlal", Suffix::S), ("umull", Suffix::S),
+    ("b", Suffix::Nothing), ("bl", Suffix::Nothing), ("blx", Suffix::Nothing), ("bx", Suffix::Nothing),
+    ("ldr", Suffix::Nothing), ("ldrb", Suffix::Nothing), ("ldrbt", Suffix::Nothing), ("ldrd", Suffix::Nothing),
+    ("ldrh", Suffix::Nothing), ("ldrsb", Suffix::Nothing), ("ldrsh", Suffix::Nothing), ("ldrt", Suffix::Nothing),
+    ("str", Suffix::Nothing), ("strb", Suffix::Nothing), ("strbt", Suffix::Nothing), ("strd", Suffix::Nothing),
+    ("strh", Suffix::Nothing), ("strt", Suffix::Nothing),
+    ("ldm", Suffix::Mode), ("stm", Suffix::Mode), ("pop", Suffix::Nothing), ("push", Suffix::Nothing),
+    ("mcr", Suffix::Nothing), ("mrc", Suffix::Nothing), ("mrs", Suffix::Nothing), ("msr", Suffix::Nothing),
+    ("svc", Suffix::Nothing), ("swi", Suffix::Nothing), ("adr", Suffix::Nothing), ("nop", Suffix::Nothing),
+];
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const ARMV6T2_MNEMONICS: [&str; 10] = [
+    "bfc", "bfi", "movt", "movw", "sbfx", "sxtb", "sxth", "ubfx", "uxtb", "uxth",
+];
+
+/// A mnemonic split into its parts, e.g. `addseq` into `add`, S and
+/// EQ.
+struct Mnemonic {
+    base: &'static str,
+    s: bool,
+    mode: &'static str,
+    cond: Condition,
+}
+
+impl Mnemonic {
+    fn parse(text: &str) -> Result<Mnemonic, String> {
+        let text = text.to_lowercase();
+        let bases = MNEMONICS.iter().cloned()
+                             .chain(ARMV6T2_MNEMONICS.iter().map(|&base| (base, Suffix::Nothing)));
+        let mut found: Option<Mnemonic> = None;
+        for (base, suffix) in bases {
+            if !text.starts_with(base) || found.as_ref().map_or(false, |m| m.base.len() > base.len()) {
+                continue;
+            }
+            let mut rest = &text[base.len()..];
+            let mut s = false;
+            let mut mode = "";
+            match suffix {
+                Suffix::S if rest.starts_with('s') => {
+                    s = true;
+                    rest = &rest[1..];
+                },
+                Suffix::Mode => {
+                    for m in ["ia", "ib", "da", "db", "fd", "fa", "ed", "ea"].iter() {
+                        if rest.starts_with(m) {
+                            mode = m;
+                            rest = &rest[2..];
+                        }
+                    }
+                },
+                _ => {},
+            }
+            if let Some(cond) = parse_condition(rest) {
+                found = Some(Mnemonic { base: base, s: s, mode: mode, cond: cond });
+            }
+        }
+        found.ok_or_else(|| format!("unknown instruction `{}`", text))
+    }
+}
+
+/// An instruction, or the words of a `.word` or a literal pool, at an
+/// address decided by the first pass.
+struct Item {
+    line: usize,
+    addr: u32,
+    kind: ItemKind,
+}
+
+enum ItemKind {
+    Instruction { mnemonic: String, operands: Vec<String>, literal: Option<(usize, usize)> },
+    Words(Vec<Expr>),
+}
+
+struct Assembler {
+    labels: HashMap<String, u32>,
+    items: Vec<Item>,
+    /// Literals waiting for the next pool, with the lines loading them.
+    pending_literals: Vec<(Expr, usize)>,
+    pool_addrs: Vec<u32>,
+    addr: u32,
+    end: u32,
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            labels: HashMap::new(),
+            items: vec![],
+            pending_literals: vec![],
+            pool_addrs: vec![],
+            addr: 0,
+            end: 0,
+        }
+    }
+
+    fn advance(&mut self, size: u32) -> Result<(), String> {
+        self.addr = self.addr.checked_add(size).ok_or("the program runs past the end of memory")?;
+        self.end = self.end.max(self.addr);
+        Ok(())
+    }
+
+    /// Give labels their addresses and everything else its place.
+    fn first_pass(&mut self, line_number: usize, line: &str) -> Result<(), String> {
+        let mut rest = strip_comment(line).trim();
+        while let Some(colon) = rest.find(':') {
+            let name = rest[..colon].trim();
+            if !is_label(name) {
+                break;
+            }
+            if self.labels.insert(name.to_owned(), self.addr).is_some() {
+                return Err(format!("`{}` is already defined", name));
+            }
+            rest = rest[colon + 1..].trim();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let (first_word, operands) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], split_operands(&rest[i..])),
+            None => (rest, vec![]),
+        };
+        match first_word.to_lowercase().as_str() {
+            ".word" => {
+                let exprs = operands.iter().map(|op| Expr::parse(op)).collect::<Result<Vec<_>, _>>()?;
+                let size = 4 * exprs.len() as u32;
+                self.items.push(Item { line: line_number, addr: self.addr, kind: ItemKind::Words(exprs) });
+                self.advance(size)
+            },
+            ".align" => {
+                let power = match operands.len() {
+                    0 => 2,
+                    1 => self.constant(&operands[0])?,
+                    _ => return Err("`.align` takes one operand".to_owned()),
+                };
+                if power > 16 {
+                    return Err(format!("can't align to 2^{} bytes", power));
+                }
+                let alignment = 1 << power;
+                let padding = (alignment - self.addr % alignment) % alignment;
+                self.advance(padding)
+            },
+            ".org" => {
+                if operands.len() != 1 {
+                    return Err("`.org` takes one operand".to_owned());
+                }
+                let addr = self.constant(&operands[0])?;
+                if addr < self.addr {
+                    return Err(format!("`.org` can't move back from {:#x} to {:#x}", self.addr, addr));
+                }
+                let gap = addr - self.addr;
+                self.advance(gap)
+            },
+            ".ltorg" | ".pool" => {
+                self.place_literal_pool();
+                Ok(())
+            },
+            directive if directive.starts_with('.') => Err(format!("unknown directive `{}`", directive)),
+            _ => {
+                if !self.addr.is_multiple_of(4) {
+                    return Err(format!("instruction at {:#x} isn't word aligned", self.addr));
+                }
+                let mnemonic = Mnemonic::parse(first_word)?;
+                let literal = if mnemonic.base == "ldr" && operands.len() == 2 && operands[1].starts_with('=') {
+                    self.literal(Expr::parse(&operands[1][1..])?, line_number)
+                } else {
+                    None
+                };
+                self.items.push(Item {
+                    line: line_number,
+                    addr: self.addr,
+                    kind: ItemKind::Instruction {
+                        mnemonic: first_word.to_owned(),
+                        operands: operands,
+                        literal: literal,
+                    },
+                });
+                self.advance(4)
+            },
+        }
+    }
+
+    /// Evaluate a directive's operand, which can only use labels
+    /// defined before it.
+    fn constant(&self, text: &str) -> Result<u32, String> {
+        Expr::parse(text)?.evaluate(&self.labels).and_then(word)
+    }
+
+    /// Find a place in the next literal pool for `ldr rd, =expr`,
+    /// unless it can be a MOV or MVN. Returns the pool and the index
+    /// in it.
+    fn literal(&mut self, expr: Expr, line_number: usize) -> Option<(usize, usize)> {
+        if let Some(value) = expr.constant().and_then(|value| word(value).ok()) {
+            if rotated_immediate(value).is_some() || rotated_immediate(!value).is_some() {
+                return None;
+            }
+        }
+        let index = match self.pending_literals.iter().position(|&(ref e, _)| *e == expr) {
+            Some(index) => index,
+            None => {
+                self.pending_literals.push((expr, line_number));
+                self.pending_literals.len() - 1
+            },
+        };
+        Some((self.pool_addrs.len(), index))
+    }
+
+    fn place_literal_pool(&mut self) {
+        if self.pending_literals.is_empty() {
+            return;
+        }
+        self.addr = (self.addr + 3) & !3;
+        self.pool_addrs.push(self.addr);
+        for (expr, line) in self.pending_literals.split_off(0) {
+            self.items.push(Item { line: line, addr: self.addr, kind: ItemKind::Words(vec![expr]) });
+            self.addr += 4;
+        }
+        self.end = self.end.max(self.addr);
+    }
+
+    fn second_pass(&self) -> Result<Vec<Cell>, ParseError> {
+        let mut image = vec![0; self.end as usize];
+        for item in self.items.iter() {
+            let words = match item.kind {
+                ItemKind::Words(ref exprs) => {
+                    exprs.iter().map(|expr| expr.evaluate(&self.labels).and_then(word)).collect()
+                },
+                ItemKind::Instruction { ref mnemonic, ref operands, literal } => {
+                    self.instruction(item.addr, mnemonic, operands, literal)
+                        .map(|instr| vec![Instruction::encode(instr)])
+                },
+            };
+            let words: Vec<u32> = words.map_err(|message| ParseError { line: item.line, message: message })?;
+            for (i, word) in words.iter().enumerate() {
+                for j in 0..4 {
+                    image[item.addr as usize + 4 * i + j] = (word >> (8 * j)) as Cell;
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    fn evaluate(&self, text: &str) -> Result<i64, String> {
+        Expr::parse(text)?.evaluate(&self.labels)
+    }
+
+    /// Evaluate an immediate operand, which starts with `#`. MOVW and
+    /// MOVT can take the `:lower16:` or `:upper16:` half of a value.
+    fn immediate(&self, text: &str) -> Result<i64, String> {
+        if !text.starts_with('#') {
+            return Err(format!("expected an immediate, found `{}`", text));
+        }
+        let text = text[1..].trim();
+        if let Some(expr) = text.strip_prefix(":lower16:") {
+            Ok((word(self.evaluate(expr)?)? & 0xffff) as i64)
+        } else if let Some(expr) = text.strip_prefix(":upper16:") {
+            Ok((word(self.evaluate(expr)?)? >> 16) as i64)
+        } else {
+            self.evaluate(text)
+        }
+    }
+
+    /// An immediate that has to be between 0 and `max`.
+    fn unsigned_immediate(&self, text: &str, max: u32) -> Result<u32, String> {
+        let value = self.immediate(text)?;
+        if value < 0 || value > max as i64 {
+            return Err(format!("{} isn't between 0 and {}", value, max));
+        }
+        Ok(value as u32)
+    }
+
+    /// The offset from an instruction's PC (its address plus 8) to a
+    /// label.
+    fn pc_relative(&self, text: &str, addr: u32) -> Result<i64, String> {
+        Ok(self.evaluate(text)? - (addr as i64 + 8))
+    }
+
+    fn instruction(&self, addr: u32, mnemonic: &str, operands: &[String], literal: Option<(usize, usize)>)
+                   -> Result<Instruction, String> {
+        let m = Mnemonic::parse(mnemonic)?;
+        let ops = operands;
+        let count = |n: usize| if ops.len() == n {
+            Ok(())
+        } else {
+            Err(format!("`{}` takes {} operands", m.base, n))
+        };
+        let reg = |i: usize| parse_register(&ops[i]);
+
+        let instr = match m.base {
+            "adc" | "add" | "and" | "bic" | "eor" | "orr" | "rsb" | "rsc" | "sbc" | "sub" => {
+                if ops.len() < 3 {
+                    return Err(format!("`{}` takes a destination and two operands", m.base));
+                }
+                self.data_processing(m.base, m.s, reg(0)?, reg(1)?, &ops[2..])?
+            },
+            "mov" | "mvn" => {
+                if ops.len() < 2 {
+                    return Err(format!("`{}` takes a destination and an operand", m.base));
+                }
+                self.data_processing(m.base, m.s, reg(0)?, RegisterBank::R0, &ops[1..])?
+            },
+            "cmn" | "cmp" | "teq" | "tst" => {
+                if ops.len() < 2 {
+                    return Err(format!("`{}` takes two operands", m.base));
+                }
+                self.data_processing(m.base, true, RegisterBank::R0, reg(0)?, &ops[1..])?
+            },
+            "asr" | "lsl" | "lsr" | "ror" => {
+                count(3)?;
+                let shift = format!("{} {}", m.base, ops[2]);
+                CondInstr::MOV { s: m.s, rd: reg(0)?, shift_op: self.shift(reg(1)?, &shift, true)? }
+            },
+            "rrx" => {
+                count(2)?;
+                CondInstr::MOV { s: m.s, rd: reg(0)?, shift_op: BarrelShiftOp::RRX(reg(1)?) }
+            },
+            "nop" => {
+                count(0)?;
+                // The ARMv6K NOP hint isn't decoded, so use MOV r0, r0.
+                CondInstr::MOV {
+                    s: false,
+                    rd: RegisterBank::R0,
+                    shift_op: BarrelShiftOp::LSL(RegisterBank::R0, ShiftSize::Imm(0)),
+                }
+            },
+            "adr" => {
+                count(2)?;
+                let offset = self.pc_relative(&ops[1], addr)?;
+                let rd = reg(0)?;
+                let rn = RegisterBank::R15;
+                match rotated_immediate(offset.abs() as u32) {
+                    Some(shift_op) if offset.abs() <= u32::max_value() as i64 => if offset >= 0 {
+                        CondInstr::ADD { s: false, rd: rd, rn: rn, shift_op: shift_op }
+                    } else {
+                        CondInstr::SUB { s: false, rd: rd, rn: rn, shift_op: shift_op }
+                    },
+                    _ => return Err(format!("`{}` is out of range of `adr`", ops[1])),
+                }
+            },
+
+            "mul" => {
+                count(3)?;
+                CondInstr::MUL { s: m.s, rd: reg(0)?, rm: reg(1)?, rs: reg(2)? }
+            },
+            "mla" => {
+                count(4)?;
+                CondInstr::MLA { s: m.s, rd: reg(0)?, rm: reg(1)?, rs: reg(2)?, rn: reg(3)? }
+            },
+            "mls" => {
+                count(4)?;
+                CondInstr::MLS { rd: reg(0)?, rm: reg(1)?, rs: reg(2)?, rn: reg(3)? }
+            },
+            "smlal" | "smull" | "umlal" | "umull" => {
+                count(4)?;
+                let (s, rdlo, rdhi, rm, rs) = (m.s, reg(0)?, reg(1)?, reg(2)?, reg(3)?);
+                match m.base {
+                    "smlal" => CondInstr::SMLAL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs },
+                    "smull" => CondInstr::SMULL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs },
+                    "umlal" => CondInstr::UMLAL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs },
+                    _ => CondInstr::UMULL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs },
+                }
+            },
+
+            "b" | "bl" => {
+                count(1)?;
+                let offset = self.branch_offset(&ops[0], addr, 4)?;
+                if m.base == "b" {
+                    CondInstr::B(offset)
+                } else {
+                    CondInstr::BL(offset)
+                }
+            },
+            "bx" => {
+                count(1)?;
+                CondInstr::BX(reg(0)?)
+            },
+            "blx" => {
+                count(1)?;
+                if let Ok(rm) = reg(0) {
+                    CondInstr::BLX(rm)
+                } else if m.cond != Condition::AL {
+                    return Err("BLX to a label can't be conditional".to_owned());
+                } else {
+                    return Ok(Instruction::Uncond(UncondInstr::BLX(self.branch_offset(&ops[0], addr, 2)?)));
+                }
+            },
+
+            "ldr" if ops.len() == 2 && ops[1].starts_with('=') => {
+                let rd = reg(0)?;
+                match literal {
+                    Some((pool, index)) => {
+                        let literal_addr = self.pool_addrs[pool] + 4 * index as u32;
+                        let offset = literal_addr as i64 - (addr as i64 + 8);
+                        if offset.abs() > 0xfff {
+                            return Err("the literal pool is out of range; add a `.ltorg` nearer".to_owned());
+                        }
+                        CondInstr::LDR {
+                            rd: rd,
+                            addr_ref: WordOrUnsignedByte::PreIndex {
+                                offset: AddressingOffset12::Immed {
+                                    base_addr: RegisterBank::R15,
+                                    offset12: offset.abs() as u16,
+                                },
+                                positive: offset >= 0,
+                                writeback: false,
+                            },
+                        }
+                    },
+                    None => {
+                        let value = word(self.evaluate(&ops[1][1..])?)?;
+                        match rotated_immediate(value) {
+                            Some(shift_op) => CondInstr::MOV { s: false, rd: rd, shift_op: shift_op },
+                            None => CondInstr::MVN { s: false, rd: rd, shift_op: rotated_immediate(!value).unwrap() },
+                        }
+                    },
+                }
+            },
+            "ldr" | "ldrb" | "ldrbt" | "ldrt" | "str" | "strb" | "strbt" | "strt" => {
+                if ops.len() < 2 {
+                    return Err(format!("`{}` takes a register and an address", m.base));
+                }
+                let rd = reg(0)?;
+                let translated = m.base.ends_with('t');
+                let addr_ref = self.word_or_byte_address(&ops[1..], addr, translated)?;
+                match m.base {
+                    "ldr" => CondInstr::LDR { rd: rd, addr_ref: addr_ref },
+                    "ldrb" => CondInstr::LDRB { rd: rd, addr_ref: addr_ref },
+                    "ldrbt" => CondInstr::LDRBT { rd: rd, addr_ref: addr_ref },
+                    "ldrt" => CondInstr::LDRT { rd: rd, addr_ref: addr_ref },
+                    "str" => CondInstr::STR { rd: rd, addr_ref: addr_ref },
+                    "strb" => CondInstr::STRB { rd: rd, addr_ref: addr_ref },
+                    "strbt" => CondInstr::STRBT { rd: rd, addr_ref: addr_ref },
+                    _ => CondInstr::STRT { rd: rd, addr_ref: addr_ref },
+                }
+            },
+            "ldrh" | "ldrsb" | "ldrsh" | "strh" | "ldrd" | "strd" => {
+                if ops.len() < 2 {
+                    return Err(format!("`{}` takes a register and an address", m.base));
+                }
+                let rd = reg(0)?;
+                let mut address = &ops[1..];
+                if m.base.ends_with('d') {
+                    if RegisterBank::encode(rd) % 2 == 1 || rd == RegisterBank::R14 {
+                        return Err(format!("`{}` needs an even register below r14 first", m.base));
+                    }
+                    // UAL names the second register too.
+                    if let Ok(rd2) = reg(1) {
+                        if rd2 != rd.successor() {
+                            return Err(format!("`{}` needs consecutive registers", m.base));
+                        }
+                        address = &ops[2..];
+                    }
+                }
+                if address.is_empty() {
+                    return Err(format!("`{}` takes a register and an address", m.base));
+                }
+                let addr_ref = self.halfword_or_signed_address(address, addr)?;
+                match m.base {
+                    "ldrh" => CondInstr::LDRH { rd: rd, addr_ref: addr_ref },
+                    "ldrsb" => CondInstr::LDRSB { rd: rd, addr_ref: addr_ref },
+                    "ldrsh" => CondInstr::LDRSH { rd: rd, addr_ref: addr_ref },
+                    "strh" => CondInstr::STRH { rd: rd, addr_ref: addr_ref },
+                    "ldrd" => CondInstr::LDRD { rd: rd, addr_ref: addr_ref },
+                    _ => CondInstr::STRD { rd: rd, addr_ref: addr_ref },
+                }
+            },
+            "ldm" | "stm" => {
+                count(2)?;
+                let load = m.base == "ldm";
+                let mode = match (m.mode, load) {
+                    ("" , _) | ("ia", _) | ("fd", true) | ("ea", false) => LoadStoreMultiple::IA,
+                    ("ib", _) | ("ed", true) | ("fa", false) => LoadStoreMultiple::IB,
+                    ("da", _) | ("fa", true) | ("ed", false) => LoadStoreMultiple::DA,
+                    _ => LoadStoreMultiple::DB,
+                };
+                let w = ops[0].ends_with('!');
+                let rn = parse_register(ops[0].trim_end_matches('!'))?;
+                let carrot = ops[1].ends_with('^');
+                let reg_list = parse_register_list(ops[1].trim_end_matches('^'))?;
+                if load {
+                    CondInstr::LDM { mode: mode, carrot: carrot, w: w, rn: rn, reg_list: reg_list }
+                } else {
+                    CondInstr::STM { mode: mode, carrot: carrot, w: w, rn: rn, reg_list: reg_list }
+                }
+            },
+            "pop" | "push" => {
+                count(1)?;
+                let reg_list = parse_register_list(&ops[0])?;
+                let rn = RegisterBank::R13;
+                if m.base == "pop" {
+                    CondInstr::LDM { mode: LoadStoreMultiple::IA, carrot: false, w: true, rn: rn, reg_list: reg_list }
+                } else {
+                    CondInstr::STM { mode: LoadStoreMultiple::DB, carrot: false, w: true, rn: rn, reg_list: reg_list }
+                }
+            },
+
+            "svc" | "swi" => {
+                count(1)?;
+                let comment = if ops[0].starts_with('#') {
+                    self.unsigned_immediate(&ops[0], 0xffffff)?
+                } else {
+                    self.unsigned_immediate(&format!("#{}", ops[0]), 0xffffff)?
+                };
+                CondInstr::SWI(comment)
+            },
+            "mrs" => {
+                count(2)?;
+                CondInstr::MRS { rd: reg(0)?, psr: parse_status_register(&ops[1])? }
+            },
+            "msr" => {
+                count(2)?;
+                let field_mask = ops[0].to_lowercase();
+                let (psr, fields) = match field_mask.find('_') {
+                    Some(i) => (&field_mask[..i], &field_mask[i + 1..]),
+                    None => (field_mask.as_str(), "fc"),
+                };
+                if fields.is_empty() || !fields.chars().all(|c| "fsxc".contains(c)) {
+                    return Err(format!("bad status register fields `{}`", fields));
+                }
+                if ops[1].starts_with('#') {
+                    return Err("MSR with an immediate isn't supported".to_owned());
+                }
+                CondInstr::MSR {
+                    psr: parse_status_register(psr)?,
+                    rm: reg(1)?,
+                    f: fields.contains('f'),
+                    s: fields.contains('s'),
+                    x: fields.contains('x'),
+                    c: fields.contains('c'),
+                }
+            },
+            "mcr" | "mrc" => {
+                if ops.len() != 5 && ops.len() != 6 {
+                    return Err(format!("`{}` takes five or six operands", m.base));
+                }
+                let copro = coprocessor_operand(&ops[0], 'p')?;
+                let op1 = self.unsigned_immediate(&immediate_text(&ops[1]), 7)?;
+                let rd = reg(2)?;
+                let cn = coprocessor_operand(&ops[3], 'c')?;
+                let cm = coprocessor_operand(&ops[4], 'c')?;
+                let op2 = match ops.get(5) {
+                    Some(op2) => self.unsigned_immediate(&immediate_text(op2), 7)?,
+                    None => 0,
+                };
+                if m.base == "mcr" {
+                    CondInstr::MCR { op1: op1, cn: cn, rd: rd, copro: copro, op2: op2, cm: cm }
+                } else {
+                    CondInstr::MRC { op1: op1, cn: cn, rd: rd, copro: copro, op2: op2, cm: cm }
+                }
+            },
+
+            "movt" | "movw" => {
+                count(2)?;
+                let imm16 = self.unsigned_immediate(&ops[1], 0xffff)?;
+                if m.base == "movt" {
+                    CondInstr::MOVT { rd: reg(0)?, imm16: imm16 }
+                } else {
+                    CondInstr::MOV { s: false, rd: reg(0)?, shift_op: BarrelShiftOp::Imm(imm16) }
+                }
+            },
+            "bfc" => {
+                count(3)?;
+                let (lsb, msb) = self.bitfield(&ops[1], &ops[2])?;
+                CondInstr::BFC { rd: reg(0)?, lsb: lsb, msb: msb }
+            },
+            "bfi" | "sbfx" | "ubfx" => {
+                count(4)?;
+                let (rd, rn) = (reg(0)?, reg(1)?);
+                if m.base == "bfi" && rn == RegisterBank::R15 {
+                    // That's the encoding of BFC.
+                    return Err("`bfi` can't take its bits from pc".to_owned());
+                }
+                let (lsb, msb) = self.bitfield(&ops[2], &ops[3])?;
+                match m.base {
+                    "bfi" => CondInstr::BFI { rd: rd, rn: rn, lsb: lsb, msb: msb },
+                    "sbfx" => CondInstr::SBFX { rd: rd, rn: rn, lsb: lsb, widthm1: msb - lsb },
+                    _ => CondInstr::UBFX { rd: rd, rn: rn, lsb: lsb, widthm1: msb - lsb },
+                }
+            },
+            "sxtb" | "sxth" | "uxtb" | "uxth" => {
+                if ops.len() != 2 && ops.len() != 3 {
+                    return Err(format!("`{}` takes two operands and an optional rotation", m.base));
+                }
+                let (rd, rm) = (reg(0)?, reg(1)?);
+                let rotate = match ops.get(2) {
+                    Some(rotation) => match self.shift(rm, rotation, false)? {
+                        BarrelShiftOp::ROR(_, ShiftSize::Imm(n)) if n % 8 == 0 => n,
+                        _ => return Err(format!("`{}` can only rotate by 8, 16 or 24", m.base)),
+                    },
+                    None => 0,
+                };
+                match m.base {
+                    "sxtb" => CondInstr::SXTB { rd: rd, rm: rm, rotate: rotate },
+                    "sxth" => CondInstr::SXTH { rd: rd, rm: rm, rotate: rotate },
+                    "uxtb" => CondInstr::UXTB { rd: rd, rm: rm, rotate: rotate },
+                    _ => CondInstr::UXTH { rd: rd, rm: rm, rotate: rotate },
+                }
+            },
+            _ => unreachable!(),
+        };
+        Ok(Instruction::Cond(instr, m.cond))
+    }
+
+    /// Build a data-processing instruction. Like other assemblers, an
+    /// immediate that can't be encoded is tried negated or inverted in
+    /// the complementary instruction, and MOV falls back to MOVW.
+    fn data_processing(&self, name: &str, s: bool, rd: RegisterBank, rn: RegisterBank, operands: &[String])
+                       -> Result<CondInstr, String> {
+        let value = match operands.len() {
+            1 if operands[0].starts_with('#') => word(self.immediate(&operands[0])?)?,
+            1 => {
+                let rm = parse_register(&operands[0])?;
+                return Ok(data_processing(name, s, rd, rn, BarrelShiftOp::LSL(rm, ShiftSize::Imm(0))));
+            },
+            2 => {
+                let shift_op = self.shift(parse_register(&operands[0])?, &operands[1], true)?;
+                return Ok(data_processing(name, s, rd, rn, shift_op));
+            },
+            _ => return Err(format!("too many operands for `{}`", name)),
+        };
+
+        if let Some(shift_op) = rotated_immediate(value) {
+            return Ok(data_processing(name, s, rd, rn, shift_op));
+        }
+        let complement = match name {
+            "add" => Some(("sub", value.wrapping_neg())),
+            "sub" => Some(("add", value.wrapping_neg())),
+            "cmp" => Some(("cmn", value.wrapping_neg())),
+            "cmn" => Some(("cmp", value.wrapping_neg())),
+            "adc" => Some(("sbc", !value)),
+            "sbc" => Some(("adc", !value)),
+            "and" => Some(("bic", !value)),
+            "bic" => Some(("and", !value)),
+            "mov" => Some(("mvn", !value)),
+            "mvn" => Some(("mov", !value)),
+            _ => None,
+        };
+        if let Some((name, value)) = complement {
+            if let Some(shift_op) = rotated_immediate(value) {
+                return Ok(data_processing(name, s, rd, rn, shift_op));
+            }
+        }
+        if name == "mov" && !s && value <= 0xffff {
+            return Ok(CondInstr::MOV { s: false, rd: rd, shift_op: BarrelShiftOp::Imm(value) });
+        }
+        Err(format!("{:#x} can't be encoded as an immediate", value))
+    }
+
+    /// Parse a shift of `rm` such as `lsl #2`, `asr r3` or `rrx`.
+    /// Register amounts are only allowed when `register_shifts` is.
+    fn shift(&self, rm: RegisterBank, text: &str, register_shifts: bool) -> Result<BarrelShiftOp, String> {
+        let text = text.trim();
+        let (name, amount) = match text.find(char::is_whitespace) {
+            Some(i) => (text[..i].to_lowercase(), text[i..].trim()),
+            None => (text.to_lowercase(), ""),
+        };
+        if name == "rrx" && amount.is_empty() {
+            return Ok(BarrelShiftOp::RRX(rm));
+        }
+        let (min, max) = match name.as_str() {
+            "lsl" => (0, 31),
+            "lsr" | "asr" => (1, 32),
+            "ror" => (1, 31),
+            _ => return Err(format!("expected a shift, found `{}`", text)),
+        };
+        let shift_size = if amount.starts_with('#') {
+            let n = self.unsigned_immediate(amount, max)?;
+            if n < min {
+                return Err(format!("can't {} by {}", name, n));
+            }
+            ShiftSize::Imm(n)
+        } else if register_shifts {
+            ShiftSize::Reg(parse_register(amount)?)
+        } else {
+            return Err(format!("`{}` can only shift by an immediate", text));
+        };
+        Ok(match name.as_str() {
+            "lsl" => BarrelShiftOp::LSL(rm, shift_size),
+            "lsr" => BarrelShiftOp::LSR(rm, shift_size),
+            "asr" => BarrelShiftOp::ASR(rm, shift_size),
+            _ => BarrelShiftOp::ROR(rm, shift_size),
+        })
+    }
+
+    /// A word-aligned byte offset from an instruction's PC to a label.
+    fn branch_offset(&self, target: &str, addr: u32, alignment: i64) -> Result<i32, String> {
+        let offset = self.pc_relative(target, addr)?;
+        if offset % alignment != 0 || offset < -(1 << 25) || offset >= 1 << 25 {
+            return Err(format!("can't branch to `{}` from {:#x}", target, addr));
+        }
+        Ok(offset as i32)
+    }
+
+    /// Parse `#lsb, #width` into the least and most significant bits.
+    fn bitfield(&self, lsb: &str, width: &str) -> Result<(u32, u32), String> {
+        let lsb = self.unsigned_immediate(lsb, 31)?;
+        let width = self.unsigned_immediate(width, 32 - lsb)?;
+        if width == 0 {
+            return Err("a bitfield can't be empty".to_owned());
+        }
+        Ok((lsb, lsb + width - 1))
+    }
+
+    /// Parse the address operands of LDR, STR, LDRB and STRB (and
+    /// their T variants, which only post-index).
+    fn word_or_byte_address(&self, operands: &[String], addr: u32, translated: bool)
+                            -> Result<WordOrUnsignedByte, String> {
+        let (base, offset, writeback, post_index) = self.address(operands, addr)?;
+        let (offset, positive) = match offset.len() {
+            0 => (AddressingOffset12::Immed { base_addr: base, offset12: 0 }, true),
+            1 if offset[0].starts_with('#') => {
+                let value = self.immediate(&offset[0])?;
+                if value.abs() > 0xfff {
+                    return Err(format!("offset {} is out of range", value));
+                }
+                let offset12 = value.abs() as u16;
+                (AddressingOffset12::Immed { base_addr: base, offset12: offset12 }, is_positive(&offset[0], value))
+            },
+            1 | 2 => {
+                let (positive, rm) = signed_register(&offset[0])?;
+                let offset = match offset.get(1) {
+                    Some(shift) => match self.shift(rm, shift, false)? {
+                        BarrelShiftOp::LSL(_, ShiftSize::Imm(0)) =>
+                            AddressingOffset12::Register { base_addr: base, offset: rm },
+                        shift_op => AddressingOffset12::ScaledRegister { base_addr: base, shift_op: shift_op },
+                    },
+                    None => AddressingOffset12::Register { base_addr: base, offset: rm },
+                };
+                (offset, positive)
+            },
+            _ => return Err("too many address operands".to_owned()),
+        };
+        if post_index || translated {
+            if !post_index && (writeback || offset != AddressingOffset12::Immed { base_addr: base, offset12: 0 }) {
+                return Err("the T variants can only post-index".to_owned());
+            }
+            Ok(WordOrUnsignedByte::PostIndex { offset: offset, positive: positive })
+        } else {
+            Ok(WordOrUnsignedByte::PreIndex { offset: offset, positive: positive, writeback: writeback })
+        }
+    }
+
+    /// Parse the address operands of the halfword, signed byte and
+    /// doubleword transfers.
+    fn halfword_or_signed_address(&self, operands: &[String], addr: u32) -> Result<HalfwordOrSigned, String> {
+        let (base, offset, writeback, post_index) = self.address(operands, addr)?;
+        let (offset, positive) = match offset.len() {
+            0 => (AddressingOffset8::Immed { base_addr: base, offset8: 0 }, true),
+            1 if offset[0].starts_with('#') => {
+                let value = self.immediate(&offset[0])?;
+                if value.abs() > 0xff {
+                    return Err(format!("offset {} is out of range", value));
+                }
+                let offset8 = value.abs() as u8;
+                (AddressingOffset8::Immed { base_addr: base, offset8: offset8 }, is_positive(&offset[0], value))
+            },
+            1 => {
+                let (positive, rm) = signed_register(&offset[0])?;
+                (AddressingOffset8::Register { base_addr: base, offset: rm }, positive)
+            },
+            _ => return Err("halfword and doubleword offsets can't be shifted".to_owned()),
+        };
+        if post_index {
+            Ok(HalfwordOrSigned::PostIndex { offset: offset, positive: positive })
+        } else {
+            Ok(HalfwordOrSigned::PreIndex { offset: offset, positive: positive, writeback: writeback })
+        }
+    }
+
+    /// Split the operands of an address into its base register, the
+    /// operands of its offset, and whether it writes back or
+    /// post-indexes. A label becomes an immediate offset from the PC.
+    fn address(&self, operands: &[String], addr: u32) -> Result<(RegisterBank, Vec<String>, bool, bool), String> {
+        let first = &operands[0];
+        if !first.starts_with('[') {
+            if operands.len() != 1 {
+                return Err(format!("expected an address, found `{}`", first));
+            }
+            let offset = self.pc_relative(first, addr)?;
+            return Ok((RegisterBank::R15, vec![format!("#{}", offset)], false, false));
+        }
+
+        let (inner, writeback) = if first.ends_with("]!") {
+            (&first[1..first.len() - 2], true)
+        } else if first.ends_with(']') {
+            (&first[1..first.len() - 1], false)
+        } else {
+            return Err(format!("expected `]` to end `{}`", first));
+        };
+        let mut parts = split_operands(inner);
+        if parts.is_empty() {
+            return Err("expected a base register".to_owned());
+        }
+        let base = parse_register(&parts.remove(0))?;
+        if operands.len() == 1 {
+            Ok((base, parts, writeback, false))
+        } else if parts.is_empty() && !writeback {
+            Ok((base, operands[1..].to_vec(), false, true))
+        } else {
+            Err(format!("expected `[rn]` before a post-index offset, found `{}`", first))
+        }
+    }
+}
+
+/// The data-processing instruction called `name`. Comparisons ignore
+/// `s` and `rd`, and moves ignore `rn`.
+fn data_processing(name: &str, s: bool, rd: RegisterBank, rn: RegisterBank, shift_op: BarrelShiftOp) -> CondInstr {
+    match name {
+        "adc" => CondInstr::ADC { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "add" => CondInstr::ADD { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "and" => CondInstr::AND { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "bic" => CondInstr::BIC { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "eor" => CondInstr::EOR { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "orr" => CondInstr::ORR { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "rsb" => CondInstr::RSB { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "rsc" => CondInstr::RSC { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "sbc" => CondInstr::SBC { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "sub" => CondInstr::SUB { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        "mov" => CondInstr::MOV { s: s, rd: rd, shift_op: shift_op },
+        "mvn" => CondInstr::MVN { s: s, rd: rd, shift_op: shift_op },
+        "cmn" => CondInstr::CMN { rn: rn, shift_op: shift_op },
+        "cmp" => CondInstr::CMP { rn: rn, shift_op: shift_op },
+        "teq" => CondInstr::TEQ { rn: rn, shift_op: shift_op },
+        "tst" => CondInstr::TST { rn: rn, shift_op: shift_op },
+        _ => unreachable!(),
+    }
+}
+
+/// Check the sign of an immediate offset, which can be `#-0`.
+fn is_positive(text: &str, value: i64) -> bool {
+    value > 0 || (value == 0 && !text[1..].trim_start().starts_with('-'))
+}
+
+/// Parse an offset register, which may be preceded by a sign.
+fn signed_register(text: &str) -> Result<(bool, RegisterBank), String> {
+    if let Some(register) = text.strip_prefix('-') {
+        Ok((false, parse_register(register)?))
+    } else if let Some(register) = text.strip_prefix('+') {
+        Ok((true, parse_register(register)?))
+    } else {
+        Ok((true, parse_register(text)?))
+    }
+}
+
+fn parse_status_register(text: &str) -> Result<RegisterBank, String> {
+    match text.trim().to_lowercase().as_str() {
+        "cpsr" | "apsr" => Ok(RegisterBank::CPSR),
+        "spsr" => Ok(RegisterBank::SPSR),
+        _ => Err(format!("expected a status register, found `{}`", text)),
+    }
+}
+
+/// Parse a coprocessor number like `p15`, or a coprocessor register
+/// like `c7`.
+fn coprocessor_operand(text: &str, prefix: char) -> Result<u32, String> {
+    let text = text.trim().to_lowercase();
+    match text.strip_prefix(prefix).map(|number| number.parse::<u32>()) {
+        Some(Ok(n)) if n < 16 => Ok(n),
+        _ => Err(format!("expected {}0 to {}15, found `{}`", prefix, prefix, text)),
+    }
+}
+
+/// Coprocessor opcodes can be written with or without a `#`.
+fn immediate_text(text: &str) -> String {
+    if text.starts_with('#') {
+        text.to_owned()
+    } else {
+        format!("#{}", text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use address::MemMap32;
+    use processor::Processor;
+
+    fn words(image: &[Cell]) -> Vec<u32> {
+        image.chunks(4)
+             .map(|w| w.iter().enumerate().fold(0, |word, (i, &b)| word | (b as u32) << (8 * i)))
+             .collect()
+    }
+
+    fn error_line(source: &str) -> usize {
+        assemble(source).unwrap_err().line
+    }
+
+    #[test]
+    fn assemble_instructions() {
+        // The expected words came from the GNU-compatible LLVM
+        // assembler, targeting ARMv7-A.
+        let source = "
+            start:
+                mov r0, #0
+                movs r1, r2, lsl #3
+                mvn r3, #0xff000000
+                add r4, r5, #-16          @ becomes sub
+                subs r6, r7, r8, asr r9
+                cmp r0, #-1               @ becomes cmn
+                tst r1, #0x10000
+                and r2, r2, #0xffffff00   @ becomes bic
+                rsb r3, r4, r5, rrx
+                lsl r0, r1, #2
+                lsrs r2, r3, r4
+                ror r5, r6, #31
+                mul r0, r1, r2
+                mla r3, r4, r5, r6
+                umull r7, r8, r9, r10
+                bne start
+                bl func
+                bx lr
+                blx r3
+            func: ldr r0, [r1, #-4]!
+                strb r2, [r3], r4, lsl #2
+                ldrh r5, [r6, #2]
+                ldrsb r7, [r8], -r9
+                ldrd r0, r1, [r2, #8]
+                stmdb sp!, {r4-r6, lr}
+                ldmfd sp!, {r4-r6, pc}^
+                push {r0, r1}
+                pop {r0, r1}
+                svc #0x123456
+                mrs r0, cpsr
+                msr cpsr_fc, r1
+                mcr p15, 0, r0, c1, c0, 0
+                movw r2, #0x1234
+                movt r2, #0xabcd
+                bfi r3, r4, #8, #4
+                ubfx r5, r6, #1, #31
+                uxtb r7, r8, ror #8
+                adr r9, start
+                nop                       // mov r0, r0
+                ldrt r0, [r1]
+                mov r0, #0x1234           // becomes movw
+        ";
+        let expected = vec![
+            0xe3a00000, 0xe1b01182, 0xe3e034ff, 0xe2454010, 0xe0576958, 0xe3700001, 0xe3110801,
+            0xe3c220ff, 0xe0643065, 0xe1a00101, 0xe1b02433, 0xe1a05fe6, 0xe0000291, 0xe0236594,
+            0xe0887a99, 0x1affffef, 0xeb000001, 0xe12fff1e, 0xe12fff33, 0xe5310004, 0xe6c32104,
+            0xe1d650b2, 0xe01870d9, 0xe1c200d8, 0xe92d4070, 0xe8fd8070, 0xe92d0003, 0xe8bd0003,
+            0xef123456, 0xe10f0000, 0xe129f001, 0xee010f10, 0xe3012234, 0xe34a2bcd, 0xe7cb3414,
+            0xe7fe50d6, 0xe6ef7478, 0xe24f909c, 0xe1a00000, 0xe4b10000, 0xe3010234,
+        ];
+        assert_eq!(expected, words(&assemble(source).unwrap()));
+    }
+
+    #[test]
+    fn roundtrip_through_decoder() {
+        let source = "
+                addeq r0, r1, r2, ror #7
+                bicsne r3, r4, #0x3fc
+                ldrbhs r5, [pc, #-12]
+                strhlt r6, [r7, -r8]!
+                ldmib r0, {r1, r3}
+                stmfa sp!, {r4}
+                smlalvs r0, r1, r2, r3
+                mrc p15, 0, r2, c9, c13, 1
+                sxth r1, r2, ror #16
+                sbfx r3, r4, #4, #8
+                bfc r5, #0, #16
+                mls r6, r7, r8, r9
+                msr spsr_f, r10
+        ";
+        let processor = Processor::new();
+        for word in words(&assemble(source).unwrap()) {
+            let instr = processor.decode_instruction(word)
+                                 .unwrap_or_else(|| panic!("{:#010x} didn't decode", word));
+            assert_eq!(word, Instruction::encode(instr));
+        }
+    }
+
+    #[test]
+    fn literal_pools_and_directives() {
+        let source = "
+                ldr r0, =0x12345678
+                ldr r1, =0xffffff00       @ fits mvn
+                ldr r2, =table
+                ldr r3, =0x12345678       @ shares the first literal
+                b next
+                .ltorg
+            next:
+                ldr r4, =-2
+                ldr r5, [pc, #0]
+                .align 4
+            table:
+                .word 1, table + 4, -1
+                .org 0x40
+                .word next - table
+        ";
+        let expected = vec![
+            0xe59f000c, // ldr r0, [pc, #12]
+            0xe3e010ff, // mvn r1, #0xff
+            0xe59f2008, // ldr r2, [pc, #8]
+            0xe59f3000, // ldr r3, [pc, #0]
+            0xea000001, // b next
+            0x12345678,
+            0x00000030,
+            0xe3e04001, // mvn r4, #1
+            0xe59f5000,
+            0, 0, 0,
+            0x00000001, 0x00000034, 0xffffffff,
+            0,
+            0xffffffec,
+        ];
+        assert_eq!(expected, words(&assemble(source).unwrap()));
+    }
+
+    #[test]
+    fn literal_pool_at_end_of_program() {
+        let image = assemble("ldr r0, =0xdeadbeef\nbx lr").unwrap();
+        assert_eq!(vec![0xe59f0000, 0xe12fff1e, 0xdeadbeef], words(&image));
+
+        let map = MemMap32::new(image);
+        assert_eq!(0xdeadbeef, map.get32(8, false).unwrap());
+    }
+
+    #[test]
+    fn assembly_errors() {
+        assert_eq!(1, error_line("frob r0"));
+        assert_eq!(2, error_line("nop\nmov r0, #0x12345\n"));
+        assert_eq!(2, error_line("nop\nb nowhere"));
+        assert_eq!(3, error_line("a:\nnop\na: nop"));
+        assert_eq!(2, error_line(".org 8\n.org 4"));
+        assert_eq!(1, error_line("ldr r0, [r1, #4096]"));
+        assert_eq!(1, error_line("ldrd r1, r2, [r3]"));
+        assert_eq!(1, error_line("lsl r0, r1, #32"));
+        assert_eq!(1, error_line(".space 4"));
+        assert_eq!(1, error_line("ldr r0, =0x12345678\n.org 0x2000"));
+        assert_eq!(1, error_line("mcr p15, 0, r0, , c0"));
+        assert_eq!(1, error_line("mcr \u{e9}15, 0, r0, c1, c0"));
+
+        let error = assemble("\n\nmov r16, r0").unwrap_err();
+        assert_eq!("line 3: expected a register, found `r16`", error.to_string());
+    }
+}
@@ -2,300 +2,1201 @@
 
 use address;
 use address::Region;
+use elf::{ElfError, ElfFile};
+use error::{EmulationError, ParseError};
+use image;
 use processor;
 use processor::{
+    AddressingOffset12,
+    AddressingOffset8,
     BarrelShiftOp,
     Condition,
     CondInstr,
+    Decodable,
+    Encodable,
+    HalfwordOrSigned,
     Instruction,
+    LoadStoreMultiple,
     ShiftSize,
     UncondInstr,
+    WordOrUnsignedByte,
 };
+use symbols::SymbolTable;
+use thumb;
+use thumb::ThumbInstr;
 use registers::{
     ConditionFlag,
+    Exception,
+    InstructionSet,
+    InterruptMask,
+    ProcessorMode,
     ProgramStatusRegister,
     Register32,
     RegisterBank,
 };
 
+/// What the computer does when it can't fetch or execute an
+/// instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FaultPolicy {
+    /// Take the exception the hardware would: Prefetch Abort for a
+    /// failed fetch, Data Abort for a failed load or store, and
+    /// Undefined Instruction otherwise. Guest handlers can then
+    /// emulate or skip the instruction.
+    Trap,
+
+    /// Leave the machine state alone and report the fault to the
+    /// caller.
+    Stop,
+}
+
 pub struct Computer {
     pub cpu: processor::Processor,
     pub mem: address::MemMap32,
     pub big_endian: bool,
+
+    /// Fetch instructions little-endian even when `big_endian` is set,
+    /// as ARMv6 and later cores do with BE-8 images.
+    pub little_endian_code: bool,
+
+    /// Put the exception vector table at 0xffff0000 rather than 0.
+    pub high_vectors: bool,
+
+    /// Interrupt request lines, checked before each instruction.
+    /// Devices hold these high until the handler acknowledges them.
+    pub irq_asserted: bool,
+    pub fiq_asserted: bool,
+
+    pub fault_policy: FaultPolicy,
+
+    /// Names for addresses in the guest program, used in disassembly.
+    pub symbols: SymbolTable,
+
+    /// Set when the executing instruction writes the PC, so it isn't
+    /// advanced past the instruction.
+    branched: bool,
+
+    /// Size in bytes of the instruction being executed: 4 for ARM, and
+    /// 2 or 4 for Thumb.
+    instr_size: u32,
 }
 
 impl Computer {
     pub fn new(boot_code: Vec<address::Cell>) -> Computer {
+        Computer::with_memory(address::MemMap32::new(boot_code))
+    }
+
+    /// Load an ELF executable, ready to run from its entry point with
+    /// its byte order and symbols.
+    pub fn from_elf(data: &[u8]) -> Result<Computer, ElfError> {
+        let file = ElfFile::parse(data)?;
+        let mut computer = Computer::with_memory(address::MemMap32::from_elf(&file)?);
+        computer.big_endian = file.is_big_endian();
+        computer.little_endian_code = file.is_be8();
+        computer.symbols = SymbolTable::from_elf(data)?;
+        // The entry point of Thumb code has bit 0 set.
+        computer.branch_exchange(file.entry);
+        Ok(computer)
+    }
+
+    /// Load an Intel HEX image into RAM, ready to run from its start
+    /// address, or from 0 if it has none.
+    pub fn from_ihex(text: &str) -> Result<Computer, ParseError> {
+        let mut mem = address::MemMap32::with_ram();
+        let start = image::load_ihex(text, &mut mem)?;
+        Ok(Computer::with_start_address(mem, start))
+    }
+
+    /// Load a Motorola S-record image into RAM, ready to run from its
+    /// start address, or from 0 if it has none.
+    pub fn from_srec(text: &str) -> Result<Computer, ParseError> {
+        let mut mem = address::MemMap32::with_ram();
+        let start = image::load_srec(text, &mut mem)?;
+        Ok(Computer::with_start_address(mem, start))
+    }
+
+    fn with_start_address(mem: address::MemMap32, start: Option<u32>) -> Computer {
+        let mut computer = Computer::with_memory(mem);
+        if let Some(start) = start {
+            computer.branch_exchange(start);
+        }
+        computer
+    }
+
+    /// Build a computer around a memory map made by the caller, for
+    /// example with `MemMap32::with_low_memory` to leave the low 1GB
+    /// unmapped so wild pointers fault.
+    pub fn with_memory(mem: address::MemMap32) -> Computer {
         Computer {
             cpu: Default::default(),
-            mem: address::MemMap32::new(boot_code),
+            mem: mem,
             big_endian: false,  // TODO: look up endianness in the CPSR instead.
+            little_endian_code: false,
+            high_vectors: false,
+            irq_asserted: false,
+            fiq_asserted: false,
+            fault_policy: FaultPolicy::Trap,
+            symbols: SymbolTable::new(),
+            branched: false,
+            instr_size: 4,
+        }
+    }
+
+    pub fn execute_next_instruction(&mut self) -> Result<(), EmulationError> {
+        if self.fiq_asserted && self.cpsr().permit_interrupt(InterruptMask::FIQ) {
+            self.raise_exception(Exception::FastInterruptRequest);
+            return Ok(());
+        }
+        if self.irq_asserted && self.cpsr().permit_interrupt(InterruptMask::IRQ) {
+            self.raise_exception(Exception::InterruptRequest);
+            return Ok(());
+        }
+
+        let pc_addr = self.pc() as address::Address;
+        let (opcode, size) = match self.fetch(pc_addr) {
+            Ok(fetched) => fetched,
+            Err(err) => return self.fault(Exception::PrefetchAbort, err),
+        };
+        self.instr_size = size;
+        let instr = match self.decode(opcode, size) {
+            Some(instr) => instr,
+            None => {
+                let err = EmulationError::Undecodable { addr: pc_addr, opcode: Some(opcode) };
+                return self.fault(Exception::UndefinedInstruction, err);
+            },
+        };
+        match self.execute(instr) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let exception = match err {
+                    EmulationError::Unimplemented { .. } |
+                    EmulationError::InvalidMode { .. } => Exception::UndefinedInstruction,
+                    _ => Exception::DataAbort,
+                };
+                self.fault(exception, err.with_opcode(opcode))
+            },
+        }
+    }
+
+    /// Handle an error at the PC according to the fault policy.
+    fn fault(&mut self, exception: Exception, err: EmulationError) -> Result<(), EmulationError> {
+        match self.fault_policy {
+            FaultPolicy::Trap => {
+                self.raise_exception(exception);
+                Ok(())
+            },
+            FaultPolicy::Stop => Err(err),
         }
     }
 
-    pub fn execute_next_instruction(&mut self) {
-        let pc_addr = self.program_counter().bits;
-        match self.instruction_at(pc_addr as address::Address) {
-            Err(s) => panic!(s),
-            Ok(instr) => self.execute(instr),
+    /// Decode the instruction at an address in the current
+    /// instruction set.
+    pub fn instruction_at(&self, addr: address::Address) -> Result<processor::Instruction, EmulationError> {
+        let (opcode, size) = self.fetch(addr)?;
+        match self.decode(opcode, size) {
+            None => Err(EmulationError::Undecodable { addr: addr, opcode: Some(opcode) }),
+            Some(instr) => Ok(instr),
         }
     }
 
-    pub fn instruction_at(&self, addr: address::Address) -> Result<processor::Instruction, String> {
-        debug_assert!(addr % 4 == 0);
+    /// Disassemble the instruction at an address in the current
+    /// instruction set, naming addresses after symbols. A literal
+    /// load also shows the value it loads.
+    pub fn disassemble(&self, addr: address::Address) -> Result<String, EmulationError> {
+        let instr = self.instruction_at(addr)?;
+        let (text, pc) = match self.cpsr().active_instruction_set() {
+            InstructionSet::ARM => (instr.as_str_with_symbols(addr as u32, &self.symbols), addr as u32 + 8),
+            InstructionSet::Thumb => (instr.as_thumb_str_with_symbols(addr as u32, &self.symbols), addr as u32 + 4),
+        };
+        match instr.literal_address(pc) {
+            Some(literal) => match self.mem.get32(literal as address::Address, self.big_endian) {
+                Ok(value) => Ok(format!("{} = {}", text, processor::address_str(value, &self.symbols))),
+                Err(_) => Ok(text),
+            },
+            None => Ok(text),
+        }
+    }
+
+    /// The size in bytes of the instruction at an address in the
+    /// current instruction set. Where nothing can be fetched this is
+    /// the smallest instruction size, so callers can step past it.
+    pub fn instruction_size(&self, addr: address::Address) -> u32 {
+        match self.fetch(addr) {
+            Ok((_, size)) => size,
+            Err(_) => match self.cpsr().active_instruction_set() {
+                InstructionSet::ARM => 4,
+                InstructionSet::Thumb => 2,
+            },
+        }
+    }
+
+    /// Fetch the opcode at an address, returning its size in bytes
+    /// too. A 32-bit Thumb opcode has its first halfword on top.
+    fn fetch(&self, addr: address::Address) -> Result<(u32, u32), EmulationError> {
         debug_assert!(addr <= self.mem.address_space.end());
+        let big_endian = self.big_endian && !self.little_endian_code;
 
-        match self.mem.get32(addr, self.big_endian) {
-            None => Err("[ uninitialized memory ]".to_owned()),
-            Some(word) => match self.cpu.decode_instruction(word) {
-                None => Err(format!("[ ??? '0b{:0>32b}' ]", word)),
-                Some(instr) => Ok(instr),
+        match self.cpsr().active_instruction_set() {
+            InstructionSet::ARM => {
+                if !addr.is_multiple_of(4) {
+                    return Err(EmulationError::Misaligned { addr: addr, opcode: None });
+                }
+                Ok((self.mem.get32(addr, big_endian)?, 4))
             },
+            InstructionSet::Thumb => {
+                if !addr.is_multiple_of(2) {
+                    return Err(EmulationError::Misaligned { addr: addr, opcode: None });
+                }
+                let first = self.mem.get16(addr, big_endian)?;
+                if !thumb::is_wide(first) {
+                    return Ok((first as u32, 2));
+                }
+                let second = self.mem.get16(addr + 2, big_endian)?;
+                Ok(((first as u32) << 16 | second as u32, 4))
+            },
+        }
+    }
+
+    fn decode(&self, opcode: u32, size: u32) -> Option<processor::Instruction> {
+        let in_it_block = self.it_condition().is_some();
+        match self.cpsr().active_instruction_set() {
+            InstructionSet::ARM => self.cpu.decode_instruction(opcode),
+            InstructionSet::Thumb if size == 4 =>
+                self.cpu.decode_thumb_instruction((opcode >> 16) as u16, opcode as u16, in_it_block),
+            InstructionSet::Thumb => self.cpu.decode_thumb_instruction(opcode as u16, 0, in_it_block),
         }
     }
 
+    /// Take an exception between instructions, so that the handler
+    /// runs next. For aborts and undefined instructions the PC should
+    /// hold the address of the offending instruction.
+    pub fn raise_exception(&mut self, exception: Exception) {
+        self.enter_exception(exception);
+    }
+
+    /// Enter the handler for an exception caused by the instruction
+    /// at the PC, or (for interrupts) before the instruction at the
+    /// PC runs. The return address is the one each handler expects:
+    /// the return sequence is `MOVS pc, lr` for SWI and undefined
+    /// instructions, `SUBS pc, lr, #4` for interrupts and prefetch
+    /// aborts, and `SUBS pc, lr, #8` for data aborts, in either
+    /// instruction set.
+    fn enter_exception(&mut self, exception: Exception) {
+        let pc = self.pc();
+        let return_addr = match exception {
+            Exception::DataAbort => pc.wrapping_add(8),
+            Exception::SoftwareInterrupt |
+            Exception::UndefinedInstruction => pc.wrapping_add(self.instr_size),
+            _ => pc.wrapping_add(4),
+        };
+        self.cpu.register_file.enter_exception(exception, return_addr);
+
+        let vector_table = if self.high_vectors { 0xffff0000 } else { 0 };
+        self.set_register(RegisterBank::R15, vector_table + exception.vector_offset());
+    }
+
     fn register(&mut self, reg_bank: RegisterBank) -> Option<&mut Register32> {
         self.cpu.register_file.lookup_mut(reg_bank)
     }
 
+    /// Read a register as an instruction operand. Because of the
+    /// pipeline, R15 reads as the address of the current instruction
+    /// plus 8 (ARM) or 4 (Thumb).
     fn register_bits(&self, reg_bank: RegisterBank) -> u32 {
-        self.cpu.register_file.lookup(reg_bank).unwrap().bits
+        let bits = self.cpu.register_file.lookup(reg_bank).unwrap().bits;
+        if reg_bank == RegisterBank::R15 {
+            bits.wrapping_add(self.pipeline_offset())
+        } else {
+            bits
+        }
+    }
+
+    fn pipeline_offset(&self) -> u32 {
+        match self.cpsr().active_instruction_set() {
+            InstructionSet::ARM => 8,
+            InstructionSet::Thumb => 4,
+        }
+    }
+
+    /// Address of the instruction to execute next.
+    pub fn pc(&self) -> u32 {
+        self.cpu.register_file.lookup(RegisterBank::R15).unwrap().bits
+    }
+
+    fn cpsr(&self) -> &Register32 {
+        self.cpu.register_file.cpsr()
     }
 
     fn program_counter(&mut self) -> &mut Register32 {
         self.register(RegisterBank::R15).unwrap()
     }
 
-    fn execute(&mut self, instr: Instruction) {
+    /// Execute an instruction, advancing the PC past it unless it
+    /// fails or branches.
+    fn execute(&mut self, instr: Instruction) -> Result<(), EmulationError> {
+        self.branched = false;
+        // Inside an IT block, the block supplies the condition.
+        let it_condition = self.it_condition();
+        let in_it_block = it_condition.is_some();
         match instr {
             Instruction::Cond(instr, cond) =>
-                if self.condition_satisfied(cond) {
-                    self.execute_conditional(&instr)
+                if self.condition_satisfied(it_condition.unwrap_or(cond)) {
+                    self.execute_conditional(&instr)?
+                },
+            Instruction::Uncond(UncondInstr::BLX(rel_offset)) => {
+                let target = (self.register_bits(RegisterBank::R15) & !3).wrapping_add(rel_offset as u32);
+                let ret = self.return_address();
+                self.set_register(RegisterBank::R14, ret);
+                // Always switches instruction set.
+                let target = match self.cpsr().active_instruction_set() {
+                    InstructionSet::ARM => target | 1,
+                    InstructionSet::Thumb => target & !1,
+                };
+                self.branch_exchange(target);
+            },
+            Instruction::Thumb(instr) =>
+                if self.condition_satisfied(it_condition.unwrap_or(Condition::AL)) {
+                    self.execute_thumb(&instr)?
                 },
-            Instruction::Uncond(instr) => (),
         }
-        self.program_counter().bits += 4;
+        if in_it_block {
+            self.advance_it_state();
+        }
+        if !self.branched {
+            let size = self.instr_size;
+            let pc = self.program_counter();
+            pc.bits = pc.bits.wrapping_add(size);
+        }
+        Ok(())
     }
 
-    fn condition_satisfied(&self, cond: Condition) -> bool {
-        true                    // TODO: implement properly
+    /// The condition of the current instruction of an IT block, or
+    /// None outside one.
+    fn it_condition(&self) -> Option<Condition> {
+        let it = self.cpsr().it_state();
+        if self.cpsr().active_instruction_set() == InstructionSet::ARM || it & 0xf == 0 {
+            return None;
+        }
+        Some(Condition::decode((it >> 4) as u32).unwrap_or(Condition::AL))
     }
 
-    // TODO: delegate to the BarrelShiftOp
-    fn ror(x: u32, y: u32) -> u32 {
-        if y == 0 {
-            x
+    /// Move on to the next instruction of an IT block, leaving the
+    /// block after the last.
+    fn advance_it_state(&mut self) {
+        let it = self.cpsr().it_state();
+        let it = if it & 0x7 == 0 {
+            0
         } else {
-            (x >> y) | (x << (32 - y))
+            (it & 0xe0) | ((it << 1) & 0x1f)
+        };
+        self.register(RegisterBank::CPSR).unwrap().set_it_state(it);
+    }
+
+    fn unimplemented(&self) -> EmulationError {
+        EmulationError::Unimplemented {
+            addr: self.pc() as address::Address,
+            opcode: None,
+        }
+    }
+
+    fn condition_satisfied(&self, cond: Condition) -> bool {
+        let cpsr = self.cpsr();
+        let n = cpsr.is_condition_flag_on(ConditionFlag::Negative);
+        let z = cpsr.is_condition_flag_on(ConditionFlag::Zero);
+        let c = cpsr.is_condition_flag_on(ConditionFlag::Carry);
+        let v = cpsr.is_condition_flag_on(ConditionFlag::Overflow);
+
+        match cond {
+            Condition::EQ => z,
+            Condition::NE => !z,
+            Condition::CS_HS => c,
+            Condition::CC_LO => !c,
+            Condition::MI => n,
+            Condition::PL => !n,
+            Condition::VS => v,
+            Condition::VC => !v,
+            Condition::HI => c && !z,
+            Condition::LS => !c || z,
+            Condition::GE => n == v,
+            Condition::LT => n != v,
+            Condition::GT => !z && n == v,
+            Condition::LE => z || n != v,
+            Condition::AL => true,
         }
     }
 
     fn shift_size(&self, sz: &ShiftSize) -> u32 {
         match sz {
             &ShiftSize::Imm(n) => n,
-            &ShiftSize::Reg(r) => self.register_bits(r)
+            // Only the least significant byte of Rs is used.
+            &ShiftSize::Reg(r) => self.register_bits(r) & 0xff,
+        }
+    }
+
+    /// Logical shift left. Returns the shifted value and the shifter
+    /// carry-out.
+    fn lsl(x: u32, amount: u32, carry_in: bool) -> (u32, bool) {
+        match amount {
+            0 => (x, carry_in),
+            1..=31 => (x << amount, (x >> (32 - amount)) & 1 == 1),
+            32 => (0, x & 1 == 1),
+            _ => (0, false),
+        }
+    }
+
+    /// Logical shift right. Returns the shifted value and the shifter
+    /// carry-out.
+    fn lsr(x: u32, amount: u32, carry_in: bool) -> (u32, bool) {
+        match amount {
+            0 => (x, carry_in),
+            1..=31 => (x >> amount, (x >> (amount - 1)) & 1 == 1),
+            32 => (0, x >> 31 == 1),
+            _ => (0, false),
+        }
+    }
+
+    /// Arithmetic shift right. Returns the shifted value and the
+    /// shifter carry-out.
+    fn asr(x: u32, amount: u32, carry_in: bool) -> (u32, bool) {
+        match amount {
+            0 => (x, carry_in),
+            1..=31 => (((x as i32) >> amount) as u32, (x >> (amount - 1)) & 1 == 1),
+            _ => if x >> 31 == 1 {
+                (0xffffffff, true)
+            } else {
+                (0, false)
+            },
         }
     }
 
-    fn execute_barrel_shift(&self, op: &BarrelShiftOp) -> u32 {
+    /// Rotate right. Returns the rotated value and the shifter
+    /// carry-out.
+    fn ror(x: u32, amount: u32, carry_in: bool) -> (u32, bool) {
+        if amount == 0 {
+            (x, carry_in)
+        } else {
+            let val = x.rotate_right(amount % 32);
+            (val, val >> 31 == 1)
+        }
+    }
+
+    /// Rotate right by one bit through the carry flag. Returns the
+    /// rotated value and the shifter carry-out.
+    fn rrx(x: u32, carry_in: bool) -> (u32, bool) {
+        (((carry_in as u32) << 31) | (x >> 1), x & 1 == 1)
+    }
+
+    /// Compute the shifter operand of a data-processing instruction,
+    /// along with the shifter carry-out.
+    fn execute_barrel_shift(&self, op: &BarrelShiftOp) -> (u32, bool) {
+        let carry_in = self.cpsr().is_condition_flag_on(ConditionFlag::Carry);
         match op {
-            &BarrelShiftOp::Imm(n) => n,
+            &BarrelShiftOp::Imm(n) => (n, carry_in),
+            &BarrelShiftOp::Reg(rm) => (self.register_bits(rm), carry_in),
             &BarrelShiftOp::RotateImmed { immed, rotate } =>
-                Self::ror(immed, 2 * rotate),
-            &BarrelShiftOp::LSL(ref reg, ref shift_size) =>
-                self.register_bits(*reg) << self.shift_size(shift_size),
-            _ => panic!("unhandled barrel shift op"),
+                Self::ror(immed, 2 * rotate, carry_in),
+            &BarrelShiftOp::LSL(rm, ref shift_size) =>
+                Self::lsl(self.register_bits(rm), self.shift_size(shift_size), carry_in),
+            &BarrelShiftOp::LSR(rm, ref shift_size) =>
+                Self::lsr(self.register_bits(rm), self.shift_size(shift_size), carry_in),
+            &BarrelShiftOp::ASR(rm, ref shift_size) =>
+                Self::asr(self.register_bits(rm), self.shift_size(shift_size), carry_in),
+            &BarrelShiftOp::ROR(rm, ref shift_size) =>
+                Self::ror(self.register_bits(rm), self.shift_size(shift_size), carry_in),
+            &BarrelShiftOp::RRX(rm) =>
+                Self::rrx(self.register_bits(rm), carry_in),
         }
     }
 
-    fn execute_conditional(&mut self, instr: &CondInstr) {
+    fn execute_conditional(&mut self, instr: &CondInstr) -> Result<(), EmulationError> {
         match *instr {
-            CondInstr::AND { s, rd, rn, rotate, immed } => {
-                let bits = self.register(rn).unwrap().bits & Self::ror(immed, 2 * rotate);
-                self.register(rd).unwrap().bits = bits;
-                if s {
-                    // TODO: update CPSR's condition flags
-                }
+            CondInstr::ADC { s, rd, rn, ref shift_op } => {
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let carry = self.cpsr().is_condition_flag_on(ConditionFlag::Carry);
+                let (val, c, v) = Self::add_with_carry(self.register_bits(rn), shift_result, carry);
+                self.write_arithmetic_result(s, rd, val, c, v)?;
+            },
+            CondInstr::ADD { s, rd, rn, ref shift_op } => {
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let (val, c, v) = Self::add_with_carry(self.register_bits(rn), shift_result, false);
+                self.write_arithmetic_result(s, rd, val, c, v)?;
+            },
+            CondInstr::AND { s, rd, rn, ref shift_op } => {
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                let val = self.register_bits(rn) & shift_result;
+                self.write_logical_result(s, rd, val, shifter_carry)?;
             },
             CondInstr::B(rel_offset) => {
-                let mut pc = self.program_counter();
-                pc.bits = ((pc.bits as i32) + rel_offset) as u32;
-
-                // hack to invert effect of PC increment behavior
-                pc.bits -= 4;
+                let target = self.register_bits(RegisterBank::R15).wrapping_add(rel_offset as u32);
+                self.set_register(RegisterBank::R15, target);
             },
             CondInstr::BL(rel_offset) => {
-                let ret = self.program_counter().bits + 4;
-                {
-                    let mut lr = self.register(RegisterBank::R14).unwrap();
-                    lr.bits = ret;
-                }
-
-                let mut pc = self.program_counter();
-                pc.bits = ((pc.bits as i32) + rel_offset) as u32;
-
-                // hack to invert effect of PC increment behavior
-                pc.bits -= 4;
+                let ret = self.return_address();
+                self.set_register(RegisterBank::R14, ret);
+                let target = self.register_bits(RegisterBank::R15).wrapping_add(rel_offset as u32);
+                self.set_register(RegisterBank::R15, target);
             },
-            CondInstr::BIC { s, rd, rn, rotate, immed } => {
-                let val = self.register(rn).unwrap().bits & !Self::ror(immed, 2 * rotate);
-                let cpsr = self.register(RegisterBank::CPSR).unwrap();
-                if s {
-                    // TODO: update C according to shifter carry
-                    // cpsr.set_condition_flag(ConditionFlag::Carry, false);
-
-                    cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
-                    cpsr.set_condition_flag(ConditionFlag::Negative, (val as i32) < 0);
-                }
+            CondInstr::BIC { s, rd, rn, ref shift_op } => {
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                let val = self.register_bits(rn) & !shift_result;
+                self.write_logical_result(s, rd, val, shifter_carry)?;
+            },
+            CondInstr::BFC { rd, lsb, msb } => {
+                let val = self.register_bits(rd) & !Self::bitfield_mask(lsb, msb);
+                self.set_register(rd, val);
+            },
+            CondInstr::BFI { rd, rn, lsb, msb } => {
+                let mask = Self::bitfield_mask(lsb, msb);
+                let val = (self.register_bits(rd) & !mask) | ((self.register_bits(rn) << lsb) & mask);
+                self.set_register(rd, val);
+            },
+            CondInstr::BLX(rm) => {
+                let target = self.register_bits(rm);
+                let ret = self.return_address();
+                self.set_register(RegisterBank::R14, ret);
+                self.branch_exchange(target);
             },
             CondInstr::BX(rm) => {
-                let val = self.register(rm).unwrap().bits & 0xfffffffe;
-                let mut pc = self.program_counter();
-                pc.bits = val;
-
-                // hack to invert effect of PC increment behavior
-                pc.bits -= 4;
-
-                // TODO: Set T-flag to 'Rm & 1' (may enable Thumb
-                // mode)
+                let target = self.register_bits(rm);
+                self.branch_exchange(target);
             },
             CondInstr::CMN { rn, ref shift_op } => {
-                let shift_result = self.execute_barrel_shift(shift_op);
-                let val = self.register(rn).unwrap().bits + shift_result;
-
-                let cpsr = self.register(RegisterBank::CPSR).unwrap();
-                // TODO: update C according to shifter carry
-                // cpsr.set_condition_flag(ConditionFlag::Carry, false);
-
-                // TODO: update V according to signed overflow check
-
-                cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
-                cpsr.set_condition_flag(ConditionFlag::Negative, (val as i32) < 0); // TODO: test
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let (val, c, v) = Self::add_with_carry(self.register_bits(rn), shift_result, false);
+                self.set_arithmetic_flags(val, c, v);
             },
             CondInstr::CMP { rn, ref shift_op } => {
-                let shift_result = self.execute_barrel_shift(shift_op);
-                // TODO: test casting logic
-                let val = ((self.register(rn).unwrap().bits as i32) - (shift_result as i32)) as u32;
-
-                let cpsr = self.register(RegisterBank::CPSR).unwrap();
-                // TODO: update C according to shifter carry
-                // cpsr.set_condition_flag(ConditionFlag::Carry, false);
-
-                // TODO: update V according to signed overflow check
-
-                cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
-                cpsr.set_condition_flag(ConditionFlag::Negative, (val as i32) < 0); // TODO: test
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let (val, c, v) = Self::add_with_carry(self.register_bits(rn), !shift_result, true);
+                self.set_arithmetic_flags(val, c, v);
             },
-            CondInstr::LDR { rd, ref addr_ref } => {
-                let addr = {
-                    let mut rn = self.register(*addr_ref.get_base()).unwrap();
-                    let addr = addr_ref.get_addr(rn);
-                    addr_ref.handle_writeback(&mut rn);
-                    addr
-                };
-
-                if let Some(word) = self.mem.get32(addr, self.big_endian) {
-                    let mut rd = self.register(rd).unwrap();
-                    rd.bits = word;
+            CondInstr::EOR { s, rd, rn, ref shift_op } => {
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                let val = self.register_bits(rn) ^ shift_result;
+                self.write_logical_result(s, rd, val, shifter_carry)?;
+            },
+            CondInstr::LDM { mode, carrot, w, rn, ref reg_list } => {
+                let (start_addr, new_base) = self.block_transfer_addresses(mode, rn, reg_list.len());
+                let loads_pc = reg_list.contains(&RegisterBank::R15);
+                // Load everything before touching any register, so an
+                // abort leaves them as they were.
+                let mut words = Vec::with_capacity(reg_list.len());
+                let mut addr = start_addr;
+                for _ in reg_list {
+                    words.push(self.load_word(addr)?);
+                    addr = addr.wrapping_add(4);
+                }
+                // Writing back first lets a loaded base register win.
+                if w {
+                    self.set_register(rn, new_base);
+                }
+                for (&reg, &word) in reg_list.iter().zip(words.iter()) {
+                    if carrot && !loads_pc {
+                        // The ^ form without the PC loads the User
+                        // mode registers.
+                        self.cpu.register_file.lookup_banked_mut(reg, ProcessorMode::User).unwrap().bits = word;
+                    } else if reg == RegisterBank::R15 && carrot {
+                        // Aligned below, once the CPSR says which
+                        // instruction set it returns to.
+                        self.program_counter().bits = word;
+                    } else if reg == RegisterBank::R15 {
+                        self.branch_exchange(word);
+                    } else {
+                        self.set_register(reg, word);
+                    }
+                }
+                // The ^ form with the PC returns from an exception.
+                if carrot && loads_pc {
+                    self.restore_cpsr()?;
+                    let pc = self.pc();
+                    self.set_register(RegisterBank::R15, pc);
+                }
+            },
+            CondInstr::LDR { rd, ref addr_ref } |
+            CondInstr::LDRT { rd, ref addr_ref } => {
+                // Without an MMU, the T variants' user-mode access is
+                // the same as any other.
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let word = self.load_word(addr)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                if rd == RegisterBank::R15 {
+                    self.branch_exchange(word);
                 } else {
-                    panic!("Failed to read memory at address {:#x}", addr);
+                    self.set_register(rd, word);
                 }
             },
-            CondInstr::MCR { op1, cn, rd, copro, op2, cm } => {
-                // TODO: implement coprocessors and interpret this instruction
-                println!("Skipping coprocessor logic for now!");
+            CondInstr::LDRB { rd, ref addr_ref } |
+            CondInstr::LDRBT { rd, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let byte = self.mem.get8(addr as address::Address)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, byte as u32);
+            },
+            CondInstr::LDRD { rd, ref addr_ref } => {
+                let (addr, writeback) = self.halfword_or_signed_address(addr_ref);
+                self.check_aligned(addr, 8)?;
+                let low = self.load_word(addr)?;
+                let high = self.load_word(addr.wrapping_add(4))?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, low);
+                self.set_register(rd.successor(), high);
+            },
+            CondInstr::LDRH { rd, ref addr_ref } => {
+                let (addr, writeback) = self.halfword_or_signed_address(addr_ref);
+                let halfword = self.load_halfword(addr)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, halfword as u32);
+            },
+            CondInstr::LDRSB { rd, ref addr_ref } => {
+                let (addr, writeback) = self.halfword_or_signed_address(addr_ref);
+                let byte = self.mem.get8(addr as address::Address)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, byte as i8 as i32 as u32);
+            },
+            CondInstr::LDRSH { rd, ref addr_ref } => {
+                let (addr, writeback) = self.halfword_or_signed_address(addr_ref);
+                let halfword = self.load_halfword(addr)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, halfword as i16 as i32 as u32);
+            },
+            CondInstr::MLA { s, rd, rm, rs, rn } => {
+                let val = self.register_bits(rm)
+                    .wrapping_mul(self.register_bits(rs))
+                    .wrapping_add(self.register_bits(rn));
+                self.write_multiply_result(s, rd, val);
+            },
+            CondInstr::MLS { rd, rm, rs, rn } => {
+                let val = self.register_bits(rn)
+                    .wrapping_sub(self.register_bits(rm).wrapping_mul(self.register_bits(rs)));
+                self.set_register(rd, val);
             },
             CondInstr::MOV { s, rd, ref shift_op } => {
-                let shift_result = self.execute_barrel_shift(shift_op);
-                if s {
-                    // TODO: update CPSR
-                }
-                let mut rd = self.register(rd).unwrap();
-                rd.bits = shift_result;
-            }
-            CondInstr::MRC { op1, cn, rd, copro, op2, cm } => {
-                // TODO: implement coprocessors and interpret this instruction
-                println!("Skipping coprocessor logic for now!");
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                self.write_logical_result(s, rd, shift_result, shifter_carry)?;
+            },
+            CondInstr::MOVT { rd, imm16 } => {
+                let val = (self.register_bits(rd) & 0xffff) | (imm16 << 16);
+                self.set_register(rd, val);
             },
             CondInstr::MRS { rd, psr } => {
-                self.copy_register(rd, psr);
+                // Reading the SPSR is unpredictable in modes without
+                // one, so leave Rd as it was.
+                if let Some(bits) = self.cpu.register_file.lookup(psr).map(|reg| reg.bits) {
+                    self.set_register(rd, bits);
+                }
             },
             CondInstr::MSR { psr, rm, f, s, x, c } => {
-                // TODO: observe note that bits[23:0] of the cpsr are
-                // unaffected in User mode.
-                let mask: u32 = {
+                let mut mask: u32 = {
                     let c_mask = if c { 0x000000ff } else { 0 };
                     let x_mask = if x { 0x0000ff00 } else { 0 };
                     let s_mask = if s { 0x00ff0000 } else { 0 };
                     let f_mask = if f { 0xff000000 } else { 0 };
                     c_mask | x_mask | s_mask | f_mask
                 };
-                let mut psr_bits = self.register(psr).unwrap().bits;
-                let masked_psr = psr_bits & !mask;
-                let masked_reg = self.register(rm).unwrap().bits & mask;
-                psr_bits = masked_psr | masked_reg;
-            },
-            CondInstr::ORR { s, rd, rn, rotate, immed } => {
-                // TODO: address Notes section of ORR in A.3.
-
-                let val = self.register(rn).unwrap().bits | Self::ror(immed, 2 * rotate);
-                let cpsr = self.register(RegisterBank::CPSR).unwrap();
-                if s {
-                    // TODO: update C according to shifter carry
-                    // cpsr.set_condition_flag(ConditionFlag::Carry, false);
-
-                    cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
-                    cpsr.set_condition_flag(ConditionFlag::Negative, (val as i32) < 0);
+                // Bits[23:0] of the CPSR are unaffected in User mode.
+                if psr == RegisterBank::CPSR && !self.cpsr().is_privileged_mode() {
+                    mask &= 0xff000000;
+                }
+                let masked_reg = self.register_bits(rm) & mask;
+                if psr == RegisterBank::CPSR {
+                    let bits = (self.cpsr().bits & !mask) | masked_reg;
+                    self.write_cpsr(bits)?;
+                } else if let Some(reg) = self.register(psr) {
+                    reg.bits = (reg.bits & !mask) | masked_reg;
                 }
             },
-            CondInstr::STMDB { carrot, w, rn, ref reg_list } => {
-                // TODO
-                println!("Skipping STMDB logic for now!");
+            CondInstr::MUL { s, rd, rm, rs } => {
+                let val = self.register_bits(rm).wrapping_mul(self.register_bits(rs));
+                self.write_multiply_result(s, rd, val);
             },
-            CondInstr::SUB { s, rd, rn, ref shift_op } => {
-                let shift_result = self.execute_barrel_shift(shift_op);
-                let sub = self.register_bits(rn) - shift_result;
-                if s {
-                    // TODO: update CPSR
+            CondInstr::MVN { s, rd, ref shift_op } => {
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                self.write_logical_result(s, rd, !shift_result, shifter_carry)?;
+            },
+            CondInstr::ORR { s, rd, rn, ref shift_op } => {
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                let val = self.register_bits(rn) | shift_result;
+                self.write_logical_result(s, rd, val, shifter_carry)?;
+            },
+            CondInstr::RSB { s, rd, rn, ref shift_op } => {
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let (val, c, v) = Self::add_with_carry(shift_result, !self.register_bits(rn), true);
+                self.write_arithmetic_result(s, rd, val, c, v)?;
+            },
+            CondInstr::RSC { s, rd, rn, ref shift_op } => {
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let carry = self.cpsr().is_condition_flag_on(ConditionFlag::Carry);
+                let (val, c, v) = Self::add_with_carry(shift_result, !self.register_bits(rn), carry);
+                self.write_arithmetic_result(s, rd, val, c, v)?;
+            },
+            CondInstr::SBC { s, rd, rn, ref shift_op } => {
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let carry = self.cpsr().is_condition_flag_on(ConditionFlag::Carry);
+                let (val, c, v) = Self::add_with_carry(self.register_bits(rn), !shift_result, carry);
+                self.write_arithmetic_result(s, rd, val, c, v)?;
+            },
+            CondInstr::SBFX { rd, rn, lsb, widthm1 } => {
+                let msb = lsb + widthm1;
+                let val = ((self.register_bits(rn) << (31 - msb)) as i32) >> (31 - widthm1);
+                self.set_register(rd, val as u32);
+            },
+            CondInstr::SMLAL { s, rdlo, rdhi, rm, rs } => {
+                let product = (self.register_bits(rm) as i32 as i64)
+                    .wrapping_mul(self.register_bits(rs) as i32 as i64);
+                let val = (product as u64).wrapping_add(self.long_register_bits(rdlo, rdhi));
+                self.write_long_multiply_result(s, rdlo, rdhi, val);
+            },
+            CondInstr::SMULL { s, rdlo, rdhi, rm, rs } => {
+                let product = (self.register_bits(rm) as i32 as i64)
+                    .wrapping_mul(self.register_bits(rs) as i32 as i64);
+                self.write_long_multiply_result(s, rdlo, rdhi, product as u64);
+            },
+            CondInstr::STR { rd, ref addr_ref } |
+            CondInstr::STRT { rd, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let word = self.register_bits(rd);
+                self.store_word(addr, word)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+            },
+            CondInstr::STRB { rd, ref addr_ref } |
+            CondInstr::STRBT { rd, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let byte = self.register_bits(rd) as u8;
+                self.mem.put8(addr as address::Address, byte)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+            },
+            CondInstr::STRD { rd, ref addr_ref } => {
+                let (addr, writeback) = self.halfword_or_signed_address(addr_ref);
+                self.check_aligned(addr, 8)?;
+                let low = self.register_bits(rd);
+                let high = self.register_bits(rd.successor());
+                self.store_word(addr, low)?;
+                self.store_word(addr.wrapping_add(4), high)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+            },
+            CondInstr::STRH { rd, ref addr_ref } => {
+                let (addr, writeback) = self.halfword_or_signed_address(addr_ref);
+                let halfword = self.register_bits(rd) as u16;
+                self.mem.put16((addr & !1) as address::Address, halfword, self.big_endian)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+            },
+            CondInstr::STM { mode, carrot, w, rn, ref reg_list } => {
+                let (start_addr, new_base) = self.block_transfer_addresses(mode, rn, reg_list.len());
+                let mut addr = start_addr;
+                for &reg in reg_list {
+                    // The ^ form stores the User mode registers.
+                    let word = if carrot {
+                        self.cpu.register_file.lookup_banked(reg, ProcessorMode::User).unwrap().bits
+                    } else {
+                        self.register_bits(reg)
+                    };
+                    self.store_word(addr, word)?;
+                    addr = addr.wrapping_add(4);
+                }
+                if w {
+                    self.set_register(rn, new_base);
                 }
-                let mut rd = self.register(rd).unwrap();
-                rd.bits = sub;
+            },
+            CondInstr::SUB { s, rd, rn, ref shift_op } => {
+                let (shift_result, _) = self.execute_barrel_shift(shift_op);
+                let (val, c, v) = Self::add_with_carry(self.register_bits(rn), !shift_result, true);
+                self.write_arithmetic_result(s, rd, val, c, v)?;
+            },
+            CondInstr::SWI(_) => {
+                // The handler finds the comment field through the
+                // return address in LR.
+                // The handler returns to the rest of any IT block.
+                self.advance_it_state();
+                self.enter_exception(Exception::SoftwareInterrupt);
+            },
+            CondInstr::SXTB { rd, rm, rotate } => {
+                let val = self.register_bits(rm).rotate_right(rotate) as i8 as i32 as u32;
+                self.set_register(rd, val);
+            },
+            CondInstr::SXTH { rd, rm, rotate } => {
+                let val = self.register_bits(rm).rotate_right(rotate) as i16 as i32 as u32;
+                self.set_register(rd, val);
             },
             CondInstr::TEQ { rn, ref shift_op } => {
-                let shift_result = self.execute_barrel_shift(shift_op);
-                let val = self.register(rn).unwrap().bits ^ shift_result;
-
-                let cpsr = self.register(RegisterBank::CPSR).unwrap();
-                // TODO: update C according to shifter carry
-                // cpsr.set_condition_flag(ConditionFlag::Carry, false);
-
-                cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
-                cpsr.set_condition_flag(ConditionFlag::Negative, (val as i32) < 0); // TODO: test
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                let val = self.register_bits(rn) ^ shift_result;
+                self.set_logical_flags(val, shifter_carry);
             },
             CondInstr::TST { rn, ref shift_op } => {
-                let shift_result = self.execute_barrel_shift(shift_op);
-                let val = self.register(rn).unwrap().bits & shift_result;
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                let val = self.register_bits(rn) & shift_result;
+                self.set_logical_flags(val, shifter_carry);
+            },
+            CondInstr::UBFX { rd, rn, lsb, widthm1 } => {
+                let val = (self.register_bits(rn) >> lsb) & Self::bitfield_mask(0, widthm1);
+                self.set_register(rd, val);
+            },
+            CondInstr::UMLAL { s, rdlo, rdhi, rm, rs } => {
+                let product = (self.register_bits(rm) as u64)
+                    .wrapping_mul(self.register_bits(rs) as u64);
+                let val = product.wrapping_add(self.long_register_bits(rdlo, rdhi));
+                self.write_long_multiply_result(s, rdlo, rdhi, val);
+            },
+            CondInstr::UMULL { s, rdlo, rdhi, rm, rs } => {
+                let product = (self.register_bits(rm) as u64)
+                    .wrapping_mul(self.register_bits(rs) as u64);
+                self.write_long_multiply_result(s, rdlo, rdhi, product);
+            },
+            CondInstr::UXTB { rd, rm, rotate } => {
+                let val = self.register_bits(rm).rotate_right(rotate) as u8 as u32;
+                self.set_register(rd, val);
+            },
+            CondInstr::UXTH { rd, rm, rotate } => {
+                let val = self.register_bits(rm).rotate_right(rotate) as u16 as u32;
+                self.set_register(rd, val);
+            },
+            // TODO: implement coprocessors. Until then MCR and MRC
+            // are undefined, as on a core without them.
+            _ => return Err(self.unimplemented()),
+        }
+        Ok(())
+    }
 
-                let cpsr = self.register(RegisterBank::CPSR).unwrap();
-                // TODO: update C according to shifter carry
-                // cpsr.set_condition_flag(ConditionFlag::Carry, false);
+    fn execute_thumb(&mut self, instr: &ThumbInstr) -> Result<(), EmulationError> {
+        match *instr {
+            ThumbInstr::ADR { rd, offset } => {
+                let val = (self.register_bits(RegisterBank::R15) & !3).wrapping_add(offset as u32);
+                self.set_register(rd, val);
+            },
+            ThumbInstr::BKPT(_) => self.enter_exception(Exception::PrefetchAbort),
+            ThumbInstr::CBNZ { rn, offset } |
+            ThumbInstr::CBZ { rn, offset } => {
+                let zero = self.register_bits(rn) == 0;
+                let on_zero = match *instr {
+                    ThumbInstr::CBZ { .. } => true,
+                    _ => false,
+                };
+                if zero == on_zero {
+                    let target = self.register_bits(RegisterBank::R15).wrapping_add(offset);
+                    self.set_register(RegisterBank::R15, target);
+                }
+            },
+            ThumbInstr::IT { ref firstcond, mask } => {
+                let it = (Condition::encode(firstcond.clone()) << 4) | mask;
+                self.register(RegisterBank::CPSR).unwrap().set_it_state(it as u8);
+            },
+            ThumbInstr::LDRD { rd, rd2, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                self.check_aligned(addr, 4)?;
+                let low = self.load_word(addr)?;
+                let high = self.load_word(addr.wrapping_add(4))?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, low);
+                self.set_register(rd2, high);
+            },
+            ThumbInstr::LDRH { rd, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let halfword = self.load_halfword(addr)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, halfword as u32);
+            },
+            ThumbInstr::LDRSB { rd, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let byte = self.mem.get8(addr as address::Address)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, byte as i8 as i32 as u32);
+            },
+            ThumbInstr::LDRSH { rd, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let halfword = self.load_halfword(addr)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+                self.set_register(rd, halfword as i16 as i32 as u32);
+            },
+            ThumbInstr::NOP => (),
+            ThumbInstr::ORN { s, rd, rn, ref shift_op } => {
+                let (shift_result, shifter_carry) = self.execute_barrel_shift(shift_op);
+                let val = self.register_bits(rn) | !shift_result;
+                self.write_logical_result(s, rd, val, shifter_carry)?;
+            },
+            ThumbInstr::STRD { rd, rd2, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                self.check_aligned(addr, 4)?;
+                let low = self.register_bits(rd);
+                let high = self.register_bits(rd2);
+                self.store_word(addr, low)?;
+                self.store_word(addr.wrapping_add(4), high)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+            },
+            ThumbInstr::STRH { rd, ref addr_ref } => {
+                let (addr, writeback) = self.word_or_byte_address(addr_ref);
+                let halfword = self.register_bits(rd) as u16;
+                self.mem.put16((addr & !1) as address::Address, halfword, self.big_endian)?;
+                self.write_back(*addr_ref.get_base(), writeback);
+            },
+            ThumbInstr::TBB { rn, rm } => {
+                let addr = self.register_bits(rn).wrapping_add(self.register_bits(rm));
+                let offset = self.mem.get8(addr as address::Address)? as u32;
+                let target = self.register_bits(RegisterBank::R15).wrapping_add(offset << 1);
+                self.set_register(RegisterBank::R15, target);
+            },
+            ThumbInstr::TBH { rn, rm } => {
+                let addr = self.register_bits(rn).wrapping_add(self.register_bits(rm) << 1);
+                let offset = self.load_halfword(addr)? as u32;
+                let target = self.register_bits(RegisterBank::R15).wrapping_add(offset << 1);
+                self.set_register(RegisterBank::R15, target);
+            },
+        }
+        Ok(())
+    }
 
-                // TODO: update V according to signed overflow check
+    /// Compute the address accessed by a word or unsigned byte
+    /// load/store, along with the new base register value if the
+    /// addressing mode writes one back.
+    fn word_or_byte_address(&self, addr_ref: &WordOrUnsignedByte) -> (u32, Option<u32>) {
+        let base = self.base_register_bits(*addr_ref.get_base());
+        let offset = match addr_ref.get_offset() {
+            &AddressingOffset12::Immed { offset12, .. } => offset12 as u32,
+            &AddressingOffset12::Register { offset, .. } => self.register_bits(offset),
+            &AddressingOffset12::ScaledRegister { ref shift_op, .. } =>
+                self.execute_barrel_shift(shift_op).0,
+        };
+        let offset_addr = if addr_ref.is_positive_offset() {
+            base.wrapping_add(offset)
+        } else {
+            base.wrapping_sub(offset)
+        };
 
-                cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
-                cpsr.set_condition_flag(ConditionFlag::Negative, (val as i32) < 0); // TODO: test
-            },
-            _ => panic!("Unhandled instruction {:?}", instr),
+        match addr_ref {
+            &WordOrUnsignedByte::PreIndex { writeback, .. } =>
+                (offset_addr, if writeback { Some(offset_addr) } else { None }),
+            &WordOrUnsignedByte::PostIndex { .. } =>
+                (base, Some(offset_addr)),
         }
     }
 
-    fn copy_register(&mut self, dest: RegisterBank, src: RegisterBank) {
-        let bits = {
-            let s = self.register(src).unwrap();
-            s.bits
+    /// Compute the address accessed by a halfword, signed byte or
+    /// doubleword load/store, along with the new base register value
+    /// if the addressing mode writes one back.
+    fn halfword_or_signed_address(&self, addr_ref: &HalfwordOrSigned) -> (u32, Option<u32>) {
+        let base = self.base_register_bits(*addr_ref.get_base());
+        let offset = match addr_ref.get_offset() {
+            &AddressingOffset8::Immed { offset8, .. } => offset8 as u32,
+            &AddressingOffset8::Register { offset, .. } => self.register_bits(offset),
+        };
+        let offset_addr = if addr_ref.is_positive_offset() {
+            base.wrapping_add(offset)
+        } else {
+            base.wrapping_sub(offset)
         };
-        let mut d = self.register(dest).unwrap();
-        d.bits = bits;
+
+        match addr_ref {
+            &HalfwordOrSigned::PreIndex { writeback, .. } =>
+                (offset_addr, if writeback { Some(offset_addr) } else { None }),
+            &HalfwordOrSigned::PostIndex { .. } =>
+                (base, Some(offset_addr)),
+        }
+    }
+
+    /// Read the base register of a load or store. A PC base is word
+    /// aligned, which only matters in Thumb state.
+    fn base_register_bits(&self, rn: RegisterBank) -> u32 {
+        if rn == RegisterBank::R15 {
+            self.register_bits(rn) & !3
+        } else {
+            self.register_bits(rn)
+        }
+    }
+
+    /// Compute the lowest address accessed by a load or store
+    /// multiple, along with the base register's written back value.
+    fn block_transfer_addresses(&self, mode: LoadStoreMultiple, rn: RegisterBank, count: usize) -> (u32, u32) {
+        let base = self.register_bits(rn) & !3;
+        let size = 4 * count as u32;
+        match mode {
+            LoadStoreMultiple::IA => (base, base.wrapping_add(size)),
+            LoadStoreMultiple::IB => (base.wrapping_add(4), base.wrapping_add(size)),
+            LoadStoreMultiple::DA => (base.wrapping_sub(size).wrapping_add(4), base.wrapping_sub(size)),
+            LoadStoreMultiple::DB => (base.wrapping_sub(size), base.wrapping_sub(size)),
+        }
+    }
+
+    fn write_back(&mut self, base: RegisterBank, writeback: Option<u32>) {
+        if let Some(bits) = writeback {
+            self.set_register(base, bits);
+        }
+    }
+
+    /// Load the word containing `addr`. Unaligned addresses rotate the
+    /// loaded word so the addressed byte is least significant.
+    fn load_word(&self, addr: u32) -> Result<u32, EmulationError> {
+        let word = self.mem.get32((addr & !3) as address::Address, self.big_endian)?;
+        Ok(word.rotate_right(8 * (addr & 3)))
+    }
+
+    /// Load a halfword, ignoring the low bit of an unaligned address.
+    fn load_halfword(&self, addr: u32) -> Result<u16, EmulationError> {
+        self.mem.get16((addr & !1) as address::Address, self.big_endian)
+    }
+
+    /// Store a word, ignoring the low bits of an unaligned address.
+    fn store_word(&mut self, addr: u32, word: u32) -> Result<(), EmulationError> {
+        self.mem.put32((addr & !3) as address::Address, word, self.big_endian)
+    }
+
+    /// Doubleword transfers need an aligned address: to a doubleword
+    /// in ARM code, but only to a word in Thumb-2.
+    fn check_aligned(&self, addr: u32, alignment: u32) -> Result<(), EmulationError> {
+        if addr.is_multiple_of(alignment) {
+            Ok(())
+        } else {
+            Err(EmulationError::Misaligned { addr: addr as address::Address, opcode: None })
+        }
+    }
+
+    /// The mask of bits `lsb` to `msb` inclusive.
+    fn bitfield_mask(lsb: u32, msb: u32) -> u32 {
+        (0xffffffff >> (31 - msb)) & (0xffffffff << lsb)
+    }
+
+    /// Add two operands and a carry the way the ALU does, returning
+    /// the result along with the unsigned carry-out and the signed
+    /// overflow. Subtraction is `x + !y + 1`, so a set carry means "no
+    /// borrow".
+    fn add_with_carry(x: u32, y: u32, carry_in: bool) -> (u32, bool, bool) {
+        let unsigned_sum = x as u64 + y as u64 + carry_in as u64;
+        let signed_sum = x as i32 as i64 + y as i32 as i64 + carry_in as i64;
+        let result = unsigned_sum as u32;
+        (result, unsigned_sum != result as u64, signed_sum != result as i32 as i64)
+    }
+
+    fn set_result_flags(&mut self, val: u32) {
+        let cpsr = self.register(RegisterBank::CPSR).unwrap();
+        cpsr.set_condition_flag(ConditionFlag::Negative, (val as i32) < 0);
+        cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
+    }
+
+    /// Arithmetic operations set C from the unsigned carry (or lack of
+    /// borrow) and V from the signed overflow.
+    fn set_arithmetic_flags(&mut self, val: u32, carry: bool, overflow: bool) {
+        self.set_result_flags(val);
+        let cpsr = self.register(RegisterBank::CPSR).unwrap();
+        cpsr.set_condition_flag(ConditionFlag::Carry, carry);
+        cpsr.set_condition_flag(ConditionFlag::Overflow, overflow);
+    }
+
+    /// Logical operations set C from the shifter carry-out and leave V
+    /// alone.
+    fn set_logical_flags(&mut self, val: u32, shifter_carry: bool) {
+        self.set_result_flags(val);
+        let cpsr = self.register(RegisterBank::CPSR).unwrap();
+        cpsr.set_condition_flag(ConditionFlag::Carry, shifter_carry);
+    }
+
+    fn write_arithmetic_result(&mut self, s: bool, rd: RegisterBank, val: u32, carry: bool, overflow: bool)
+                               -> Result<(), EmulationError> {
+        if s {
+            if rd == RegisterBank::R15 {
+                self.restore_cpsr()?;
+            } else {
+                self.set_arithmetic_flags(val, carry, overflow);
+            }
+        }
+        self.set_register(rd, val);
+        Ok(())
+    }
+
+    fn write_logical_result(&mut self, s: bool, rd: RegisterBank, val: u32, shifter_carry: bool)
+                            -> Result<(), EmulationError> {
+        if s {
+            if rd == RegisterBank::R15 {
+                self.restore_cpsr()?;
+            } else {
+                self.set_logical_flags(val, shifter_carry);
+            }
+        }
+        self.set_register(rd, val);
+        Ok(())
+    }
+
+    /// Multiplies set N and Z from the result and leave C and V
+    /// alone.
+    fn write_multiply_result(&mut self, s: bool, rd: RegisterBank, val: u32) {
+        self.set_register(rd, val);
+        if s {
+            self.set_result_flags(val);
+        }
+    }
+
+    fn long_register_bits(&self, lo: RegisterBank, hi: RegisterBank) -> u64 {
+        ((self.register_bits(hi) as u64) << 32) | self.register_bits(lo) as u64
+    }
+
+    fn write_long_multiply_result(&mut self, s: bool, rdlo: RegisterBank, rdhi: RegisterBank, val: u64) {
+        self.set_register(rdlo, val as u32);
+        self.set_register(rdhi, (val >> 32) as u32);
+        if s {
+            let cpsr = self.register(RegisterBank::CPSR).unwrap();
+            cpsr.set_condition_flag(ConditionFlag::Negative, (val as i64) < 0);
+            cpsr.set_condition_flag(ConditionFlag::Zero, val == 0);
+        }
+    }
+
+    /// Copy the current mode's SPSR into the CPSR, as done when an
+    /// S-suffixed instruction writes the PC. Modes without an SPSR
+    /// leave the CPSR unchanged.
+    fn restore_cpsr(&mut self) -> Result<(), EmulationError> {
+        let spsr = match self.cpu.register_file.lookup(RegisterBank::SPSR) {
+            Some(spsr) => spsr.bits,
+            None => return Ok(()),
+        };
+        self.write_cpsr(spsr)
+    }
+
+    /// Replace the CPSR, refusing values that don't name a processor
+    /// mode.
+    fn write_cpsr(&mut self, bits: u32) -> Result<(), EmulationError> {
+        if (Register32 { bits: bits }).mode().is_none() {
+            return Err(EmulationError::InvalidMode {
+                addr: self.pc() as address::Address,
+                opcode: None,
+                mode: bits & 0x1f,
+            });
+        }
+        self.register(RegisterBank::CPSR).unwrap().bits = bits;
+        Ok(())
+    }
+
+    /// Write the result of an instruction to a register. Writing to
+    /// the PC branches to the written address, aligned for the
+    /// current instruction set.
+    fn set_register(&mut self, reg_bank: RegisterBank, bits: u32) {
+        let bits = if reg_bank == RegisterBank::R15 {
+            self.branched = true;
+            match self.cpsr().active_instruction_set() {
+                InstructionSet::ARM => bits & !3,
+                InstructionSet::Thumb => bits & !1,
+            }
+        } else {
+            bits
+        };
+        self.register(reg_bank).unwrap().bits = bits;
+    }
+
+    /// Branch to an address, entering Thumb state if bit 0 is set and
+    /// ARM state otherwise, as BX and loads into the PC do.
+    fn branch_exchange(&mut self, target: u32) {
+        let instr_set = if target & 1 == 1 {
+            InstructionSet::Thumb
+        } else {
+            InstructionSet::ARM
+        };
+        self.register(RegisterBank::CPSR).unwrap().set_instruction_set(instr_set);
+        self.set_register(RegisterBank::R15, target);
+    }
+
+    /// The address a BL or BLX returns to: the next instruction, with
+    /// bit 0 set in Thumb state so that BX returns to Thumb code.
+    fn return_address(&self) -> u32 {
+        let ret = self.pc().wrapping_add(self.instr_size);
+        match self.cpsr().active_instruction_set() {
+            InstructionSet::ARM => ret,
+            InstructionSet::Thumb => ret | 1,
+        }
     }
 }
 
@@ -303,11 +1204,31 @@ impl Computer {
 
 #[cfg(test)]
 mod test {
-    use super::Computer;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::{Computer, FaultPolicy};
+    use address::{AccessSize, CellCount, Device, LowMemory, MemMap32};
+    use assembler;
+    use elf;
+    use elf::ElfError;
+    use elf::test::build_elf_with;
+    use error::EmulationError;
     use processor::{
+        BarrelShiftOp,
         Condition,
         CondInstr,
+        Encodable,
         Instruction,
+        ShiftSize,
+    };
+    use registers::{
+        ConditionFlag,
+        Exception,
+        InstructionSet,
+        InterruptMask,
+        ProcessorMode,
+        ProgramStatusRegister,
+        RegisterBank,
     };
 
 
@@ -318,15 +1239,1065 @@ mod test {
             CondInstr::B(rel_offset),
             Condition::AL);
 
-        // TODO: Create a Computer with a random PC addr, and the
-        // encoded form of `branch` at that PC addr. Execute one
-        // instruction and assert post-conditions hold.
+        // Offsets are relative to the branch's address plus 8.
+        let mut computer = Computer::new(vec![]);
+        set_register(&mut computer, RegisterBank::R15, 0x80001000);
+        computer.execute(branch).unwrap();
+        assert_eq!(computer.pc(), 0x80001038);
+
+        computer.execute(Instruction::Cond(CondInstr::BL(-16), Condition::AL)).unwrap();
+        assert_eq!(computer.pc(), 0x80001030);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x8000103c);
+
+        computer.execute(Instruction::Cond(CondInstr::BX(RegisterBank::R14), Condition::AL)).unwrap();
+        assert_eq!(computer.pc(), 0x8000103c);
+    }
+
+    #[test]
+    fn encoded_program() {
+        use registers::RegisterBank::*;
+
+        let add = |rd, rn, shift_op| CondInstr::ADD { s: false, rd: rd, rn: rn, shift_op: shift_op };
+        let program: Vec<u32> = vec![
+            (CondInstr::MOV { s: false, rd: R0, shift_op: BarrelShiftOp::Imm(10) }, Condition::AL),
+            (CondInstr::MOV { s: false, rd: R1, shift_op: BarrelShiftOp::Imm(0) }, Condition::AL),
+            // loop:
+            (add(R1, R1, BarrelShiftOp::LSL(R0, ShiftSize::Imm(1))), Condition::AL),
+            (CondInstr::SUB { s: true, rd: R0, rn: R0, shift_op: BarrelShiftOp::Imm(1) }, Condition::AL),
+            (CondInstr::B(-16), Condition::NE),
+            (CondInstr::MOVT { rd: R1, imm16: 0xabcd }, Condition::AL),
+            (CondInstr::B(-8), Condition::AL),
+        ].into_iter().map(|(instr, cond)| Instruction::encode(Instruction::Cond(instr, cond))).collect();
+
+        let mut computer = computer_with_program(&program);
+        run_until(&mut computer, 0x18);
+        assert_eq!(computer.register_bits(R0), 0);
+        assert_eq!(computer.register_bits(R1), 0xabcd006e);
+    }
+
+    #[test]
+    fn assembled_program() {
+        let boot_code = assembler::assemble("
+                ldr sp, =0x80001000
+                adr r0, table
+                mov r1, #3
+                bl sum
+                ldr r3, =0x80000000
+                str r2, [r3]
+            done:
+                b done
+
+            sum:                        @ adds r1 words from r0 into r2
+                push {r4, lr}
+                mov r2, #0
+            next:
+                ldr r4, [r0], #4
+                add r2, r2, r4
+                subs r1, r1, #1
+                bne next
+                pop {r4, pc}
+
+                .ltorg
+                .align 3
+            table:
+                .word 0x11111111, 0x22222222, 0x12345678
+        ").unwrap();
+        let mut computer = Computer::new(boot_code);
+        run_until(&mut computer, 0x18);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 0x456789ab);
+        assert_eq!(computer.register_bits(RegisterBank::R13), 0x80001000);
+        assert_eq!(computer.mem.get32(0x80000000, false), Ok(0x456789ab));
+    }
+
+    #[test]
+    fn load_elf_executables() {
+        let code = assembler::assemble("
+                mov r0, #0x20000
+                ldr r1, [r0]
+                ldr r2, [r0, #4]
+                add r1, r1, r2
+                str r1, [r0, #4]
+            done:
+                b done
+        ").unwrap();
+        let symbols = [("done", 0x8014, 4, elf::STT_FUNC, 1), ("total", 0x20004, 4, elf::STT_OBJECT, 3)];
+
+        // Little-endian, BE-8 with little-endian code, and BE-32.
+        for &(big_endian, flags) in [(false, 0), (true, elf::EF_ARM_BE8), (true, 0)].iter() {
+            let mut text = code.clone();
+            if big_endian && flags == 0 {
+                for word in text.chunks_mut(4) {
+                    word.reverse();
+                }
+            }
+            let data = if big_endian { 0x12345678u32.to_be_bytes() } else { 0x12345678u32.to_le_bytes() };
+            let file = build_elf_with(big_endian, flags, 0x8000, &[
+                (".text", 1, 0x8000, text),
+                (".data", 1, 0x20000, data.to_vec()),
+                (".bss", elf::SHT_NOBITS, 0x20004, vec![0; 4]),
+            ], &symbols);
+
+            let mut computer = Computer::from_elf(&file).unwrap();
+            assert_eq!(computer.pc(), 0x8000);
+            assert_eq!(computer.big_endian, big_endian);
+            assert_eq!(computer.disassemble(0x8014), Ok("b <done>".to_owned()));
+            run_until(&mut computer, 0x8014);
+            assert_eq!(computer.register_bits(RegisterBank::R1), 0x12345678);
+            assert_eq!(computer.mem.get32(0x20004, big_endian), Ok(0x12345678));
+            assert_eq!(computer.symbols.address_of("total"), Some(0x20004));
+        }
+
+        // An odd entry point starts in Thumb state.
+        let thumb = vec![0x01, 0x20, 0xfe, 0xe7];  // movs r0, #1; b .
+        let file = build_elf_with(false, 0, 0x8001, &[(".text", 1, 0x8000, thumb)], &[]);
+        let mut computer = Computer::from_elf(&file).unwrap();
+        assert_eq!(computer.pc(), 0x8000);
+        assert_eq!(computer.cpsr().active_instruction_set(), InstructionSet::Thumb);
+        run_until(&mut computer, 0x8002);
+        assert_eq!(computer.register_bits(RegisterBank::R0), 1);
+
+        let mut file = build_elf_with(false, 0, 0x8000, &[], &[]);
+        file[18] = 3;
+        assert_eq!(Computer::from_elf(&file).err(), Some(ElfError::Unsupported("not an ARM file")));
+        file[4] = 2;
+        assert_eq!(Computer::from_elf(&file).err(), Some(ElfError::Unsupported("not a 32-bit file")));
+    }
+
+    #[test]
+    fn load_hex_images() {
+        // mov r0, #42; b . at 0x8000, starting there.
+        let ihex = ":088000002A00A0E3FEFFFFEAE5\n:040000050000800077\n:00000001FF\n";
+        let srec = "S30D000080002A00A0E3FEFFFFEADF\nS705000080007A\n";
+        for computer in [Computer::from_ihex(ihex), Computer::from_srec(srec)].iter_mut() {
+            let computer = computer.as_mut().unwrap();
+            assert_eq!(computer.pc(), 0x8000);
+            run_until(computer, 0x8004);
+            assert_eq!(computer.register_bits(RegisterBank::R0), 42);
+        }
+
+        assert_eq!(Computer::from_srec("S30D000080002A00A0E3FEFFFFEAE0").err().unwrap().to_string(),
+                   "line 1: bad checksum");
+    }
+
+    #[test]
+    fn disassemble_with_symbols() {
+        let boot_code = assembler::assemble("
+            main:
+                bl uart_init
+                ldr r0, =message
+                b main
+            uart_init:
+                bx lr
+                .ltorg
+            message:
+                .word 0
+        ").unwrap();
+        let mut computer = Computer::new(boot_code);
+        assert_eq!(computer.disassemble(4), Ok("ldr r0, [pc, #4] @ 0x10 = 0x14".to_owned()));
+
+        computer.symbols.insert("main", 0x00, 0x0c);
+        computer.symbols.insert("uart_init", 0x0c, 4);
+        computer.symbols.insert("message", 0x14, 4);
+        assert_eq!(computer.disassemble(0), Ok("bl <uart_init>".to_owned()));
+        assert_eq!(computer.disassemble(4), Ok("ldr r0, [pc, #4] @ 0x10 = <message>".to_owned()));
+        assert_eq!(computer.disassemble(8), Ok("b <main>".to_owned()));
+        assert!(computer.disassemble(0x40000000).is_err());
+    }
+
+    #[test]
+    fn pc_reads_include_pipeline_offset() {
+        let ram = 0x80000000;
+        let mut computer = computer_with_program(&[
+            0xe28f0004,         // 0x00: add r0, pc, #4
+            0xe1a0100f,         // 0x04: mov r1, pc
+            0xe51f2004,         // 0x08: ldr r2, [pc, #-4]
+            0xe583f000,         // 0x0c: str pc, [r3]
+            0xe28ff000,         // 0x10: add pc, pc, #0
+            0xe3a04001,         // 0x14: mov r4, #1
+            0xe3a05001,         // 0x18: mov r5, #1
+        ]);
+        set_register(&mut computer, RegisterBank::R3, ram);
+        run_until(&mut computer, 0x1c);
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0x0c);
+        assert_eq!(computer.register_bits(RegisterBank::R1), 0x0c);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 0xe583f000);
+        assert_eq!(computer.mem.get32(ram as u64, false), Ok(0x14));
+        assert_eq!(computer.register_bits(RegisterBank::R4), 0);
+        assert_eq!(computer.register_bits(RegisterBank::R5), 1);
     }
 
     #[test]
     fn conditional_instructions() {
-        // TODO: Test combinations of `Condition` and CPSR values.
-        // Test both when condition is satisfied and not.
+        // Each row lists a condition and whether it passes for every
+        // NZCV combination, indexed by the flags read as a 4-bit
+        // number (N is the most significant bit).
+        let table = vec![
+            (Condition::EQ,    "0000111100001111"),
+            (Condition::NE,    "1111000011110000"),
+            (Condition::CS_HS, "0011001100110011"),
+            (Condition::CC_LO, "1100110011001100"),
+            (Condition::MI,    "0000000011111111"),
+            (Condition::PL,    "1111111100000000"),
+            (Condition::VS,    "0101010101010101"),
+            (Condition::VC,    "1010101010101010"),
+            (Condition::HI,    "0011000000110000"),
+            (Condition::LS,    "1100111111001111"),
+            (Condition::GE,    "1010101001010101"),
+            (Condition::LT,    "0101010110101010"),
+            (Condition::GT,    "1010000001010000"),
+            (Condition::LE,    "0101111110101111"),
+            (Condition::AL,    "1111111111111111"),
+        ];
+
+        let mut computer = Computer::new(vec![]);
+        for (cond, expected) in table {
+            for (nzcv, outcome) in expected.chars().enumerate() {
+                set_flags(&mut computer, nzcv as u32);
+                assert_eq!(computer.condition_satisfied(cond.clone()), outcome == '1',
+                           "{:?} with NZCV={:04b}", cond, nzcv);
+            }
+        }
+    }
+
+    #[test]
+    fn barrel_shifter() {
+        let r1 = 0x80000001u32;
+        // (operation, shift amount held in R2, carry in, result, carry out)
+        let cases = vec![
+            (BarrelShiftOp::Imm(0x1234), 0, true, 0x1234, true),
+            (BarrelShiftOp::Reg(RegisterBank::R1), 0, false, r1, false),
+            (BarrelShiftOp::RotateImmed { immed: 0xff, rotate: 0 }, 0, true, 0xff, true),
+            (BarrelShiftOp::RotateImmed { immed: 0x3f, rotate: 1 }, 0, false, 0xc000000f, true),
+            (BarrelShiftOp::RotateImmed { immed: 0x02, rotate: 1 }, 0, true, 0x80000000, true),
+            (BarrelShiftOp::RotateImmed { immed: 0x04, rotate: 1 }, 0, true, 0x00000001, false),
+
+            (BarrelShiftOp::LSL(RegisterBank::R1, ShiftSize::Imm(0)), 0, true, r1, true),
+            (BarrelShiftOp::LSL(RegisterBank::R1, ShiftSize::Imm(1)), 0, false, 0x00000002, true),
+            (BarrelShiftOp::LSL(RegisterBank::R1, ShiftSize::Imm(31)), 0, true, 0x80000000, false),
+            (BarrelShiftOp::LSL(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 0, true, r1, true),
+            (BarrelShiftOp::LSL(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 32, false, 0, true),
+            (BarrelShiftOp::LSL(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 33, true, 0, false),
+            (BarrelShiftOp::LSL(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 0x101, false, 0x00000002, true),
+
+            (BarrelShiftOp::LSR(RegisterBank::R1, ShiftSize::Imm(1)), 0, false, 0x40000000, true),
+            (BarrelShiftOp::LSR(RegisterBank::R1, ShiftSize::Imm(32)), 0, false, 0, true),
+            (BarrelShiftOp::LSR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 0, true, r1, true),
+            (BarrelShiftOp::LSR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 31, false, 1, false),
+            (BarrelShiftOp::LSR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 32, false, 0, true),
+            (BarrelShiftOp::LSR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 40, true, 0, false),
+
+            (BarrelShiftOp::ASR(RegisterBank::R1, ShiftSize::Imm(1)), 0, false, 0xc0000000, true),
+            (BarrelShiftOp::ASR(RegisterBank::R1, ShiftSize::Imm(32)), 0, false, 0xffffffff, true),
+            (BarrelShiftOp::ASR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 0, false, r1, false),
+            (BarrelShiftOp::ASR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 4, true, 0xf8000000, false),
+            (BarrelShiftOp::ASR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 200, false, 0xffffffff, true),
+
+            (BarrelShiftOp::ROR(RegisterBank::R1, ShiftSize::Imm(1)), 0, false, 0xc0000000, true),
+            (BarrelShiftOp::ROR(RegisterBank::R1, ShiftSize::Imm(4)), 0, true, 0x18000000, false),
+            (BarrelShiftOp::ROR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 0, true, r1, true),
+            (BarrelShiftOp::ROR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 32, false, r1, true),
+            (BarrelShiftOp::ROR(RegisterBank::R1, ShiftSize::Reg(RegisterBank::R2)), 33, false, 0xc0000000, true),
+
+            (BarrelShiftOp::RRX(RegisterBank::R1), 0, false, 0x40000000, true),
+            (BarrelShiftOp::RRX(RegisterBank::R1), 0, true, 0xc0000000, true),
+        ];
+
+        let mut computer = Computer::new(vec![]);
+        computer.cpu.register_file.lookup_mut(RegisterBank::R1).unwrap().bits = r1;
+        for (op, amount, carry_in, result, carry_out) in cases {
+            computer.cpu.register_file.lookup_mut(RegisterBank::R2).unwrap().bits = amount;
+            computer.cpu.register_file.lookup_mut(RegisterBank::CPSR).unwrap()
+                .set_condition_flag(ConditionFlag::Carry, carry_in);
+            assert_eq!(computer.execute_barrel_shift(&op), (result, carry_out),
+                       "{:?} by {} with C={}", op, amount, carry_in);
+        }
+    }
+
+    fn set_flags(computer: &mut Computer, nzcv: u32) {
+        let cpsr = computer.cpu.register_file.lookup_mut(RegisterBank::CPSR).unwrap();
+        cpsr.set_condition_flag(ConditionFlag::Negative, nzcv & 0b1000 != 0);
+        cpsr.set_condition_flag(ConditionFlag::Zero, nzcv & 0b0100 != 0);
+        cpsr.set_condition_flag(ConditionFlag::Carry, nzcv & 0b0010 != 0);
+        cpsr.set_condition_flag(ConditionFlag::Overflow, nzcv & 0b0001 != 0);
+    }
+
+    fn flags(computer: &Computer) -> u32 {
+        computer.cpu.register_file.cpsr().bits >> 28
+    }
+
+    #[test]
+    fn data_processing_flags() {
+        fn op2() -> BarrelShiftOp {
+            BarrelShiftOp::LSL(RegisterBank::R2, ShiftSize::Imm(0))
+        }
+        let (rd, rn) = (RegisterBank::R0, RegisterBank::R1);
+
+        // (instruction, R1, R2, NZCV before, R0 after, NZCV after)
+        let vectors = vec![
+            (CondInstr::ADD { s: true, rd: rd, rn: rn, shift_op: op2() }, 1, 2, 0b0000, 3, 0b0000),
+            (CondInstr::ADD { s: true, rd: rd, rn: rn, shift_op: op2() }, 0xffffffff, 1, 0b0000, 0, 0b0110),
+            (CondInstr::ADD { s: true, rd: rd, rn: rn, shift_op: op2() }, 0x7fffffff, 1, 0b0000, 0x80000000, 0b1001),
+            (CondInstr::ADD { s: true, rd: rd, rn: rn, shift_op: op2() }, 0x80000000, 0x80000000, 0b0000, 0, 0b0111),
+            (CondInstr::ADD { s: true, rd: rd, rn: rn, shift_op: op2() }, 0xfffffffe, 1, 0b0111, 0xffffffff, 0b1000),
+            (CondInstr::ADD { s: false, rd: rd, rn: rn, shift_op: op2() }, 0xffffffff, 1, 0b1001, 0, 0b1001),
+            (CondInstr::ADC { s: true, rd: rd, rn: rn, shift_op: op2() }, 0xffffffff, 0, 0b0010, 0, 0b0110),
+            (CondInstr::ADC { s: true, rd: rd, rn: rn, shift_op: op2() }, 0x7fffffff, 0, 0b0010, 0x80000000, 0b1001),
+            (CondInstr::ADC { s: true, rd: rd, rn: rn, shift_op: op2() }, 1, 1, 0b0000, 2, 0b0000),
+            (CondInstr::SUB { s: true, rd: rd, rn: rn, shift_op: op2() }, 5, 5, 0b0000, 0, 0b0110),
+            (CondInstr::SUB { s: true, rd: rd, rn: rn, shift_op: op2() }, 3, 5, 0b0000, 0xfffffffe, 0b1000),
+            (CondInstr::SUB { s: true, rd: rd, rn: rn, shift_op: op2() }, 5, 3, 0b0000, 2, 0b0010),
+            (CondInstr::SUB { s: true, rd: rd, rn: rn, shift_op: op2() }, 0x80000000, 1, 0b0000, 0x7fffffff, 0b0011),
+            (CondInstr::SUB { s: true, rd: rd, rn: rn, shift_op: op2() },
+             0x7fffffff, 0xffffffff, 0b0000, 0x80000000, 0b1001),
+            (CondInstr::SUB { s: true, rd: rd, rn: rn, shift_op: op2() }, 0, 0, 0b1001, 0, 0b0110),
+            (CondInstr::SBC { s: true, rd: rd, rn: rn, shift_op: op2() }, 5, 3, 0b0000, 1, 0b0010),
+            (CondInstr::SBC { s: true, rd: rd, rn: rn, shift_op: op2() }, 5, 3, 0b0010, 2, 0b0010),
+            (CondInstr::SBC { s: true, rd: rd, rn: rn, shift_op: op2() }, 0, 0, 0b0000, 0xffffffff, 0b1000),
+            (CondInstr::RSB { s: true, rd: rd, rn: rn, shift_op: op2() }, 1, 0, 0b0000, 0xffffffff, 0b1000),
+            (CondInstr::RSB { s: true, rd: rd, rn: rn, shift_op: op2() }, 3, 5, 0b0000, 2, 0b0010),
+            (CondInstr::RSC { s: true, rd: rd, rn: rn, shift_op: op2() }, 0, 0, 0b0000, 0xffffffff, 0b1000),
+            (CondInstr::RSC { s: true, rd: rd, rn: rn, shift_op: op2() }, 3, 5, 0b0010, 2, 0b0010),
+            (CondInstr::AND { s: true, rd: rd, rn: rn, shift_op: op2() }, 0xf0, 0x0f, 0b0011, 0, 0b0111),
+            (CondInstr::EOR { s: true, rd: rd, rn: rn, shift_op: op2() }, 0x80000000, 1, 0b0001, 0x80000001, 0b1001),
+            (CondInstr::ORR { s: true, rd: rd, rn: rn, shift_op: op2() }, 0, 0, 0b1010, 0, 0b0110),
+            (CondInstr::BIC { s: true, rd: rd, rn: rn, shift_op: op2() },
+             0xffffffff, 0x7fffffff, 0b0000, 0x80000000, 0b1000),
+            (CondInstr::MOV { s: true, rd: rd, shift_op: op2() }, 0, 0, 0b1011, 0, 0b0111),
+            (CondInstr::MOV { s: true, rd: rd, shift_op: BarrelShiftOp::LSL(RegisterBank::R2, ShiftSize::Imm(1)) },
+             0, 0x80000000, 0b0001, 0, 0b0111),
+            (CondInstr::MVN { s: true, rd: rd, shift_op: op2() }, 0, 0, 0b0000, 0xffffffff, 0b1000),
+        ];
+
+        let mut computer = Computer::new(vec![]);
+        for (instr, r1, r2, before, result, after) in vectors {
+            computer.cpu.register_file.lookup_mut(RegisterBank::R0).unwrap().bits = 0xdeadbeef;
+            computer.cpu.register_file.lookup_mut(RegisterBank::R1).unwrap().bits = r1;
+            computer.cpu.register_file.lookup_mut(RegisterBank::R2).unwrap().bits = r2;
+            set_flags(&mut computer, before);
+            computer.execute_conditional(&instr).unwrap();
+            assert_eq!(computer.register_bits(RegisterBank::R0), result,
+                       "{:?} {:#x}, {:#x}", instr, r1, r2);
+            assert_eq!(flags(&computer), after,
+                       "{:?} {:#x}, {:#x} flags", instr, r1, r2);
+        }
+
+        // (comparison, R1, R2, NZCV before, NZCV after)
+        let comparisons = vec![
+            (CondInstr::CMP { rn: rn, shift_op: op2() }, 5, 5, 0b0000, 0b0110),
+            (CondInstr::CMP { rn: rn, shift_op: op2() }, 3, 5, 0b0000, 0b1000),
+            (CondInstr::CMP { rn: rn, shift_op: op2() }, 0x80000000, 1, 0b0000, 0b0011),
+            (CondInstr::CMN { rn: rn, shift_op: op2() }, 0xffffffff, 1, 0b0000, 0b0110),
+            (CondInstr::CMN { rn: rn, shift_op: op2() }, 0x7fffffff, 1, 0b0000, 0b1001),
+            (CondInstr::TST { rn: rn, shift_op: op2() }, 0xf0, 0x0f, 0b0001, 0b0101),
+            (CondInstr::TST { rn: rn, shift_op: op2() }, 0x80000000, 0x80000000, 0b0000, 0b1000),
+            (CondInstr::TEQ { rn: rn, shift_op: op2() }, 0x12345678, 0x12345678, 0b1001, 0b0101),
+        ];
+
+        for (instr, r1, r2, before, after) in comparisons {
+            computer.cpu.register_file.lookup_mut(RegisterBank::R0).unwrap().bits = 0xdeadbeef;
+            computer.cpu.register_file.lookup_mut(RegisterBank::R1).unwrap().bits = r1;
+            computer.cpu.register_file.lookup_mut(RegisterBank::R2).unwrap().bits = r2;
+            set_flags(&mut computer, before);
+            computer.execute_conditional(&instr).unwrap();
+            assert_eq!(computer.register_bits(RegisterBank::R0), 0xdeadbeef);
+            assert_eq!(flags(&computer), after,
+                       "{:?} {:#x}, {:#x} flags", instr, r1, r2);
+        }
+    }
+
+    #[test]
+    fn flag_setting_write_to_pc_restores_cpsr() {
+        let mut computer = Computer::new(vec![]);
+        let user_cpsr = 0x10 | (0b1010 << 28);
+        computer.cpu.register_file.lookup_mut(RegisterBank::SPSR).unwrap().bits = user_cpsr;
+        computer.cpu.register_file.lookup_mut(RegisterBank::R14).unwrap().bits = 0x1000;
+
+        computer.execute(Instruction::Cond(
+            CondInstr::MOV {
+                s: true,
+                rd: RegisterBank::R15,
+                shift_op: BarrelShiftOp::LSL(RegisterBank::R14, ShiftSize::Imm(0)),
+            },
+            Condition::AL)).unwrap();
+
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::User);
+        assert_eq!(computer.cpu.register_file.cpsr().bits, user_cpsr);
+        assert_eq!(computer.pc(), 0x1000);
+    }
+
+    #[test]
+    fn mrs_spsr_in_user_mode_leaves_register() {
+        let mut computer = computer_with_program(&[
+            0xe3a00010,         // mov r0, #0x10
+            0xe121f000,         // msr cpsr_c, r0
+            0xe3a01005,         // mov r1, #5
+            0xe14f1000,         // mrs r1, spsr
+        ]);
+        run_until(&mut computer, 0x10);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::User);
+        assert_eq!(computer.register_bits(RegisterBank::R1), 5);
+    }
+
+    fn computer_with_program(words: &[u32]) -> Computer {
+        let mut boot_code = vec![];
+        for word in words {
+            for i in 0..4 {
+                boot_code.push((word >> (8 * i)) as u8);
+            }
+        }
+        Computer::new(boot_code)
+    }
+
+    fn run_until(computer: &mut Computer, pc: u32) {
+        for _ in 0..10000 {
+            if computer.pc() == pc {
+                return;
+            }
+            computer.execute_next_instruction().unwrap();
+        }
+        panic!("never reached {:#x}", pc);
+    }
+
+    #[test]
+    fn factorial() {
+        let mut computer = computer_with_program(&[
+            0xe3a00005,         //         mov r0, #5
+            0xe3a01001,         //         mov r1, #1
+            0xe0020190,         // loop:   mul r2, r0, r1
+            0xe1a01002,         //         mov r1, r2
+            0xe2500001,         //         subs r0, r0, #1
+            0x1afffffb,         //         bne loop
+        ]);
+        run_until(&mut computer, 0x18);
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0);
+        assert_eq!(computer.register_bits(RegisterBank::R1), 120);
+    }
+
+    #[test]
+    fn multiplies() {
+        fn regs(computer: &mut Computer, values: &[u32]) {
+            for (i, &val) in values.iter().enumerate() {
+                let bank = [RegisterBank::R0, RegisterBank::R1, RegisterBank::R2, RegisterBank::R3][i];
+                computer.cpu.register_file.lookup_mut(bank).unwrap().bits = val;
+            }
+        }
+        let long = |s, f: fn(bool, RegisterBank, RegisterBank, RegisterBank, RegisterBank) -> CondInstr| {
+            f(s, RegisterBank::R0, RegisterBank::R1, RegisterBank::R2, RegisterBank::R3)
+        };
+        fn umull(s: bool, rdlo: RegisterBank, rdhi: RegisterBank, rm: RegisterBank, rs: RegisterBank) -> CondInstr {
+            CondInstr::UMULL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs }
+        }
+        fn umlal(s: bool, rdlo: RegisterBank, rdhi: RegisterBank, rm: RegisterBank, rs: RegisterBank) -> CondInstr {
+            CondInstr::UMLAL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs }
+        }
+        fn smull(s: bool, rdlo: RegisterBank, rdhi: RegisterBank, rm: RegisterBank, rs: RegisterBank) -> CondInstr {
+            CondInstr::SMULL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs }
+        }
+        fn smlal(s: bool, rdlo: RegisterBank, rdhi: RegisterBank, rm: RegisterBank, rs: RegisterBank) -> CondInstr {
+            CondInstr::SMLAL { s: s, rdlo: rdlo, rdhi: rdhi, rm: rm, rs: rs }
+        }
+
+        // (instruction, R0-R3 before, R0 and R1 after, NZCV before, NZCV after)
+        let cases = vec![
+            (CondInstr::MUL { s: true, rd: RegisterBank::R0, rm: RegisterBank::R2, rs: RegisterBank::R3 },
+             [0, 0, 0x10000, 0x10000], [0, 0], 0b0011, 0b0111),
+            (CondInstr::MUL { s: false, rd: RegisterBank::R0, rm: RegisterBank::R2, rs: RegisterBank::R3 },
+             [0, 0, 0xffffffff, 2], [0xfffffffe, 0], 0b0000, 0b0000),
+            (CondInstr::MLA {
+                s: true,
+                rd: RegisterBank::R0,
+                rm: RegisterBank::R2,
+                rs: RegisterBank::R3,
+                rn: RegisterBank::R1,
+            },
+             [0, 0x80000000, 3, 4], [0x8000000c, 0x80000000], 0b0000, 0b1000),
+            (long(false, umull), [0, 0, 0xffffffff, 0xffffffff], [0x00000001, 0xfffffffe], 0b0000, 0b0000),
+            (long(true, umull), [0, 0, 0, 0xffffffff], [0, 0], 0b0001, 0b0101),
+            (long(true, umlal), [1, 2, 0x80000000, 2], [1, 3], 0b0000, 0b0000),
+            (long(true, smull), [0, 0, 0xffffffff, 2], [0xfffffffe, 0xffffffff], 0b0000, 0b1000),
+            (long(false, smull), [0, 0, 0x80000000, 0x80000000], [0, 0x40000000], 0b0000, 0b0000),
+            (long(true, smlal), [5, 0, 0xffffffff, 5], [0, 0], 0b0010, 0b0110),
+        ];
+
+        let mut computer = Computer::new(vec![]);
+        for (instr, before, after, flags_before, flags_after) in cases {
+            regs(&mut computer, &before);
+            set_flags(&mut computer, flags_before);
+            computer.execute_conditional(&instr).unwrap();
+            assert_eq!([computer.register_bits(RegisterBank::R0),
+                        computer.register_bits(RegisterBank::R1)],
+                       after, "{:?}", instr);
+            assert_eq!(flags(&computer), flags_after, "{:?} flags", instr);
+        }
+    }
+
+    fn execute_word(computer: &mut Computer, word: u32) {
+        let instr = computer.cpu.decode_instruction(word).unwrap();
+        computer.execute(instr).unwrap();
+    }
+
+    fn set_register(computer: &mut Computer, reg_bank: RegisterBank, bits: u32) {
+        computer.cpu.register_file.lookup_mut(reg_bank).unwrap().bits = bits;
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        let ram = 0x80000000;
+        let mut computer = Computer::new(vec![]);
+        set_register(&mut computer, RegisterBank::R1, ram + 0x10);
+        set_register(&mut computer, RegisterBank::R2, 0xcafef00d);
+
+        execute_word(&mut computer, 0xe5a12004); // str r2, [r1, #4]!
+        assert_eq!(computer.mem.get32(ram as u64 + 0x14, false), Ok(0xcafef00d));
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 0x14);
+
+        execute_word(&mut computer, 0xe4110004); // ldr r0, [r1], #-4
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0xcafef00d);
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 0x10);
+
+        execute_word(&mut computer, 0xe5910005); // ldr r0, [r1, #5]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0x0dcafef0);
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 0x10);
+
+        set_register(&mut computer, RegisterBank::R3, 1);
+        execute_word(&mut computer, 0xe7d10103); // ldrb r0, [r1, r3, lsl #2]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0x0d);
+
+        execute_word(&mut computer, 0xe7612103); // strb r2, [r1, -r3, lsl #2]!
+        assert_eq!(computer.mem.get8(ram as u64 + 0x0c), Ok(0x0d));
+        assert_eq!(computer.mem.get32(ram as u64 + 0x0c, false), Ok(0x0000000d));
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 0x0c);
+
+        execute_word(&mut computer, 0xe6e12003); // strbt r2, [r1], r3
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 0x0d);
+        execute_word(&mut computer, 0xe4f10003); // ldrbt r0, [r1], #3
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0);
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 0x10);
+
+        execute_word(&mut computer, 0xe7813062); // str r3, [r1, r2, rrx]
+        // The unaligned address 0xe57f7816 stores to the word at 0xe57f7814.
+        assert_eq!(computer.mem.get32(0xe57f7814, false), Ok(1));
+    }
+
+    #[test]
+    fn halfword_signed_and_doubleword_transfers() {
+        let ram = 0x80000000;
+        let mut computer = Computer::new(vec![]);
+        computer.mem.put32(ram as u64, 0x8081f0f1, false).unwrap();
+        set_register(&mut computer, RegisterBank::R1, ram);
+
+        execute_word(&mut computer, 0xe1d100b2); // ldrh r0, [r1, #2]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0x8081);
+        execute_word(&mut computer, 0xe1d100f2); // ldrsh r0, [r1, #2]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0xffff8081);
+        execute_word(&mut computer, 0xe1d100d1); // ldrsb r0, [r1, #1]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0xfffffff0);
+        execute_word(&mut computer, 0xe1d100d3); // ldrsb r0, [r1, #3]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0xffffff80);
+        execute_word(&mut computer, 0xe1d100f0); // ldrsh r0, [r1]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0xfffff0f1);
+
+        set_register(&mut computer, RegisterBank::R2, 0x12347fff);
+        execute_word(&mut computer, 0xe0c120b6); // strh r2, [r1], #6
+        assert_eq!(computer.mem.get32(ram as u64, false), Ok(0x80817fff));
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 6);
+        set_register(&mut computer, RegisterBank::R3, 2);
+        execute_word(&mut computer, 0xe13100f3); // ldrsh r0, [r1, -r3]!
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0);
+        assert_eq!(computer.register_bits(RegisterBank::R1), ram + 4);
+        execute_word(&mut computer, 0xe15100f4); // ldrsh r0, [r1, #-4]
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0x7fff);
+
+        set_register(&mut computer, RegisterBank::R4, 0x11111111);
+        set_register(&mut computer, RegisterBank::R5, 0x22222222);
+        set_register(&mut computer, RegisterBank::R13, ram + 0x100);
+        execute_word(&mut computer, 0xe16d40f8); // strd r4, r5, [sp, #-8]!
+        assert_eq!(computer.register_bits(RegisterBank::R13), ram + 0xf8);
+        assert_eq!(computer.mem.get32(ram as u64 + 0xf8, false), Ok(0x11111111));
+        assert_eq!(computer.mem.get32(ram as u64 + 0xfc, false), Ok(0x22222222));
+        execute_word(&mut computer, 0xe0cd60d8); // ldrd r6, r7, [sp], #8
+        assert_eq!(computer.register_bits(RegisterBank::R6), 0x11111111);
+        assert_eq!(computer.register_bits(RegisterBank::R7), 0x22222222);
+        assert_eq!(computer.register_bits(RegisterBank::R13), ram + 0x100);
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let ram = 0x80000000;
+        let mut computer = computer_with_program(&[
+            0xe92d4ff0,         // push {r4-r11, lr}
+            0xe8bd8ff0,         // pop {r4-r11, pc}
+        ]);
+        let regs = [RegisterBank::R4, RegisterBank::R5, RegisterBank::R6, RegisterBank::R7,
+                    RegisterBank::R8, RegisterBank::R9, RegisterBank::R10, RegisterBank::R11];
+        for (i, &reg) in regs.iter().enumerate() {
+            set_register(&mut computer, reg, 0x100 + i as u32);
+        }
+        set_register(&mut computer, RegisterBank::R14, 0x40);
+        set_register(&mut computer, RegisterBank::R13, ram + 0x100);
+
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.register_bits(RegisterBank::R13), ram + 0x100 - 36);
+        assert_eq!(computer.mem.get32(ram as u64 + 0x100 - 36, false), Ok(0x100));
+        assert_eq!(computer.mem.get32(ram as u64 + 0x100 - 4, false), Ok(0x40));
+
+        for &reg in regs.iter() {
+            set_register(&mut computer, reg, 0);
+        }
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.register_bits(RegisterBank::R13), ram + 0x100);
+        assert_eq!(computer.pc(), 0x40);
+        for (i, &reg) in regs.iter().enumerate() {
+            assert_eq!(computer.register_bits(reg), 0x100 + i as u32);
+        }
+    }
+
+    #[test]
+    fn block_transfer_addressing_modes() {
+        let ram = 0x80000000;
+        let mut computer = Computer::new(vec![]);
+        for i in 0..8 {
+            computer.mem.put32(ram as u64 + 4 * i, i as u32, false).unwrap();
+        }
+        set_register(&mut computer, RegisterBank::R0, ram + 0x10);
+
+        execute_word(&mut computer, 0xe9900006); // ldmib r0, {r1, r2}
+        assert_eq!(computer.register_bits(RegisterBank::R1), 5);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 6);
+        assert_eq!(computer.register_bits(RegisterBank::R0), ram + 0x10);
+
+        execute_word(&mut computer, 0xe8100006); // ldmda r0, {r1, r2}
+        assert_eq!(computer.register_bits(RegisterBank::R1), 3);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 4);
+
+        execute_word(&mut computer, 0xe8b00006); // ldmia r0!, {r1, r2}
+        assert_eq!(computer.register_bits(RegisterBank::R1), 4);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 5);
+        assert_eq!(computer.register_bits(RegisterBank::R0), ram + 0x18);
+
+        execute_word(&mut computer, 0xe820000a); // stmda r0!, {r1, r3}
+        assert_eq!(computer.mem.get32(ram as u64 + 0x14, false), Ok(4));
+        assert_eq!(computer.mem.get32(ram as u64 + 0x18, false), Ok(0));
+        assert_eq!(computer.register_bits(RegisterBank::R0), ram + 0x10);
+
+        execute_word(&mut computer, 0xe9a00006); // stmib r0!, {r1, r2}
+        assert_eq!(computer.mem.get32(ram as u64 + 0x14, false), Ok(4));
+        assert_eq!(computer.mem.get32(ram as u64 + 0x18, false), Ok(5));
+        assert_eq!(computer.register_bits(RegisterBank::R0), ram + 0x18);
+
+        execute_word(&mut computer, 0xe9300006); // ldmdb r0!, {r1, r2}
+        assert_eq!(computer.register_bits(RegisterBank::R1), 4);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 4);
+        assert_eq!(computer.register_bits(RegisterBank::R0), ram + 0x10);
+    }
+
+    #[test]
+    fn user_bank_block_transfers() {
+        let ram = 0x80000000;
+        let mut computer = Computer::new(vec![]);
+        set_register(&mut computer, RegisterBank::R8, 0x88);
+        computer.cpu.register_file.lookup_mut(RegisterBank::CPSR).unwrap()
+            .set_mode(ProcessorMode::FastInterruptRequest);
+        set_register(&mut computer, RegisterBank::R8, 0xf8);
+        set_register(&mut computer, RegisterBank::R0, ram);
+
+        execute_word(&mut computer, 0xe8c00100); // stmia r0, {r8}^
+        assert_eq!(computer.mem.get32(ram as u64, false), Ok(0x88));
+
+        computer.mem.put32(ram as u64, 0x99, false).unwrap();
+        execute_word(&mut computer, 0xe8d00100); // ldmia r0, {r8}^
+        assert_eq!(computer.register_bits(RegisterBank::R8), 0xf8);
+        assert_eq!(computer.cpu.register_file.lookup_banked(RegisterBank::R8, ProcessorMode::User)
+                           .unwrap().bits, 0x99);
+
+        // Returning with the PC in the list restores the CPSR.
+        let user_cpsr = 0x10 | (0b0100 << 28);
+        set_register(&mut computer, RegisterBank::SPSR, user_cpsr);
+        computer.mem.put32(ram as u64, 0x1234, false).unwrap();
+        computer.mem.put32(ram as u64 + 4, 0x2000, false).unwrap();
+        set_register(&mut computer, RegisterBank::R13, ram);
+        execute_word(&mut computer, 0xe8fd8100); // ldmia sp!, {r8, pc}^
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::User);
+        assert_eq!(computer.cpu.register_file.cpsr().bits, user_cpsr);
+        assert_eq!(computer.register_bits(RegisterBank::R8), 0x99);
+        assert_eq!(computer.cpu.register_file.lookup_banked(RegisterBank::R8, ProcessorMode::FastInterruptRequest)
+                           .unwrap().bits, 0x1234);
+        assert_eq!(computer.pc(), 0x2000);
+    }
+
+    #[test]
+    fn software_interrupt_and_irq() {
+        let mut computer = computer_with_program(&[
+            0xea000006,         // 0x00:        b start
+            0xe1b0f00e,         // 0x04:        movs pc, lr
+            0xea000009,         // 0x08:        b swi_handler
+            0xe25ef004,         // 0x0c:        subs pc, lr, #4
+            0xe25ef008,         // 0x10:        subs pc, lr, #8
+            0xe1a00000,         // 0x14:        nop
+            0xea000009,         // 0x18:        b irq_handler
+            0xe1a00000,         // 0x1c:        nop
+            0xe3a00010,         // start:       mov r0, #0x10
+            0xe121f000,         //              msr cpsr_c, r0
+            0xef00002a,         //              swi #42
+            0xe2811001,         //              add r1, r1, #1
+            0xeafffffe,         // idle:        b idle
+            0xe51e2004,         // swi_handler: ldr r2, [lr, #-4]
+            0xe3c224ff,         //              bic r2, r2, #0xff000000
+            0xe14f3000,         //              mrs r3, spsr
+            0xe1b0f00e,         //              movs pc, lr
+            0xe3a04007,         // irq_handler: mov r4, #7
+            0xe25ef004,         //              subs pc, lr, #4
+        ]);
+        run_until(&mut computer, 0x28);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::User);
+
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x08);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::Supervisor);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x2c);
+        assert!(!computer.cpsr().permit_interrupt(InterruptMask::IRQ));
+
+        run_until(&mut computer, 0x30);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::User);
+        assert_eq!(computer.register_bits(RegisterBank::R1), 1);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 42);
+        assert_eq!(computer.register_bits(RegisterBank::R3), 0x10);
+
+        computer.irq_asserted = true;
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x18);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::InterruptRequest);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x34);
+        computer.irq_asserted = false;
+
+        run_until(&mut computer, 0x30);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::User);
+        assert_eq!(computer.register_bits(RegisterBank::R4), 7);
+        assert!(computer.cpsr().permit_interrupt(InterruptMask::IRQ));
+    }
+
+    #[test]
+    fn exception_vectors() {
+        let mut computer = Computer::new(vec![]);
+        computer.high_vectors = true;
+        execute_word(&mut computer, 0xef000000); // swi #0
+        assert_eq!(computer.pc(), 0xffff0008);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x4);
+
+        // Masked interrupts wait until they're enabled.
+        computer.fiq_asserted = true;
+        computer.cpu.register_file.lookup_mut(RegisterBank::CPSR).unwrap()
+            .set_interrupt_mask(InterruptMask::FIQ, true);
+        computer.high_vectors = false;
+        computer.raise_exception(Exception::DataAbort);
+        assert_eq!(computer.pc(), 0x10);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::Abort);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0xffff0010);
+
+        computer.cpu.register_file.lookup_mut(RegisterBank::CPSR).unwrap()
+            .set_interrupt_mask(InterruptMask::FIQ, false);
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x1c);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::FastInterruptRequest);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x14);
+        assert!(!computer.cpsr().permit_interrupt(InterruptMask::FIQ));
+    }
+
+    #[test]
+    fn undefined_instructions_trap() {
+        let mut computer = computer_with_program(&[
+            0xea000007,         // 0x00:        b start
+            0xea000009,         // 0x04:        b undef_handler
+            0xe1a00000,         // 0x08:        nop
+            0xe25ef004,         // 0x0c:        subs pc, lr, #4
+            0xe1a00000,         // 0x10:        nop
+            0xe1a00000,         // 0x14:        nop
+            0xe1a00000,         // 0x18:        nop
+            0xe1a00000,         // 0x1c:        nop
+            0xe1a00000,         // 0x20:        nop
+            0xe7f000f0,         // start:       udf #0
+            0xee010f10,         //              mcr p15, #0, r0, c1, c0, #0
+            0xeafffffe,         // idle:        b idle
+            0xe2855001,         // undef_handler: add r5, r5, #1
+            0xe1b0f00e,         //              movs pc, lr
+        ]);
+        run_until(&mut computer, 0x2c);
+        assert_eq!(computer.register_bits(RegisterBank::R5), 2);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::Supervisor);
+
+        // Fetching from unmapped memory is a prefetch abort.
+        set_register(&mut computer, RegisterBank::R15, 0x40000000);
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x0c);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::Abort);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x40000004);
+    }
+
+    /// A transmit-only UART: writing the data register sends a byte,
+    /// and the status register reads as ready.
+    struct Uart {
+        sent: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Device for Uart {
+        fn size(&self) -> CellCount {
+            8
+        }
+
+        fn read(&mut self, offset: u32, _size: AccessSize) -> Option<u32> {
+            match offset {
+                4 => Some(1),
+                _ => None,
+            }
+        }
+
+        fn write(&mut self, offset: u32, _size: AccessSize, value: u32) -> Option<()> {
+            match offset {
+                0 => {
+                    self.sent.borrow_mut().push(value as u8);
+                    Some(())
+                },
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn memory_mapped_devices() {
+        let boot_code = assembler::assemble("
+                ldr r0, =0x40000000
+                adr r1, message
+            next:
+                ldr r3, [r0, #4]        @ wait until ready
+                tst r3, #1
+                beq next
+                ldrb r2, [r1], #1
+                cmp r2, #0
+                strbne r2, [r0]
+                bne next
+            done:
+                b done
+            message:
+                .word 0x0a6968
+        ").unwrap();
+        let sent = Rc::new(RefCell::new(vec![]));
+        let mut computer = Computer::new(boot_code);
+        assert!(computer.mem.attach_device(0x40000000, Box::new(Uart { sent: sent.clone() })));
+        run_until(&mut computer, 0x24);
+        assert_eq!(*sent.borrow(), b"hi\n".to_vec());
+
+        // The UART has no data to read.
+        set_register(&mut computer, RegisterBank::R3, 0x40000000);
+        let ldr = computer.cpu.decode_instruction(0xe5934000).unwrap();  // ldr r4, [r3]
+        assert_eq!(computer.execute(ldr), Err(EmulationError::BusError { addr: 0x40000000, opcode: None }));
+    }
+
+    #[test]
+    fn stop_and_report_errors() {
+        let ram = 0x80000000;
+        let mut computer = computer_with_program(&[
+            0xe7f000f0,         // udf #0
+            0xee010f10,         // mcr p15, #0, r0, c1, c0, #0
+            0xe5901000,         // ldr r1, [r0]
+            0xe5821000,         // str r1, [r2]
+            0xe1c340d0,         // ldrd r4, r5, [r3]
+            0xe121f006,         // msr cpsr_c, r6
+        ]);
+        computer.fault_policy = FaultPolicy::Stop;
+        set_register(&mut computer, RegisterBank::R0, 0x40000000);
+        set_register(&mut computer, RegisterBank::R2, 0x8);
+        set_register(&mut computer, RegisterBank::R3, ram + 4);
+
+        let expected = vec![
+            EmulationError::Undecodable { addr: 0, opcode: Some(0xe7f000f0) },
+            EmulationError::Unimplemented { addr: 4, opcode: Some(0xee010f10) },
+            EmulationError::UnmappedAccess { addr: 0x40000000, opcode: Some(0xe5901000) },
+            EmulationError::ReadOnlyWrite { addr: 0x8, opcode: Some(0xe5821000) },
+            EmulationError::Misaligned { addr: ram as u64 + 4, opcode: Some(0xe1c340d0) },
+            EmulationError::InvalidMode { addr: 0x14, opcode: Some(0xe121f006), mode: 0 },
+        ];
+        for (i, err) in expected.into_iter().enumerate() {
+            let pc = 4 * i as u32;
+            set_register(&mut computer, RegisterBank::R15, pc);
+            assert_eq!(computer.execute_next_instruction(), Err(err));
+            // Nothing changes, so the host can fix things up and retry.
+            assert_eq!(computer.pc(), pc);
+            assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::Supervisor);
+        }
+
+        set_register(&mut computer, RegisterBank::R15, 0x40000000);
+        assert_eq!(computer.execute_next_instruction(),
+                   Err(EmulationError::UnmappedAccess { addr: 0x40000000, opcode: None }));
+        set_register(&mut computer, RegisterBank::R15, 2);
+        assert_eq!(computer.execute_next_instruction(),
+                   Err(EmulationError::Misaligned { addr: 2, opcode: None }));
+    }
+
+    #[test]
+    fn unmapped_low_memory_catches_wild_pointers() {
+        let boot_code = assembler::assemble("
+                ldr r0, =0x00100000
+                ldr r1, [r0]
+        ").unwrap();
+        let mut computer = Computer::with_memory(MemMap32::with_low_memory(boot_code, LowMemory::Unmapped));
+        computer.fault_policy = FaultPolicy::Stop;
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.execute_next_instruction(),
+                   Err(EmulationError::UnmappedAccess { addr: 0x00100000, opcode: Some(0xe5901000) }));
+    }
+
+    #[test]
+    fn data_aborts_trap() {
+        let mut computer = computer_with_program(&[
+            0xe5901000,         // ldr r1, [r0]
+        ]);
+        set_register(&mut computer, RegisterBank::R0, 0x40000000);
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x10);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::Abort);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x8);
+    }
+
+    #[test]
+    fn load_into_pc() {
+        let ram = 0x80000000;
+        let mut computer = Computer::new(vec![]);
+        computer.mem.put32(ram as u64, 0x00001234, false).unwrap();
+        set_register(&mut computer, RegisterBank::R13, ram);
+
+        execute_word(&mut computer, 0xe49df004); // ldr pc, [sp], #4
+        assert_eq!(computer.pc(), 0x1234 & !3);
+        assert_eq!(computer.register_bits(RegisterBank::R13), ram + 4);
+    }
+
+    #[test]
+    fn thumb_interworking() {
+        let mut computer = computer_with_program(&[
+            0xea000002,         // 0x00:        b start
+            0xe1a00000,         // 0x04:        nop
+            0xea000002,         // 0x08:        b swi_handler
+            0xe1a00000,         // 0x0c:        nop
+            0xe28f0015,         // start:       adr r0, thumb_code + 1
+            0xe12fff10,         //              bx r0
+            0xe14f5000,         // swi_handler: mrs r5, spsr
+            0xe55e6002,         //              ldrb r6, [lr, #-2]
+            0xe1b0f00e,         //              movs pc, lr
+            0xe2877001,         // arm_func:    add r7, r7, #1
+            0xe12fff1e,         //              bx lr
+            0x22002105,         // thumb_code:  movs r1, #5; movs r2, #0
+            0x39011852,         // loop:        adds r2, r1; subs r1, #1
+            0xf000d1fc,         //              bne loop; bl thumb_func
+            0xf7fff805,         //              ...; blx arm_func
+            0xdf07eff4,         //              ...; svc #7
+            0x46c0e7fe,         // idle:        b idle; nop
+            0x4b01b510,         // thumb_func:  push {r4, lr}; ldr r3, =0x12345678
+            0xbd10a400,         //              adr r4, literals; pop {r4, pc}
+            0x12345678,         // literals:
+        ]);
+        set_register(&mut computer, RegisterBank::R13, 0x80001000);
+
+        run_until(&mut computer, 0x2c);
+        assert_eq!(computer.cpsr().active_instruction_set(), InstructionSet::Thumb);
+        assert_eq!(computer.instruction_size(0x34), 2);
+        assert_eq!(computer.instruction_size(0x36), 4);
+        assert_eq!(computer.instruction_size(0x40000000), 2);
+
+        run_until(&mut computer, 0x44);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 15);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x3b);
+
+        run_until(&mut computer, 0x4a);
+        assert_eq!(computer.register_bits(RegisterBank::R3), 0x12345678);
+        assert_eq!(computer.register_bits(RegisterBank::R4), 0x4c);
+
+        run_until(&mut computer, 0x24);
+        assert_eq!(computer.cpsr().active_instruction_set(), InstructionSet::ARM);
+        assert_eq!(computer.register_bits(RegisterBank::R4), 0);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x3f);
+
+        run_until(&mut computer, 0x3e);
+        assert_eq!(computer.cpsr().active_instruction_set(), InstructionSet::Thumb);
+        assert_eq!(computer.register_bits(RegisterBank::R7), 1);
+
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x08);
+        assert_eq!(computer.cpsr().active_instruction_set(), InstructionSet::ARM);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x40);
+
+        run_until(&mut computer, 0x40);
+        assert_eq!(computer.cpsr().active_instruction_set(), InstructionSet::Thumb);
+        assert_eq!(computer.register_bits(RegisterBank::R5) & 0x20, 0x20);
+        assert_eq!(computer.register_bits(RegisterBank::R6), 7);
+        assert_eq!(computer.register_bits(RegisterBank::R13), 0x80001000);
+    }
+
+    #[test]
+    fn thumb_breakpoint() {
+        let mut computer = computer_with_program(&[
+            0xe28f0001,         // 0x00: adr r0, thumb_code + 1
+            0xe12fff10,         //       bx r0
+            0xbe122101,         // thumb_code: movs r1, #1; bkpt #0x12
+        ]);
+        run_until(&mut computer, 0x0a);
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x0c);
+        assert_eq!(computer.cpu.register_file.mode(), ProcessorMode::Abort);
+        assert_eq!(computer.cpsr().active_instruction_set(), InstructionSet::ARM);
+        assert_eq!(computer.register_bits(RegisterBank::R14), 0x0e);
+        assert_eq!(computer.register_bits(RegisterBank::R1), 1);
+    }
+
+    #[test]
+    fn thumb2_it_blocks_and_table_branches() {
+        let mut computer = computer_with_program(&[
+            0xe28f0001,         // 0x00:        adr r0, code + 1
+            0xe12fff10,         //              bx r0
+            0xbf144280,         // code:        cmp r0, r0; ite ne
+            0x21022101,         //              movne r1, #1; moveq r1, #2
+            0x62eff64b,         //              movw r2, #0xbeef
+            0x62adf6cd,         //              movt r2, #0xdead
+            0x0300f04f,         //              mov.w r3, #0
+            0x130bf362,         //              bfi r3, r2, #4, #8
+            0x440bf3c2,         //              ubfx r4, r2, #16, #12
+            0x0520f20f,         //              adr.w r5, pair
+            0x6700e9d5,         //              ldrd r6, r7, [r5]
+            0xe8df2002,         //              movs r0, #2; tbb [pc, r0]
+            0x0302f000,         //              ...; table: .byte 2, 3, 4, 0
+            0xe0030004,         //              ...; case0: b idle
+            0xb108e002,         // case1:       b idle; case2: cbz r0, idle
+            0xb1002000,         //              movs r0, #0; cbz r0, done
+            0x2001e7fe,         // idle:        b idle; done: movs r0, #1
+            0xbf00e7fd,         //              b done; nop
+            0x11111111,         // pair:
+            0x22222222,
+        ]);
+
+        run_until(&mut computer, 0x0c);
+        assert_eq!(computer.cpsr().it_state(), 0b0001_0100);
+
+        // Inside the IT block the 16-bit MOVs leave the flags alone.
+        run_until(&mut computer, 0x10);
+        assert_eq!(computer.cpsr().it_state(), 0);
+        assert_eq!(computer.register_bits(RegisterBank::R1), 2);
+        assert!(computer.cpsr().is_condition_flag_on(ConditionFlag::Zero));
+        assert!(computer.cpsr().is_condition_flag_on(ConditionFlag::Carry));
+
+        run_until(&mut computer, 0x2c);
+        assert_eq!(computer.register_bits(RegisterBank::R2), 0xdeadbeef);
+        assert_eq!(computer.register_bits(RegisterBank::R3), 0xef0);
+        assert_eq!(computer.register_bits(RegisterBank::R4), 0xead);
+        assert_eq!(computer.register_bits(RegisterBank::R5), 0x48);
+        assert_eq!(computer.register_bits(RegisterBank::R6), 0x11111111);
+        assert_eq!(computer.register_bits(RegisterBank::R7), 0x22222222);
+
+        // The table branch skips to case2, whose first CBZ falls through.
+        computer.execute_next_instruction().unwrap();
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x3a);
+        computer.execute_next_instruction().unwrap();
+        assert_eq!(computer.pc(), 0x3c);
+        run_until(&mut computer, 0x42);
+        assert_eq!(computer.register_bits(RegisterBank::R0), 0);
     }
 
     // TODO: verify that a new RegisterFile starts in supervisor mode
@@ -0,0 +1,493 @@
+//! Reading 32-bit ARM ELF files, as produced by GNU and LLVM
+//! toolchains for bare-metal targets.
+
+use std::error::Error;
+use std::fmt;
+
+/// Why an ELF file couldn't be read.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ElfError {
+    /// The file doesn't start with the ELF magic number.
+    NotElf,
+
+    /// A valid ELF file, but not one for 32-bit ARM.
+    Unsupported(&'static str),
+
+    /// A header or table runs past the end of the file, or is
+    /// otherwise inconsistent.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ElfError::NotElf => write!(f, "not an ELF file"),
+            ElfError::Unsupported(what) => write!(f, "unsupported ELF file: {}", what),
+            ElfError::Malformed(what) => write!(f, "malformed ELF file: {}", what),
+        }
+    }
+}
+
+impl Error for ElfError {}
+
+pub const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// Set in the header flags of a big-endian image whose instructions
+/// are little-endian, as ARMv6 and later cores fetch them.
+pub const EF_ARM_BE8: u32 = 0x00800000;
+
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+
+const EM_ARM: u16 = 40;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProgramHeader {
+    /// One of the `PT_` constants.
+    pub kind: u32,
+    pub offset: u32,
+    pub vaddr: u32,
+    /// Where the segment is loaded, which differs from `vaddr` for
+    /// initialized data that startup code copies from ROM to RAM.
+    pub paddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+    /// `PF_` bits.
+    pub flags: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub link: u32,
+    pub entsize: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+    pub size: u32,
+    /// One of the `STT_` constants.
+    pub kind: u8,
+    /// Index of the section the symbol is defined in, or 0 if it's
+    /// undefined.
+    pub section: u16,
+}
+
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+    pub entry: u32,
+    pub flags: u32,
+    program_offset: u32,
+    program_count: u16,
+    program_size: u16,
+    section_offset: u32,
+    section_count: u16,
+    section_size: u16,
+    section_names: u16,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Check the ELF header of a file.
+    pub fn parse(data: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        if data.len() < 4 || &data[..4] != b"\x7fELF" {
+            return Err(ElfError::NotElf);
+        }
+        if data.len() < 52 {
+            return Err(ElfError::Malformed("truncated header"));
+        }
+        if data[4] != 1 {
+            return Err(ElfError::Unsupported("not a 32-bit file"));
+        }
+        let big_endian = match data[5] {
+            1 => false,
+            2 => true,
+            _ => return Err(ElfError::Malformed("unknown byte order")),
+        };
+        let mut file = ElfFile {
+            data: data,
+            big_endian: big_endian,
+            entry: 0,
+            flags: 0,
+            program_offset: 0,
+            program_count: 0,
+            program_size: 0,
+            section_offset: 0,
+            section_count: 0,
+            section_size: 0,
+            section_names: 0,
+        };
+        if file.half(18)? != EM_ARM {
+            return Err(ElfError::Unsupported("not an ARM file"));
+        }
+        file.entry = file.word(24)?;
+        file.program_offset = file.word(28)?;
+        file.section_offset = file.word(32)?;
+        file.flags = file.word(36)?;
+        file.program_size = file.half(42)?;
+        file.program_count = file.half(44)?;
+        file.section_size = file.half(46)?;
+        file.section_count = file.half(48)?;
+        file.section_names = file.half(50)?;
+        if file.program_count > 0 && file.program_size < 32 {
+            return Err(ElfError::Malformed("program headers are too small"));
+        }
+        if file.section_count > 0 && file.section_size < 40 {
+            return Err(ElfError::Malformed("section headers are too small"));
+        }
+        Ok(file)
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    /// Whether a big-endian image keeps its instructions
+    /// little-endian.
+    pub fn is_be8(&self) -> bool {
+        self.big_endian && self.flags & EF_ARM_BE8 != 0
+    }
+
+    /// Offsets are 64-bit so that sums of 32-bit fields in a
+    /// malformed file can't wrap around.
+    fn bytes(&self, offset: u64, len: u32) -> Result<&'a [u8], ElfError> {
+        let end = offset + len as u64;
+        if end > self.data.len() as u64 {
+            return Err(ElfError::Malformed("offset past the end of the file"));
+        }
+        Ok(&self.data[offset as usize..end as usize])
+    }
+
+    /// Where entry `index` of a table of `size`-byte entries starts.
+    fn entry_offset(table: u32, index: u32, size: u32) -> u64 {
+        table as u64 + index as u64 * size as u64
+    }
+
+    fn half(&self, offset: u64) -> Result<u16, ElfError> {
+        let b = self.bytes(offset, 2)?;
+        Ok(if self.big_endian {
+            ((b[0] as u16) << 8) | b[1] as u16
+        } else {
+            ((b[1] as u16) << 8) | b[0] as u16
+        })
+    }
+
+    fn word(&self, offset: u64) -> Result<u32, ElfError> {
+        let b = self.bytes(offset, 4)?;
+        Ok(if self.big_endian {
+            ((b[0] as u32) << 24) | ((b[1] as u32) << 16) | ((b[2] as u32) << 8) | b[3] as u32
+        } else {
+            ((b[3] as u32) << 24) | ((b[2] as u32) << 16) | ((b[1] as u32) << 8) | b[0] as u32
+        })
+    }
+
+    /// Read the NUL-terminated string at `offset` in a string table.
+    fn string(&self, table: &Section, offset: u32) -> Result<String, ElfError> {
+        if offset >= table.size {
+            return Err(ElfError::Malformed("string outside its table"));
+        }
+        let bytes = self.bytes(table.offset as u64 + offset as u64, table.size - offset)?;
+        match bytes.iter().position(|&b| b == 0) {
+            Some(len) => Ok(String::from_utf8_lossy(&bytes[..len]).into_owned()),
+            None => Err(ElfError::Malformed("unterminated string")),
+        }
+    }
+
+    /// The program headers, which describe the segments to load.
+    pub fn program_headers(&self) -> Result<Vec<ProgramHeader>, ElfError> {
+        let mut headers = vec![];
+        for i in 0..self.program_count {
+            let base = ElfFile::entry_offset(self.program_offset, i as u32, self.program_size as u32);
+            let header = ProgramHeader {
+                kind: self.word(base)?,
+                offset: self.word(base + 4)?,
+                vaddr: self.word(base + 8)?,
+                paddr: self.word(base + 12)?,
+                filesz: self.word(base + 16)?,
+                memsz: self.word(base + 20)?,
+                flags: self.word(base + 24)?,
+            };
+            if header.kind == PT_LOAD && header.filesz > header.memsz {
+                return Err(ElfError::Malformed("segment larger in the file than in memory"));
+            }
+            headers.push(header);
+        }
+        Ok(headers)
+    }
+
+    /// The bytes of a segment stored in the file. The rest of the
+    /// segment, up to `memsz`, is zero.
+    pub fn segment_data(&self, header: &ProgramHeader) -> Result<&'a [u8], ElfError> {
+        self.bytes(header.offset as u64, header.filesz)
+    }
+
+    fn section_header(&self, index: u16) -> Result<Section, ElfError> {
+        let base = ElfFile::entry_offset(self.section_offset, index as u32, self.section_size as u32);
+        Ok(Section {
+            name: String::new(),
+            kind: self.word(base + 4)?,
+            flags: self.word(base + 8)?,
+            addr: self.word(base + 12)?,
+            offset: self.word(base + 16)?,
+            size: self.word(base + 20)?,
+            link: self.word(base + 24)?,
+            entsize: self.word(base + 36)?,
+        })
+    }
+
+    /// The section headers, with their names.
+    pub fn sections(&self) -> Result<Vec<Section>, ElfError> {
+        let mut sections = (0..self.section_count).map(|i| self.section_header(i))
+                                                  .collect::<Result<Vec<_>, _>>()?;
+        if let Some(names) = sections.get(self.section_names as usize).cloned() {
+            for i in 0..self.section_count {
+                let name_offset = self.word(ElfFile::entry_offset(self.section_offset, i as u32,
+                                                                  self.section_size as u32))?;
+                sections[i as usize].name = self.string(&names, name_offset)?;
+            }
+        }
+        Ok(sections)
+    }
+
+    /// The entries of the `.symtab` section, without the null symbol
+    /// at the start. A stripped file has none.
+    pub fn symbols(&self) -> Result<Vec<Symbol>, ElfError> {
+        let sections = self.sections()?;
+        let symtab = match sections.iter().find(|section| section.kind == SHT_SYMTAB) {
+            Some(symtab) => symtab,
+            None => return Ok(vec![]),
+        };
+        let strtab = sections.get(symtab.link as usize)
+                             .ok_or(ElfError::Malformed("symbol table without a string table"))?;
+        if symtab.entsize < 16 {
+            return Err(ElfError::Malformed("symbol table entries are too small"));
+        }
+
+        let mut symbols = vec![];
+        for i in 1..(symtab.size / symtab.entsize) {
+            let base = ElfFile::entry_offset(symtab.offset, i, symtab.entsize);
+            symbols.push(Symbol {
+                name: self.string(strtab, self.word(base)?)?,
+                value: self.word(base + 4)?,
+                size: self.word(base + 8)?,
+                kind: self.bytes(base + 12, 1)?[0] & 0xf,
+                section: self.half(base + 14)?,
+            });
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Build a little-endian ELF file with the given sections, which
+    /// are (name, type, address, contents), followed by a symbol
+    /// table of (name, value, size, type, section index) entries.
+    pub(crate) fn build_elf(entry: u32, sections: &[(&str, u32, u32, Vec<u8>)],
+                            symbols: &[(&str, u32, u32, u8, u16)]) -> Vec<u8> {
+        build_elf_with(false, 0, entry, sections, symbols)
+    }
+
+    /// Build an ELF file in either byte order, with the given header
+    /// flags. Each section with an address gets a loadable segment,
+    /// which is writable for `.bss` and sections named `.data...`.
+    /// Section contents are copied as they are, so should already be
+    /// in the file's byte order.
+    pub(crate) fn build_elf_with(big_endian: bool, flags: u32, entry: u32,
+                                 sections: &[(&str, u32, u32, Vec<u8>)],
+                                 symbols: &[(&str, u32, u32, u8, u16)]) -> Vec<u8> {
+        let half = |half: u16| if big_endian { half.to_be_bytes() } else { half.to_le_bytes() };
+        let word = |word: u32| if big_endian { word.to_be_bytes() } else { word.to_le_bytes() };
+
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; 16];
+        for &(name, value, size, kind, section) in symbols {
+            symtab.extend_from_slice(&word(strtab.len() as u32));
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            symtab.extend_from_slice(&word(value));
+            symtab.extend_from_slice(&word(size));
+            symtab.push(0x10 | kind);
+            symtab.push(0);
+            symtab.extend_from_slice(&half(section));
+        }
+
+        // Section 0 is null; then come the given sections, .symtab,
+        // .strtab and .shstrtab.
+        let symtab_index = sections.len() as u32 + 1;
+        let mut all: Vec<(&str, u32, u32, Vec<u8>, u32, u32)> = vec![("", 0, 0, vec![], 0, 0)];
+        for &(name, kind, addr, ref contents) in sections {
+            all.push((name, kind, addr, contents.clone(), 0, 0));
+        }
+        all.push((".symtab", SHT_SYMTAB, 0, symtab, symtab_index + 1, 16));
+        all.push((".strtab", 3, 0, strtab, 0, 0));
+        let mut shstrtab = vec![0];
+        let mut name_offsets = vec![];
+        for &(name, ..) in all.iter().chain([(".shstrtab", 0, 0, vec![], 0, 0)].iter()) {
+            name_offsets.push(shstrtab.len() as u32);
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+        all.push((".shstrtab", 3, 0, shstrtab, 0, 0));
+
+        let mut data = vec![0; 52];
+        let mut offsets = vec![];
+        for &(_, kind, _, ref contents, _, _) in all.iter() {
+            offsets.push(data.len() as u32);
+            if kind != SHT_NOBITS {
+                data.extend_from_slice(contents);
+            }
+            while !data.len().is_multiple_of(4) {
+                data.push(0);
+            }
+        }
+        let section_offset = data.len() as u32;
+        for (i, &(_, kind, addr, ref contents, link, entsize)) in all.iter().enumerate() {
+            // Every symbol is global, so the first is at index 1.
+            let info = if kind == SHT_SYMTAB { 1 } else { 0 };
+            let size = contents.len() as u32;
+            for &field in [name_offsets[i], kind, 0, addr, offsets[i], size, link, info, 4, entsize].iter() {
+                data.extend_from_slice(&word(field));
+            }
+        }
+
+        let program_offset = data.len() as u32;
+        let mut program_count = 0u16;
+        for (i, &(name, kind, addr, ref contents, _, _)) in all.iter().enumerate() {
+            if addr == 0 {
+                continue;
+            }
+            let size = contents.len() as u32;
+            let (filesz, flags) = if kind == SHT_NOBITS {
+                (0, PF_R | PF_W)
+            } else if name.starts_with(".data") {
+                (size, PF_R | PF_W)
+            } else {
+                (size, PF_R | PF_X)
+            };
+            for &field in [PT_LOAD, offsets[i], addr, addr, filesz, size, flags, 4].iter() {
+                data.extend_from_slice(&word(field));
+            }
+            program_count += 1;
+        }
+
+        data[..16].copy_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+        data[5] = if big_endian { 2 } else { 1 };
+        data[16..18].copy_from_slice(&half(2));
+        data[18..20].copy_from_slice(&half(EM_ARM));
+        data[20..24].copy_from_slice(&word(1));
+        data[24..28].copy_from_slice(&word(entry));
+        if program_count > 0 {
+            data[28..32].copy_from_slice(&word(program_offset));
+        }
+        data[32..36].copy_from_slice(&word(section_offset));
+        data[36..40].copy_from_slice(&word(flags));
+        data[40..42].copy_from_slice(&half(52));
+        data[42..44].copy_from_slice(&half(32));
+        data[44..46].copy_from_slice(&half(program_count));
+        data[46..48].copy_from_slice(&half(40));
+        data[48..50].copy_from_slice(&half(all.len() as u16));
+        data[50..52].copy_from_slice(&half(all.len() as u16 - 1));
+        data
+    }
+
+    #[test]
+    fn read_sections_and_symbols() {
+        let data = build_elf(0x8000, &[(".text", 1, 0x8000, vec![0; 8])], &[
+            ("main", 0x8000, 8, STT_FUNC, 1),
+            ("puts", 0, 0, STT_NOTYPE, 0),
+        ]);
+        let file = ElfFile::parse(&data).unwrap();
+        assert_eq!(file.entry, 0x8000);
+        assert!(!file.is_big_endian());
+
+        let names: Vec<String> = file.sections().unwrap().into_iter().map(|section| section.name).collect();
+        assert_eq!(names, vec!["", ".text", ".symtab", ".strtab", ".shstrtab"]);
+
+        assert_eq!(file.symbols().unwrap(), vec![
+            Symbol { name: "main".to_owned(), value: 0x8000, size: 8, kind: STT_FUNC, section: 1 },
+            Symbol { name: "puts".to_owned(), value: 0, size: 0, kind: STT_NOTYPE, section: 0 },
+        ]);
+    }
+
+    #[test]
+    fn read_program_headers() {
+        let data = build_elf(0x8000, &[
+            (".text", 1, 0x8000, vec![1, 2, 3, 4]),
+            (".bss", SHT_NOBITS, 0x80000000, vec![0; 0x100]),
+        ], &[]);
+        let file = ElfFile::parse(&data).unwrap();
+        let headers = file.program_headers().unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].kind, PT_LOAD);
+        assert_eq!((headers[0].paddr, headers[0].filesz, headers[0].memsz), (0x8000, 4, 4));
+        assert_eq!(headers[0].flags, PF_R | PF_X);
+        assert_eq!(file.segment_data(&headers[0]), Ok(&[1, 2, 3, 4][..]));
+        assert_eq!((headers[1].paddr, headers[1].filesz, headers[1].memsz), (0x80000000, 0, 0x100));
+        assert_eq!(headers[1].flags, PF_R | PF_W);
+        assert_eq!(file.segment_data(&headers[1]), Ok(&[][..]));
+
+        let data = build_elf_with(true, EF_ARM_BE8, 0x8000, &[(".text", 1, 0x8000, vec![0; 4])], &[]);
+        let file = ElfFile::parse(&data).unwrap();
+        assert_eq!(file.entry, 0x8000);
+        assert!(file.is_big_endian() && file.is_be8());
+        assert_eq!(file.program_headers().unwrap()[0].memsz, 4);
+
+        // A segment can't hold more of the file than fits in memory.
+        let mut data = build_elf(0x8000, &[(".text", 1, 0x8000, vec![0; 4])], &[]);
+        let memsz = data.len() - 12;
+        data[memsz] = 2;
+        assert_eq!(ElfFile::parse(&data).unwrap().program_headers().err(),
+                   Some(ElfError::Malformed("segment larger in the file than in memory")));
+
+        // Program headers that would start past 4GB.
+        let mut data = build_elf(0x8000, &[(".text", 1, 0x8000, vec![0; 4])], &[]);
+        data[28..32].copy_from_slice(&[0xf0, 0xff, 0xff, 0xff]);
+        assert_eq!(ElfFile::parse(&data).unwrap().program_headers().err(),
+                   Some(ElfError::Malformed("offset past the end of the file")));
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert_eq!(ElfFile::parse(b"\x7fELG").err(), Some(ElfError::NotElf));
+        assert_eq!(ElfFile::parse(b"\x7fELF\x01").err(), Some(ElfError::Malformed("truncated header")));
+
+        let mut data = build_elf(0, &[], &[]);
+        data[18] = 62;
+        assert_eq!(ElfFile::parse(&data).err(), Some(ElfError::Unsupported("not an ARM file")));
+        data[4] = 2;
+        assert_eq!(ElfFile::parse(&data).err(), Some(ElfError::Unsupported("not a 32-bit file")));
+
+        let mut data = build_elf(0, &[], &[("main", 0, 0, STT_FUNC, 1)]);
+        let len = data.len();
+        data.truncate(len - 40);
+        assert_eq!(ElfFile::parse(&data).unwrap().symbols().err(),
+                   Some(ElfError::Malformed("offset past the end of the file")));
+
+        // Section headers that would start past 4GB.
+        let mut data = build_elf(0, &[], &[]);
+        data[32..36].copy_from_slice(&[0xff; 4]);
+        let file = ElfFile::parse(&data).unwrap();
+        assert_eq!(file.sections().err(), Some(ElfError::Malformed("offset past the end of the file")));
+        assert_eq!(file.symbols().err(), Some(ElfError::Malformed("offset past the end of the file")));
+    }
+}
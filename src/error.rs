@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::fmt;
+
+use address::Address;
+
+/// Something a guest program did that the emulator can't carry out.
+/// Each error records the address involved, and the opcode of the
+/// instruction responsible when there is one. Accesses made directly
+/// through a `MemMap32` have no opcode; `Computer` fills it in.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EmulationError {
+    /// Nothing is mapped at `addr`.
+    UnmappedAccess { addr: Address, opcode: Option<u32> },
+
+    /// A write to read-only memory at `addr`.
+    ReadOnlyWrite { addr: Address, opcode: Option<u32> },
+
+    /// `addr` isn't aligned the way the access requires.
+    Misaligned { addr: Address, opcode: Option<u32> },
+
+    /// The word fetched from `addr` isn't a valid instruction.
+    Undecodable { addr: Address, opcode: Option<u32> },
+
+    /// The instruction at `addr` decodes but isn't emulated.
+    Unimplemented { addr: Address, opcode: Option<u32> },
+
+    /// The instruction at `addr` tried to enter a processor mode whose
+    /// encoding, `mode`, isn't valid.
+    InvalidMode { addr: Address, opcode: Option<u32>, mode: u32 },
+
+    /// A device mapped at `addr` didn't respond to the access.
+    BusError { addr: Address, opcode: Option<u32> },
+}
+
+impl EmulationError {
+    pub fn addr(&self) -> Address {
+        match *self {
+            EmulationError::UnmappedAccess { addr, .. } |
+            EmulationError::ReadOnlyWrite { addr, .. } |
+            EmulationError::Misaligned { addr, .. } |
+            EmulationError::Undecodable { addr, .. } |
+            EmulationError::Unimplemented { addr, .. } |
+            EmulationError::InvalidMode { addr, .. } |
+            EmulationError::BusError { addr, .. } => addr,
+        }
+    }
+
+    pub fn opcode(&self) -> Option<u32> {
+        match *self {
+            EmulationError::UnmappedAccess { opcode, .. } |
+            EmulationError::ReadOnlyWrite { opcode, .. } |
+            EmulationError::Misaligned { opcode, .. } |
+            EmulationError::Undecodable { opcode, .. } |
+            EmulationError::Unimplemented { opcode, .. } |
+            EmulationError::InvalidMode { opcode, .. } |
+            EmulationError::BusError { opcode, .. } => opcode,
+        }
+    }
+
+    /// Attribute the error to an instruction, unless it already is.
+    pub fn with_opcode(mut self, code: u32) -> EmulationError {
+        match self {
+            EmulationError::UnmappedAccess { ref mut opcode, .. } |
+            EmulationError::ReadOnlyWrite { ref mut opcode, .. } |
+            EmulationError::Misaligned { ref mut opcode, .. } |
+            EmulationError::Undecodable { ref mut opcode, .. } |
+            EmulationError::Unimplemented { ref mut opcode, .. } |
+            EmulationError::InvalidMode { ref mut opcode, .. } |
+            EmulationError::BusError { ref mut opcode, .. } => {
+                if opcode.is_none() {
+                    *opcode = Some(code);
+                }
+            },
+        }
+        self
+    }
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulationError::UnmappedAccess { addr, .. } =>
+                write!(f, "no memory mapped at {:#x}", addr)?,
+            EmulationError::ReadOnlyWrite { addr, .. } =>
+                write!(f, "write to read-only memory at {:#x}", addr)?,
+            EmulationError::Misaligned { addr, .. } =>
+                write!(f, "misaligned access at {:#x}", addr)?,
+            EmulationError::Undecodable { addr, .. } =>
+                write!(f, "undecodable instruction at {:#x}", addr)?,
+            EmulationError::Unimplemented { addr, .. } =>
+                write!(f, "unimplemented instruction at {:#x}", addr)?,
+            EmulationError::InvalidMode { addr, mode, .. } =>
+                write!(f, "invalid processor mode {:#07b} set at {:#x}", mode, addr)?,
+            EmulationError::BusError { addr, .. } =>
+                write!(f, "bus error at {:#x}", addr)?,
+        }
+        match self.opcode() {
+            Some(opcode) => write!(f, " (opcode {:#010x})", opcode),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for EmulationError {}
+
+/// A line of a text file, such as assembly source or a symbol map,
+/// that couldn't be read.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseError {
+    /// Line number, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+#[cfg(test)]
+mod test {
+    use error::EmulationError;
+
+    #[test]
+    fn attribute_errors_to_instructions() {
+        let err = EmulationError::UnmappedAccess { addr: 0x40000000, opcode: None };
+        assert_eq!(err.to_string(), "no memory mapped at 0x40000000");
+
+        let err = err.with_opcode(0xe5901000);
+        assert_eq!(err.addr(), 0x40000000);
+        assert_eq!(err.opcode(), Some(0xe5901000));
+        assert_eq!(err.to_string(), "no memory mapped at 0x40000000 (opcode 0xe5901000)");
+
+        // The first instruction blamed keeps the blame.
+        assert_eq!(err.clone().with_opcode(0), err);
+
+        let err = EmulationError::InvalidMode { addr: 0x20, opcode: Some(0xe121f000), mode: 0 };
+        assert_eq!(err.to_string(), "invalid processor mode 0b00000 set at 0x20 (opcode 0xe121f000)");
+    }
+}
@@ -0,0 +1,190 @@
+//! Loading Intel HEX and Motorola S-record images, the text formats
+//! bootloaders and flash tools commonly use instead of raw binaries.
+
+use address::{MemMap32, Region};
+use error::ParseError;
+
+/// Decode a string of hex digit pairs.
+fn hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return None;
+    }
+    (0..digits.len()).step_by(2)
+                     .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+                     .collect()
+}
+
+/// Join big-endian bytes into a number.
+fn big_endian_value(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |value, &b| value << 8 | b as u32)
+}
+
+/// Write a record's data into memory, which must be writable.
+fn write(mem: &mut MemMap32, data: &[u8], addr: u32, line: usize) -> Result<(), ParseError> {
+    if addr as u64 + data.len() as u64 > 0x100000000 {
+        return Err(ParseError { line: line, message: "data past the end of the address space".to_owned() });
+    }
+    mem.address_space.write_cells(data, addr as u64)
+                     .map_err(|err| ParseError { line: line, message: err.to_string() })
+}
+
+/// Load an Intel HEX file into memory, returning the start address if
+/// it has one. Both segment (type 2) and linear (type 4) extended
+/// addresses are understood; a segment start address (type 3) is
+/// converted to a linear one. Anything after the end-of-file record
+/// is ignored.
+pub fn load_ihex(text: &str, mem: &mut MemMap32) -> Result<Option<u32>, ParseError> {
+    let mut base = 0u32;
+    let mut start = None;
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| ParseError { line: i + 1, message: message.to_owned() };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with(':') {
+            return Err(error("expected a record starting with ':'"));
+        }
+        let bytes = hex_bytes(&line[1..]).ok_or(error("bad hex digits"))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("record length doesn't match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(error("bad checksum"));
+        }
+        let offset = big_endian_value(&bytes[1..3]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0 => write(mem, data, base.wrapping_add(offset), i + 1)?,
+            1 => break,
+            2 if data.len() == 2 => base = big_endian_value(data) << 4,
+            3 if data.len() == 4 => {
+                let segment = big_endian_value(&data[..2]);
+                start = Some((segment << 4).wrapping_add(big_endian_value(&data[2..])));
+            },
+            4 if data.len() == 2 => base = big_endian_value(data) << 16,
+            5 if data.len() == 4 => start = Some(big_endian_value(data)),
+            2..=5 => return Err(error("wrong length for the record type")),
+            _ => return Err(error("unknown record type")),
+        }
+    }
+    Ok(start)
+}
+
+/// Load a Motorola S-record file into memory, returning the start
+/// address if it has one. Data records may have 16-bit (S1), 24-bit
+/// (S2) or 32-bit (S3) addresses. Header (S0) and count (S5, S6)
+/// records are checked but otherwise ignored.
+pub fn load_srec(text: &str, mem: &mut MemMap32) -> Result<Option<u32>, ParseError> {
+    let mut start = None;
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| ParseError { line: i + 1, message: message.to_owned() };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with('S') || line.len() < 2 {
+            return Err(error("expected a record starting with 'S'"));
+        }
+        if !line.is_ascii() {
+            return Err(error("bad hex digits"));
+        }
+        let kind = line.as_bytes()[1];
+        let bytes = hex_bytes(&line[2..]).ok_or(error("bad hex digits"))?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error("record length doesn't match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0xff {
+            return Err(error("bad checksum"));
+        }
+        let address_size = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(error("unknown record type")),
+        };
+        if bytes.len() < address_size + 2 {
+            return Err(error("record too short for its address"));
+        }
+        let addr = big_endian_value(&bytes[1..address_size + 1]);
+        let data = &bytes[address_size + 1..bytes.len() - 1];
+        match kind {
+            b'1' | b'2' | b'3' => write(mem, data, addr, i + 1)?,
+            b'7' | b'8' | b'9' => start = Some(addr),
+            _ => {},
+        }
+    }
+    Ok(start)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_intel_hex() {
+        let mut mem = MemMap32::with_ram();
+        let start = load_ihex("
+:0400000001020304F2
+:020000040800F2
+:04001000DEADBEEFB4
+:020000021000EC
+:01000200AA53
+:0400000508000101ED
+:00000001FF
+this isn't read
+", &mut mem);
+        assert_eq!(start, Ok(Some(0x08000101)));
+        assert_eq!(mem.get32(0, true), Ok(0x01020304));
+        assert_eq!(mem.get32(0x08000010, true), Ok(0xdeadbeef));
+        assert_eq!(mem.get8(0x10002), Ok(0xaa));
+
+        let start = load_ihex(":0400000300100020C9\n", &mut mem);
+        assert_eq!(start, Ok(Some(0x120)));
+
+        let error = |text: &str, mem: &mut MemMap32| load_ihex(text, mem).unwrap_err().to_string();
+        assert_eq!(error("\n:0400000001020304F3", &mut mem), "line 2: bad checksum");
+        assert_eq!(error(":0500000001020304F2", &mut mem), "line 1: record length doesn't match its byte count");
+        assert_eq!(error("0400000001020304F2", &mut mem), "line 1: expected a record starting with ':'");
+        assert_eq!(error(":04000000010203G4F2", &mut mem), "line 1: bad hex digits");
+        assert_eq!(error(":00000006FA", &mut mem), "line 1: unknown record type");
+        assert_eq!(error(":0100000400FB", &mut mem), "line 1: wrong length for the record type");
+
+        // Data has to land in writable memory.
+        let mut mem = MemMap32::new(vec![0; 4]);
+        assert_eq!(load_ihex(":0400000001020304F2", &mut mem).unwrap_err().to_string(),
+                   "line 1: write to read-only memory at 0x0");
+        assert_eq!(mem.get32(0, true), Ok(0));
+        assert_eq!(load_ihex(":0100040000FB", &mut mem).unwrap_err().to_string(), "line 1: no memory mapped at 0x4");
+    }
+
+    #[test]
+    fn load_s_records() {
+        let mut mem = MemMap32::with_ram();
+        let start = load_srec("
+S00600004844521B
+S107000001020304EE
+S208010000112233444C
+S30980000000DEADBEEF3E
+S5030003F9
+S70508000101F0
+", &mut mem);
+        assert_eq!(start, Ok(Some(0x08000101)));
+        assert_eq!(mem.get32(0, true), Ok(0x01020304));
+        assert_eq!(mem.get32(0x10000, true), Ok(0x11223344));
+        assert_eq!(mem.get32(0x80000000, true), Ok(0xdeadbeef));
+
+        assert_eq!(load_srec("S9030100FB", &mut mem), Ok(Some(0x100)));
+        assert_eq!(load_srec("S804010000FA", &mut mem), Ok(Some(0x10000)));
+
+        let error = |text: &str, mem: &mut MemMap32| load_srec(text, mem).unwrap_err().to_string();
+        assert_eq!(error("S107000001020304EF", &mut mem), "line 1: bad checksum");
+        assert_eq!(error("\nS108000001020304EE", &mut mem), "line 2: record length doesn't match its byte count");
+        assert_eq!(error(":0400000001020304F2", &mut mem), "line 1: expected a record starting with 'S'");
+        assert_eq!(error("S4030000FC", &mut mem), "line 1: unknown record type");
+        assert_eq!(error("S3030000FC", &mut mem), "line 1: record too short for its address");
+        assert_eq!(error("S10740000102030", &mut mem), "line 1: bad hex digits");
+        assert_eq!(error("S\u{e9}00", &mut mem), "line 1: bad hex digits");
+        assert_eq!(error("S30840000000000000B7", &mut mem), "line 1: no memory mapped at 0x40000000");
+    }
+}
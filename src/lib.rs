@@ -1,4 +1,14 @@
+#[cfg(test)]
+extern crate quickcheck;
+
 pub mod address;
+pub mod error;
 pub mod registers;
 pub mod processor;
+pub mod thumb;
+pub mod thumb2;
 pub mod computer;
+pub mod assembler;
+pub mod elf;
+pub mod symbols;
+pub mod image;
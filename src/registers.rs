@@ -66,7 +66,7 @@ impl fmt::Debug for Register32 {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum ProcessorMode {
     /// failed attempt to access memory
     Abort,
@@ -110,6 +110,7 @@ pub enum InterruptMask {
     FIQ,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum InstructionSet {
     /// Conventional ARM instruction set
     ARM,
@@ -164,11 +165,13 @@ pub trait ProgramStatusRegister : Register {
 
     fn thumb_state_index(&self) -> u8;
 
+    /// Switch instruction set. The T bit is execution state, which
+    /// the processor changes in any mode.
     fn set_instruction_set(&mut self, instr_set: InstructionSet) {
         let index = self.thumb_state_index();
         match instr_set {
-            InstructionSet::Thumb => self.write_bit(index, true),
-            InstructionSet::ARM => self.write_bit(index, false),
+            InstructionSet::Thumb => self._write_bit(index, true),
+            InstructionSet::ARM => self._write_bit(index, false),
         }
     }
 
@@ -179,6 +182,24 @@ pub trait ProgramStatusRegister : Register {
             InstructionSet::ARM
         }
     }
+
+    /// Index of bit `i` of IT[7:0], the If-Then state.
+    fn it_state_index(&self, i: u8) -> u8;
+
+    /// The If-Then state of Thumb code: the base condition of the
+    /// current IT block in IT[7:5], and in IT[4:0] the condition bit
+    /// and length of the rest of it. Zero outside an IT block.
+    fn it_state(&self) -> u8 {
+        (0..8).filter(|&i| self._read_bit(self.it_state_index(i)))
+            .fold(0, |it, i| it | (1 << i))
+    }
+
+    fn set_it_state(&mut self, it: u8) {
+        for i in 0..8 {
+            let index = self.it_state_index(i);
+            self._write_bit(index, (it >> i) & 1 == 1);
+        }
+    }
 }
 
 impl ProgramStatusRegister for Register32 {
@@ -253,6 +274,16 @@ impl ProgramStatusRegister for Register32 {
     fn thumb_state_index(&self) -> u8 {
         5
     }
+
+    fn it_state_index(&self, i: u8) -> u8 {
+        debug_assert!(i < 8);
+        // IT[1:0] are bits 26:25, and IT[7:2] are bits 15:10.
+        if i < 2 {
+            25 + i
+        } else {
+            8 + i
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
@@ -277,6 +308,21 @@ pub enum RegisterBank {
     SPSR,
 }
 
+impl fmt::Display for RegisterBank {
+    /// Write the register's name as it appears in assembly.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            RegisterBank::R13 => "sp",
+            RegisterBank::R14 => "lr",
+            RegisterBank::R15 => "pc",
+            RegisterBank::CPSR => "cpsr",
+            RegisterBank::SPSR => "spsr",
+            reg => return write!(f, "r{}", reg as u32),
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Debug)]
 struct RegisterID(RegisterBank, ProcessorMode);
 
@@ -381,21 +427,48 @@ impl RegisterFile {
         }
     }
 
-    // TODO: ASDG says modes can change due to: "reset, interrupt
-    // request, fast interrupt request, software interrupt, data
-    // abort, prefetch abort, and undefined instruction"
-    fn on_mode_change(&mut self, mode: ProcessorMode) {
-        // let orig_mode = self.mode();
-        self.cpsr_mut().set_mode(mode);
+    /// Look up a register as seen from a particular mode, which may
+    /// differ from the current one.
+    pub fn lookup_banked(&self, bank: RegisterBank, mode: ProcessorMode) -> Option<&Register32> {
+        match self.table.get(&RegisterID(bank, mode)) {
+            Some(reg) => Some(reg),
+            None => self.table.get(&RegisterID(bank, ProcessorMode::User)),
+        }
+    }
 
-        // TODO: if orig_mode == User (and maybe only when new mode is
-        // an IRQ or FIQ?), copy CPSR to SPSR for new mode.
+    pub fn lookup_banked_mut(&mut self, bank: RegisterBank, mode: ProcessorMode) -> Option<&mut Register32> {
+        let id = RegisterID(bank, mode);
+        if self.table.contains_key(&id) {
+            self.table.get_mut(&id)
+        } else {
+            self.table.get_mut(&RegisterID(bank, ProcessorMode::User))
+        }
+    }
 
-        // TODO: adjust interrupt masks?
+    /// Switch to the mode that handles an exception, saving the CPSR
+    /// into that mode's SPSR and the return address into its LR. The
+    /// handler runs ARM code with IRQs (and for reset and FIQ, FIQs)
+    /// masked. Returning is up to the handler, via an instruction
+    /// that copies the SPSR back into the CPSR.
+    pub fn enter_exception(&mut self, exception: Exception, return_addr: u32) {
+        let mode = exception.mode();
+        let saved_cpsr = self.cpsr().bits;
+        self.on_mode_change(mode);
+        self.lookup_banked_mut(RegisterBank::SPSR, mode).unwrap().bits = saved_cpsr;
+        self.lookup_banked_mut(RegisterBank::R14, mode).unwrap().bits = return_addr;
+
+        let cpsr = self.cpsr_mut();
+        cpsr.set_instruction_set(InstructionSet::ARM);
+        cpsr.set_it_state(0);
+        cpsr.set_interrupt_mask(InterruptMask::IRQ, true);
+        if exception == Exception::Reset || exception == Exception::FastInterruptRequest {
+            cpsr.set_interrupt_mask(InterruptMask::FIQ, true);
+        }
     }
 
-    // TODO: Support special return instruction to go back to User
-    // mode. Involves at least copying the current SPSR to CPSR.
+    fn on_mode_change(&mut self, mode: ProcessorMode) {
+        self.cpsr_mut().set_mode(mode);
+    }
 
     fn on_condition(&mut self, cf: ConditionFlag) {
         self.cpsr_mut().set_condition_flag(cf, true);
@@ -412,7 +485,46 @@ impl Default for RegisterFile {
     }
 }
 
-// TODO(low): exceptions, interrupts, and vector table (ASDG 2.4)
+/// The exceptions that interrupt normal execution, in decreasing
+/// priority (ASDG 2.4).
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Exception {
+    Reset,
+    DataAbort,
+    FastInterruptRequest,
+    InterruptRequest,
+    PrefetchAbort,
+    SoftwareInterrupt,
+    UndefinedInstruction,
+}
+
+impl Exception {
+    /// The mode the processor enters to handle the exception.
+    pub fn mode(&self) -> ProcessorMode {
+        match *self {
+            Exception::Reset => ProcessorMode::Supervisor,
+            Exception::DataAbort => ProcessorMode::Abort,
+            Exception::FastInterruptRequest => ProcessorMode::FastInterruptRequest,
+            Exception::InterruptRequest => ProcessorMode::InterruptRequest,
+            Exception::PrefetchAbort => ProcessorMode::Abort,
+            Exception::SoftwareInterrupt => ProcessorMode::Supervisor,
+            Exception::UndefinedInstruction => ProcessorMode::Undefined,
+        }
+    }
+
+    /// Offset of the exception's entry in the vector table.
+    pub fn vector_offset(&self) -> u32 {
+        match *self {
+            Exception::Reset => 0x00,
+            Exception::UndefinedInstruction => 0x04,
+            Exception::SoftwareInterrupt => 0x08,
+            Exception::PrefetchAbort => 0x0c,
+            Exception::DataAbort => 0x10,
+            Exception::InterruptRequest => 0x18,
+            Exception::FastInterruptRequest => 0x1c,
+        }
+    }
+}
 
 // TODO(low): core extensions (ASDG 2.5)
 
@@ -421,6 +533,8 @@ impl Default for RegisterFile {
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
     // TODO: verify that a new RegisterFile starts in supervisor mode
     // and using the ARM IS
 
@@ -431,4 +545,33 @@ mod test {
     fn it_works() {
         assert_eq!(2, 2);
     }
+
+    #[test]
+    fn enter_exception_banks_cpsr() {
+        let mut rf = RegisterFile::new();
+        rf.cpsr_mut().set_mode(ProcessorMode::User);
+        rf.cpsr_mut().set_instruction_set(InstructionSet::Thumb);
+        rf.cpsr_mut().set_condition_flag(ConditionFlag::Carry, true);
+        rf.cpsr_mut().set_it_state(0b0001_0100);
+        let user_cpsr = rf.cpsr().bits;
+        assert_eq!(user_cpsr & 0x0600fc00, 0x00001400);
+
+        rf.enter_exception(Exception::FastInterruptRequest, 0x104);
+        assert_eq!(rf.mode(), ProcessorMode::FastInterruptRequest);
+        assert_eq!(rf.lookup(RegisterBank::SPSR).unwrap().bits, user_cpsr);
+        assert_eq!(rf.lookup(RegisterBank::R14).unwrap().bits, 0x104);
+        assert_eq!(rf.lookup_banked(RegisterBank::R14, ProcessorMode::User).unwrap().bits, 0);
+        assert!(!rf.cpsr().permit_interrupt(InterruptMask::IRQ));
+        assert!(!rf.cpsr().permit_interrupt(InterruptMask::FIQ));
+        assert!(rf.cpsr().is_condition_flag_on(ConditionFlag::Carry));
+        assert_eq!(rf.cpsr().it_state(), 0);
+        assert_eq!(rf.cpsr().active_instruction_set(), InstructionSet::ARM);
+
+        // An IRQ taken from FIQ mode leaves FIQs masked as they were.
+        rf.enter_exception(Exception::InterruptRequest, 0x8);
+        assert_eq!(rf.mode(), ProcessorMode::InterruptRequest);
+        assert!(!rf.cpsr().permit_interrupt(InterruptMask::FIQ));
+        assert_eq!(rf.lookup_banked(RegisterBank::SPSR, ProcessorMode::FastInterruptRequest)
+                     .unwrap().bits, user_cpsr);
+    }
 }
@@ -0,0 +1,206 @@
+//! Names for addresses in a guest program, for the disassembler and
+//! debugger. They can come from an ELF file's symbol table or from a
+//! map file in the format `nm` prints.
+
+use elf;
+use elf::{ElfError, ElfFile};
+use error::ParseError;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    /// Size in bytes, or 0 if unknown.
+    pub size: u32,
+}
+
+/// Symbols sorted by address.
+#[derive(Clone, Default, Debug)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable { symbols: vec![] }
+    }
+
+    /// Read the functions, objects and labels defined in an ELF
+    /// file's `.symtab`.
+    pub fn from_elf(data: &[u8]) -> Result<SymbolTable, ElfError> {
+        let mut table = SymbolTable::new();
+        for symbol in ElfFile::parse(data)?.symbols()? {
+            let addr = match symbol.kind {
+                // The low bit of a Thumb function's address is set.
+                elf::STT_FUNC => symbol.value & !1,
+                elf::STT_NOTYPE | elf::STT_OBJECT => symbol.value,
+                _ => continue,
+            };
+            if symbol.section != 0 {
+                table.insert(&symbol.name, addr, symbol.size);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Read the output of `nm` or `nm -S`, or a `System.map`: lines
+    /// of an address in hex, an optional size, a type letter and a
+    /// name. Undefined symbols and file headers are skipped.
+    pub fn from_map(text: &str) -> Result<SymbolTable, ParseError> {
+        let mut table = SymbolTable::new();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: &str| ParseError { line: i + 1, message: message.to_owned() };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (addr, size, kind, name) = match fields.len() {
+                0 => continue,
+                1 if line.ends_with(':') => continue,
+                2 => {
+                    // Undefined symbols have no address.
+                    if fields[0].len() == 1 && "Uvw".contains(fields[0]) {
+                        continue;
+                    }
+                    return Err(error("expected an address, type and name"));
+                },
+                3 => (fields[0], "0", fields[1], fields[2]),
+                4 => (fields[0], fields[1], fields[2], fields[3]),
+                _ => return Err(error("expected an address, type and name")),
+            };
+            let addr = u32::from_str_radix(addr, 16).map_err(|_| error("bad address"))?;
+            let size = u32::from_str_radix(size, 16).map_err(|_| error("bad size"))?;
+            if kind.len() != 1 {
+                return Err(error("bad symbol type"));
+            }
+            // Skip debugging symbols.
+            if kind != "N" && kind != "n" {
+                table.insert(name, addr, size);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Add a symbol. ARM mapping symbols like `$a` and `$d`, which
+    /// mark code and data rather than naming anything, are ignored.
+    pub fn insert(&mut self, name: &str, addr: u32, size: u32) {
+        if name.is_empty() || name.starts_with('$') {
+            return;
+        }
+        let i = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        self.symbols.insert(i, Symbol { name: name.to_owned(), addr: addr, size: size });
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.addr)
+    }
+
+    /// Find the symbol an address is in, and the offset into it. A
+    /// symbol of unknown size extends to the next symbol, except for
+    /// the last one, which covers only its own address.
+    pub fn lookup(&self, addr: u32) -> Option<(&Symbol, u32)> {
+        let end = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        let candidates = &self.symbols[..end];
+        let start = candidates.last()?.addr;
+        // Prefer the first sized symbol among several at one address.
+        let at_start = candidates.iter().filter(|symbol| symbol.addr == start);
+        let symbol = at_start.clone().find(|symbol| symbol.size > 0).or_else(|| at_start.clone().next())?;
+        let offset = addr - symbol.addr;
+        let contained = if symbol.size > 0 {
+            offset < symbol.size
+        } else {
+            offset == 0 || end < self.symbols.len()
+        };
+        if contained {
+            Some((symbol, offset))
+        } else {
+            None
+        }
+    }
+
+    /// Describe an address as `name` or `name+offset`.
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        self.lookup(addr).map(|(symbol, offset)| if offset == 0 {
+            symbol.name.clone()
+        } else {
+            format!("{}+{}", symbol.name, offset)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use elf::test::build_elf;
+
+    #[test]
+    fn lookup_symbols() {
+        let mut table = SymbolTable::new();
+        table.insert("main", 0x8000, 0x20);
+        table.insert("_start", 0x7f00, 0);
+        table.insert("$a", 0x8000, 0);
+        table.insert("main_alias", 0x8000, 0);
+        table.insert("buffer", 0x9000, 0x100);
+        assert_eq!(table.len(), 4);
+
+        assert_eq!(table.describe(0x7e00), None);
+        assert_eq!(table.describe(0x7f00), Some("_start".to_owned()));
+        assert_eq!(table.describe(0x7ffc), Some("_start+252".to_owned()));
+        assert_eq!(table.describe(0x8000), Some("main".to_owned()));
+        assert_eq!(table.describe(0x8010), Some("main+16".to_owned()));
+        assert_eq!(table.describe(0x8020), None);
+        assert_eq!(table.describe(0x90ff), Some("buffer+255".to_owned()));
+        table.insert("_end", 0x9100, 0);
+        assert_eq!(table.describe(0x9100), Some("_end".to_owned()));
+        assert_eq!(table.describe(0x9104), None);
+        assert_eq!(table.address_of("buffer"), Some(0x9000));
+        assert_eq!(table.address_of("$a"), None);
+    }
+
+    #[test]
+    fn read_elf_symbols() {
+        let data = build_elf(0x8000, &[(".text", 1, 0x8000, vec![0; 0x40])], &[
+            ("uart_init", 0x8000, 0x10, elf::STT_FUNC, 1),
+            ("thumb_func", 0x8011, 0x10, elf::STT_FUNC, 1),
+            ("$d", 0x8020, 0, elf::STT_NOTYPE, 1),
+            ("loop", 0x8024, 0, elf::STT_NOTYPE, 1),
+            ("uart_base", 0x8030, 4, elf::STT_OBJECT, 1),
+            ("main.c", 0, 0, elf::STT_FILE, 0xfff1),
+            (".text", 0x8000, 0, elf::STT_SECTION, 1),
+            ("puts", 0, 0, elf::STT_FUNC, 0),
+        ]);
+        let table = SymbolTable::from_elf(&data).unwrap();
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.describe(0x8010), Some("thumb_func".to_owned()));
+        assert_eq!(table.describe(0x8028), Some("loop+4".to_owned()));
+        assert_eq!(table.address_of("uart_base"), Some(0x8030));
+        assert_eq!(table.address_of("puts"), None);
+
+        assert_eq!(SymbolTable::from_elf(b"not an elf file").err(), Some(ElfError::NotElf));
+    }
+
+    #[test]
+    fn read_map_files() {
+        let table = SymbolTable::from_map("
+firmware.o:
+00008000 00000010 T uart_init
+00008010 T main
+         U puts
+00008040 r $d
+00009000 00000100 B buffer
+00000000 N .debug_info
+").unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.describe(0x8008), Some("uart_init+8".to_owned()));
+        assert_eq!(table.describe(0x8044), Some("main+52".to_owned()));
+        assert_eq!(table.address_of("buffer"), Some(0x9000));
+
+        let error = SymbolTable::from_map("00008000 T main\nmain T 00008000\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: bad address");
+    }
+}
@@ -0,0 +1,667 @@
+//! The Thumb instruction set: the 16-bit instructions of ARMv4T and
+//! ARMv5T, and the 32-bit ones Thumb-2 adds (decoded by `thumb2`).
+//! Nearly every Thumb instruction is a restricted form of an ARM one,
+//! so most decode to the equivalent `CondInstr`, and `ThumbInstr`
+//! holds the rest.
+
+use processor::{
+    address_str,
+    bits,
+    condition_suffix,
+    data_processing_str,
+    immediate_str,
+    word_or_byte_address_str,
+    AddressingOffset12,
+    AddressingOffset8,
+    BarrelShiftOp,
+    Condition,
+    CondInstr,
+    Decodable,
+    Encodable,
+    HalfwordOrSigned,
+    Instruction,
+    LoadStoreMultiple,
+    ShiftSize,
+    WordOrUnsignedByte,
+};
+use registers::RegisterBank;
+use symbols::SymbolTable;
+use thumb2;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ThumbInstr {
+    /// ADD Rd, PC, #imm (or SUB), which is relative to the
+    /// word-aligned PC rather than the PC itself.
+    ADR { rd: RegisterBank, offset: i32 },
+
+    /// Stop for a debugger by taking a prefetch abort. The immediate
+    /// is ignored by the processor.
+    BKPT(u32),
+    CBNZ { rn: RegisterBank, offset: u32 },
+    CBZ { rn: RegisterBank, offset: u32 },
+
+    /// Make the next one to four instructions conditional. The bits
+    /// of `mask` are relative to the low bit of `firstcond`.
+    IT { firstcond: Condition, mask: u32 },
+
+    // The halfword, signed byte and doubleword transfers take 12-bit
+    // offsets and scaled registers, so use word addressing, and the
+    // doubleword pair needn't be consecutive.
+    LDRD { rd: RegisterBank, rd2: RegisterBank, addr_ref: WordOrUnsignedByte },
+    LDRH { rd: RegisterBank, addr_ref: WordOrUnsignedByte },
+    LDRSB { rd: RegisterBank, addr_ref: WordOrUnsignedByte },
+    LDRSH { rd: RegisterBank, addr_ref: WordOrUnsignedByte },
+    NOP,
+    ORN { s: bool, rd: RegisterBank, rn: RegisterBank, shift_op: BarrelShiftOp },
+    STRD { rd: RegisterBank, rd2: RegisterBank, addr_ref: WordOrUnsignedByte },
+    STRH { rd: RegisterBank, addr_ref: WordOrUnsignedByte },
+
+    /// Branch forward by twice the byte at Rn + Rm.
+    TBB { rn: RegisterBank, rm: RegisterBank },
+
+    /// Branch forward by twice the halfword at Rn + 2 * Rm.
+    TBH { rn: RegisterBank, rm: RegisterBank },
+}
+
+/// Check whether a halfword is the first half of a 32-bit
+/// instruction.
+pub fn is_wide(first: u16) -> bool {
+    bits(first as u32, 15, 11) >= 0b11101
+}
+
+/// Decode a Thumb instruction. `second` is only used when `first`
+/// begins a 32-bit instruction. Inside an IT block the 16-bit
+/// data-processing instructions don't set flags, except comparisons.
+pub fn decode(first: u16, second: u16, in_it_block: bool) -> Option<Instruction> {
+    let code = first as u32;
+    if is_wide(first) {
+        return thumb2::decode(first, second);
+    }
+
+    let s = !in_it_block;
+    let instr = match bits(code, 15, 12) {
+        0b0000 | 0b0001 => decode_shift_add_subtract(code, s),
+        0b0010 | 0b0011 => decode_immediate(code, s),
+        0b0100 => {
+            match bits(code, 11, 10) {
+                0b00 => decode_alu(code, s),
+                0b01 => decode_hi_register(code),
+                _ => {
+                    let rd = low_register(code, 8);
+                    let offset = bits(code, 7, 0) << 2;
+                    Some(CondInstr::LDR { rd: rd, addr_ref: word_immediate(RegisterBank::R15, offset) })
+                },
+            }
+        },
+        0b0101 => decode_register_offset(code),
+        0b0110 | 0b0111 => decode_immediate_offset(code),
+        0b1000 => {
+            let rn = low_register(code, 3);
+            let rd = low_register(code, 0);
+            let addr_ref = halfword_immediate(rn, bits(code, 10, 6) << 1);
+            if bits(code, 11, 11) == 0 {
+                Some(CondInstr::STRH { rd: rd, addr_ref: addr_ref })
+            } else {
+                Some(CondInstr::LDRH { rd: rd, addr_ref: addr_ref })
+            }
+        },
+        0b1001 => {
+            let rd = low_register(code, 8);
+            let addr_ref = word_immediate(RegisterBank::R13, bits(code, 7, 0) << 2);
+            if bits(code, 11, 11) == 0 {
+                Some(CondInstr::STR { rd: rd, addr_ref: addr_ref })
+            } else {
+                Some(CondInstr::LDR { rd: rd, addr_ref: addr_ref })
+            }
+        },
+        0b1010 => {
+            let rd = low_register(code, 8);
+            let offset = bits(code, 7, 0) << 2;
+            if bits(code, 11, 11) == 0 {
+                return Some(Instruction::Thumb(ThumbInstr::ADR { rd: rd, offset: offset as i32 }));
+            }
+            Some(CondInstr::ADD {
+                s: false,
+                rd: rd,
+                rn: RegisterBank::R13,
+                shift_op: BarrelShiftOp::Imm(offset),
+            })
+        },
+        0b1011 => return decode_miscellaneous(code),
+        0b1100 => {
+            let rn = low_register(code, 8);
+            let reg_list = register_list(bits(code, 7, 0), None);
+            if reg_list.is_empty() {
+                None
+            } else if bits(code, 11, 11) == 0 {
+                Some(CondInstr::STM {
+                    mode: LoadStoreMultiple::IA,
+                    carrot: false,
+                    w: true,
+                    rn: rn,
+                    reg_list: reg_list,
+                })
+            } else {
+                // The base is only written back when it isn't loaded.
+                Some(CondInstr::LDM {
+                    mode: LoadStoreMultiple::IA,
+                    carrot: false,
+                    w: !reg_list.contains(&rn),
+                    rn: rn,
+                    reg_list: reg_list,
+                })
+            }
+        },
+        0b1101 => {
+            return match bits(code, 11, 8) {
+                0b1110 => None,
+                0b1111 => Some(Instruction::Cond(CondInstr::SWI(bits(code, 7, 0)), Condition::AL)),
+                cond => Condition::decode(cond).map(|cond| {
+                    Instruction::Cond(CondInstr::B(sign_extend(bits(code, 7, 0) << 1, 9)), cond)
+                }),
+            };
+        },
+        _ => {
+            if bits(code, 11, 11) == 0 {
+                Some(CondInstr::B(sign_extend(bits(code, 10, 0) << 1, 12)))
+            } else {
+                None
+            }
+        },
+    };
+    instr.map(|instr| Instruction::Cond(instr, Condition::AL))
+}
+
+/// Decode LSL, LSR and ASR by an immediate, and the three-operand ADD
+/// and SUB.
+fn decode_shift_add_subtract(code: u32, s: bool) -> Option<CondInstr> {
+    let rd = low_register(code, 0);
+    let rm_or_rn = low_register(code, 3);
+    let op = bits(code, 12, 11);
+    if op != 0b11 {
+        let shift_size = ShiftSize::Imm(bits(code, 10, 6));
+        return BarrelShiftOp::decode(rm_or_rn, op, shift_size).map(|shift_op| {
+            CondInstr::MOV { s: s, rd: rd, shift_op: shift_op }
+        });
+    }
+
+    let shift_op = if bits(code, 10, 10) == 1 {
+        BarrelShiftOp::Imm(bits(code, 8, 6))
+    } else {
+        BarrelShiftOp::Reg(low_register(code, 6))
+    };
+    if bits(code, 9, 9) == 0 {
+        Some(CondInstr::ADD { s: s, rd: rd, rn: rm_or_rn, shift_op: shift_op })
+    } else {
+        Some(CondInstr::SUB { s: s, rd: rd, rn: rm_or_rn, shift_op: shift_op })
+    }
+}
+
+/// Decode MOV, CMP, ADD and SUB with an 8-bit immediate.
+fn decode_immediate(code: u32, s: bool) -> Option<CondInstr> {
+    let rd = low_register(code, 8);
+    let shift_op = BarrelShiftOp::Imm(bits(code, 7, 0));
+    match bits(code, 12, 11) {
+        0b00 => Some(CondInstr::MOV { s: s, rd: rd, shift_op: shift_op }),
+        0b01 => Some(CondInstr::CMP { rn: rd, shift_op: shift_op }),
+        0b10 => Some(CondInstr::ADD { s: s, rd: rd, rn: rd, shift_op: shift_op }),
+        _ => Some(CondInstr::SUB { s: s, rd: rd, rn: rd, shift_op: shift_op }),
+    }
+}
+
+/// Decode the two-operand data-processing instructions on the low
+/// registers.
+fn decode_alu(code: u32, s: bool) -> Option<CondInstr> {
+    let rd = low_register(code, 0);
+    let rm = low_register(code, 3);
+    let reg = BarrelShiftOp::Reg(rm);
+    let shift = |op| BarrelShiftOp::decode(rd, op, ShiftSize::Reg(rm));
+    match bits(code, 9, 6) {
+        0x0 => Some(CondInstr::AND { s: s, rd: rd, rn: rd, shift_op: reg }),
+        0x1 => Some(CondInstr::EOR { s: s, rd: rd, rn: rd, shift_op: reg }),
+        0x2 => shift(0b00).map(|shift_op| CondInstr::MOV { s: s, rd: rd, shift_op: shift_op }),
+        0x3 => shift(0b01).map(|shift_op| CondInstr::MOV { s: s, rd: rd, shift_op: shift_op }),
+        0x4 => shift(0b10).map(|shift_op| CondInstr::MOV { s: s, rd: rd, shift_op: shift_op }),
+        0x5 => Some(CondInstr::ADC { s: s, rd: rd, rn: rd, shift_op: reg }),
+        0x6 => Some(CondInstr::SBC { s: s, rd: rd, rn: rd, shift_op: reg }),
+        0x7 => shift(0b11).map(|shift_op| CondInstr::MOV { s: s, rd: rd, shift_op: shift_op }),
+        0x8 => Some(CondInstr::TST { rn: rd, shift_op: reg }),
+        0x9 => Some(CondInstr::RSB { s: s, rd: rd, rn: rm, shift_op: BarrelShiftOp::Imm(0) }),
+        0xa => Some(CondInstr::CMP { rn: rd, shift_op: reg }),
+        0xb => Some(CondInstr::CMN { rn: rd, shift_op: reg }),
+        0xc => Some(CondInstr::ORR { s: s, rd: rd, rn: rd, shift_op: reg }),
+        0xd => Some(CondInstr::MUL { s: s, rd: rd, rm: rm, rs: rd }),
+        0xe => Some(CondInstr::BIC { s: s, rd: rd, rn: rd, shift_op: reg }),
+        _ => Some(CondInstr::MVN { s: s, rd: rd, shift_op: reg }),
+    }
+}
+
+/// Decode ADD, CMP and MOV on any registers, along with BX and BLX.
+/// None of them set flags, except CMP.
+fn decode_hi_register(code: u32) -> Option<CondInstr> {
+    let rd = RegisterBank::decode((bits(code, 7, 7) << 3) | bits(code, 2, 0));
+    let rm = RegisterBank::decode(bits(code, 6, 3));
+    match bits(code, 9, 8) {
+        0b00 => Some(CondInstr::ADD { s: false, rd: rd, rn: rd, shift_op: BarrelShiftOp::Reg(rm) }),
+        0b01 => Some(CondInstr::CMP { rn: rd, shift_op: BarrelShiftOp::Reg(rm) }),
+        0b10 => Some(CondInstr::MOV { s: false, rd: rd, shift_op: BarrelShiftOp::Reg(rm) }),
+        _ => {
+            if bits(code, 7, 7) == 0 {
+                Some(CondInstr::BX(rm))
+            } else {
+                Some(CondInstr::BLX(rm))
+            }
+        },
+    }
+}
+
+/// Decode the loads and stores with a register offset.
+fn decode_register_offset(code: u32) -> Option<CondInstr> {
+    let rm = low_register(code, 6);
+    let rn = low_register(code, 3);
+    let rd = low_register(code, 0);
+    let word = WordOrUnsignedByte::PreIndex {
+        offset: AddressingOffset12::Register { base_addr: rn, offset: rm },
+        positive: true,
+        writeback: false,
+    };
+    let halfword = HalfwordOrSigned::PreIndex {
+        offset: AddressingOffset8::Register { base_addr: rn, offset: rm },
+        positive: true,
+        writeback: false,
+    };
+    match bits(code, 11, 9) {
+        0b000 => Some(CondInstr::STR { rd: rd, addr_ref: word }),
+        0b001 => Some(CondInstr::STRH { rd: rd, addr_ref: halfword }),
+        0b010 => Some(CondInstr::STRB { rd: rd, addr_ref: word }),
+        0b011 => Some(CondInstr::LDRSB { rd: rd, addr_ref: halfword }),
+        0b100 => Some(CondInstr::LDR { rd: rd, addr_ref: word }),
+        0b101 => Some(CondInstr::LDRH { rd: rd, addr_ref: halfword }),
+        0b110 => Some(CondInstr::LDRB { rd: rd, addr_ref: word }),
+        _ => Some(CondInstr::LDRSH { rd: rd, addr_ref: halfword }),
+    }
+}
+
+/// Decode the word and byte loads and stores with a 5-bit immediate
+/// offset, scaled by the access size.
+fn decode_immediate_offset(code: u32) -> Option<CondInstr> {
+    let rn = low_register(code, 3);
+    let rd = low_register(code, 0);
+    let imm5 = bits(code, 10, 6);
+    match (bits(code, 12, 12) == 1, bits(code, 11, 11) == 1) {
+        (false, false) => Some(CondInstr::STR { rd: rd, addr_ref: word_immediate(rn, imm5 << 2) }),
+        (false, true) => Some(CondInstr::LDR { rd: rd, addr_ref: word_immediate(rn, imm5 << 2) }),
+        (true, false) => Some(CondInstr::STRB { rd: rd, addr_ref: word_immediate(rn, imm5) }),
+        (true, true) => Some(CondInstr::LDRB { rd: rd, addr_ref: word_immediate(rn, imm5) }),
+    }
+}
+
+/// Decode the stack pointer adjustments, PUSH and POP, CBZ and CBNZ,
+/// the extends, IT and NOP.
+fn decode_miscellaneous(code: u32) -> Option<Instruction> {
+    let instr = match bits(code, 11, 8) {
+        0b0000 => {
+            let shift_op = BarrelShiftOp::Imm(bits(code, 6, 0) << 2);
+            let sp = RegisterBank::R13;
+            if bits(code, 7, 7) == 0 {
+                CondInstr::ADD { s: false, rd: sp, rn: sp, shift_op: shift_op }
+            } else {
+                CondInstr::SUB { s: false, rd: sp, rn: sp, shift_op: shift_op }
+            }
+        },
+        0b0010 => {
+            let rd = low_register(code, 0);
+            let rm = low_register(code, 3);
+            match bits(code, 7, 6) {
+                0b00 => CondInstr::SXTH { rd: rd, rm: rm, rotate: 0 },
+                0b01 => CondInstr::SXTB { rd: rd, rm: rm, rotate: 0 },
+                0b10 => CondInstr::UXTH { rd: rd, rm: rm, rotate: 0 },
+                _ => CondInstr::UXTB { rd: rd, rm: rm, rotate: 0 },
+            }
+        },
+        0b0001 | 0b0011 | 0b1001 | 0b1011 => {
+            let rn = low_register(code, 0);
+            let offset = (bits(code, 9, 9) << 6) | (bits(code, 7, 3) << 1);
+            return if bits(code, 11, 11) == 0 {
+                Some(Instruction::Thumb(ThumbInstr::CBZ { rn: rn, offset: offset }))
+            } else {
+                Some(Instruction::Thumb(ThumbInstr::CBNZ { rn: rn, offset: offset }))
+            };
+        },
+        0b0100 | 0b0101 => {
+            let extra = if bits(code, 8, 8) == 1 { Some(RegisterBank::R14) } else { None };
+            let reg_list = register_list(bits(code, 7, 0), extra);
+            if reg_list.is_empty() {
+                return None;
+            }
+            CondInstr::STM {
+                mode: LoadStoreMultiple::DB,
+                carrot: false,
+                w: true,
+                rn: RegisterBank::R13,
+                reg_list: reg_list,
+            }
+        },
+        0b1100 | 0b1101 => {
+            let extra = if bits(code, 8, 8) == 1 { Some(RegisterBank::R15) } else { None };
+            let reg_list = register_list(bits(code, 7, 0), extra);
+            if reg_list.is_empty() {
+                return None;
+            }
+            CondInstr::LDM {
+                mode: LoadStoreMultiple::IA,
+                carrot: false,
+                w: true,
+                rn: RegisterBank::R13,
+                reg_list: reg_list,
+            }
+        },
+        0b1111 => {
+            let mask = bits(code, 3, 0);
+            return if mask != 0 {
+                Condition::decode(bits(code, 7, 4)).map(|firstcond| {
+                    Instruction::Thumb(ThumbInstr::IT { firstcond: firstcond, mask: mask })
+                })
+            } else if bits(code, 7, 4) == 0 {
+                Some(Instruction::Thumb(ThumbInstr::NOP))
+            } else {
+                None // TODO: YIELD, WFE, WFI and SEV
+            };
+        },
+        0b1110 => return Some(Instruction::Thumb(ThumbInstr::BKPT(bits(code, 7, 0)))),
+        _ => return None, // TODO: REV
+    };
+    Some(Instruction::Cond(instr, Condition::AL))
+}
+
+fn low_register(code: u32, lo: u16) -> RegisterBank {
+    RegisterBank::decode(bits(code, lo + 2, lo))
+}
+
+/// The low registers selected by an 8-bit list, followed by `extra`.
+fn register_list(list: u32, extra: Option<RegisterBank>) -> Vec<RegisterBank> {
+    let mut reg_list: Vec<RegisterBank> = (0..8)
+        .filter(|&i| list & (1 << i) != 0)
+        .map(RegisterBank::decode)
+        .collect();
+    if let Some(reg) = extra {
+        reg_list.push(reg);
+    }
+    reg_list
+}
+
+pub(crate) fn word_immediate(rn: RegisterBank, offset: u32) -> WordOrUnsignedByte {
+    WordOrUnsignedByte::PreIndex {
+        offset: AddressingOffset12::Immed { base_addr: rn, offset12: offset as u16 },
+        positive: true,
+        writeback: false,
+    }
+}
+
+fn halfword_immediate(rn: RegisterBank, offset: u32) -> HalfwordOrSigned {
+    HalfwordOrSigned::PreIndex {
+        offset: AddressingOffset8::Immed { base_addr: rn, offset8: offset as u8 },
+        positive: true,
+        writeback: false,
+    }
+}
+
+/// Sign-extend the low `width` bits of a value.
+pub(crate) fn sign_extend(value: u32, width: u32) -> i32 {
+    ((value << (32 - width)) as i32) >> (32 - width)
+}
+
+/// Write a Thumb-only instruction in UAL. `pc` is the value the
+/// instruction reads from R15: its address plus 4.
+pub(crate) fn as_str(instr: &ThumbInstr, pc: u32, symbols: &SymbolTable) -> String {
+    let address = |addr_ref| word_or_byte_address_str(addr_ref, pc, symbols);
+    let target = |addr: u32| address_str(addr, symbols);
+    match *instr {
+        ThumbInstr::ADR { ref rd, offset } => format!("adr {}, {}", rd, target((pc & !3).wrapping_add(offset as u32))),
+        ThumbInstr::BKPT(imm) => format!("bkpt {}", immediate_str(imm)),
+        ThumbInstr::CBNZ { ref rn, offset } => format!("cbnz {}, {}", rn, target(pc.wrapping_add(offset))),
+        ThumbInstr::CBZ { ref rn, offset } => format!("cbz {}, {}", rn, target(pc.wrapping_add(offset))),
+        ThumbInstr::IT { ref firstcond, mask } => {
+            // Each mask bit above the lowest set one adds an
+            // instruction: then if it matches firstcond's low bit,
+            // else otherwise.
+            let low_bit = Condition::encode(firstcond.clone()) & 1;
+            let mut suffix = String::new();
+            for i in (mask.trailing_zeros() + 1..4).rev() {
+                suffix.push(if (mask >> i) & 1 == low_bit { 't' } else { 'e' });
+            }
+            format!("it{} {}", suffix, condition_suffix(firstcond))
+        },
+        ThumbInstr::LDRD { ref rd, ref rd2, ref addr_ref } => {
+            format!("ldrd {}, {}, {}", rd, rd2, address(addr_ref))
+        },
+        ThumbInstr::LDRH { ref rd, ref addr_ref } => format!("ldrh {}, {}", rd, address(addr_ref)),
+        ThumbInstr::LDRSB { ref rd, ref addr_ref } => format!("ldrsb {}, {}", rd, address(addr_ref)),
+        ThumbInstr::LDRSH { ref rd, ref addr_ref } => format!("ldrsh {}, {}", rd, address(addr_ref)),
+        ThumbInstr::NOP => "nop".to_owned(),
+        ThumbInstr::ORN { s, ref rd, ref rn, ref shift_op } => data_processing_str("orn", s, "", rd, rn, shift_op),
+        ThumbInstr::STRD { ref rd, ref rd2, ref addr_ref } => {
+            format!("strd {}, {}, {}", rd, rd2, address(addr_ref))
+        },
+        ThumbInstr::STRH { ref rd, ref addr_ref } => format!("strh {}, {}", rd, address(addr_ref)),
+        ThumbInstr::TBB { ref rn, ref rm } => format!("tbb [{}, {}]", rn, rm),
+        ThumbInstr::TBH { ref rn, ref rm } => format!("tbh [{}, {}, lsl #1]", rn, rm),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, is_wide, ThumbInstr};
+    use processor::{
+        AddressingOffset12,
+        AddressingOffset8,
+        BarrelShiftOp,
+        Condition,
+        CondInstr,
+        HalfwordOrSigned,
+        Instruction,
+        LoadStoreMultiple,
+        ShiftSize,
+        UncondInstr,
+        WordOrUnsignedByte,
+    };
+    use registers::RegisterBank::*;
+    use registers::RegisterBank;
+
+    fn word_immed(rn: RegisterBank, offset12: u16) -> WordOrUnsignedByte {
+        WordOrUnsignedByte::PreIndex {
+            offset: AddressingOffset12::Immed { base_addr: rn, offset12: offset12 },
+            positive: true,
+            writeback: false,
+        }
+    }
+
+    fn halfword_immed(rn: RegisterBank, offset8: u8) -> HalfwordOrSigned {
+        HalfwordOrSigned::PreIndex {
+            offset: AddressingOffset8::Immed { base_addr: rn, offset8: offset8 },
+            positive: true,
+            writeback: false,
+        }
+    }
+
+    #[test]
+    fn decode_data_processing() {
+        let decodings = vec![
+            // lsls r1, r2, #3
+            (0x00d1, CondInstr::MOV { s: true, rd: R1, shift_op: BarrelShiftOp::LSL(R2, ShiftSize::Imm(3)) }),
+            // lsrs r1, r2, #32
+            (0x0811, CondInstr::MOV { s: true, rd: R1, shift_op: BarrelShiftOp::LSR(R2, ShiftSize::Imm(32)) }),
+            // adds r0, r1, r2
+            (0x1888, CondInstr::ADD { s: true, rd: R0, rn: R1, shift_op: BarrelShiftOp::Reg(R2) }),
+            // subs r3, r4, #7
+            (0x1fe3, CondInstr::SUB { s: true, rd: R3, rn: R4, shift_op: BarrelShiftOp::Imm(7) }),
+            // movs r5, #200
+            (0x25c8, CondInstr::MOV { s: true, rd: R5, shift_op: BarrelShiftOp::Imm(200) }),
+            // cmp r6, #1
+            (0x2e01, CondInstr::CMP { rn: R6, shift_op: BarrelShiftOp::Imm(1) }),
+            // adds r7, #255
+            (0x37ff, CondInstr::ADD { s: true, rd: R7, rn: R7, shift_op: BarrelShiftOp::Imm(255) }),
+            // lsls r1, r2
+            (0x4091, CondInstr::MOV { s: true, rd: R1, shift_op: BarrelShiftOp::LSL(R1, ShiftSize::Reg(R2)) }),
+            // negs r3, r4
+            (0x4263, CondInstr::RSB { s: true, rd: R3, rn: R4, shift_op: BarrelShiftOp::Imm(0) }),
+            // muls r5, r6
+            (0x4375, CondInstr::MUL { s: true, rd: R5, rm: R6, rs: R5 }),
+            // add r8, r1
+            (0x4488, CondInstr::ADD { s: false, rd: R8, rn: R8, shift_op: BarrelShiftOp::Reg(R1) }),
+            // mov pc, lr
+            (0x46f7, CondInstr::MOV { s: false, rd: R15, shift_op: BarrelShiftOp::Reg(R14) }),
+            // bx lr
+            (0x4770, CondInstr::BX(R14)),
+            // blx r3
+            (0x4798, CondInstr::BLX(R3)),
+            // add r6, sp, #16
+            (0xae04, CondInstr::ADD { s: false, rd: R6, rn: R13, shift_op: BarrelShiftOp::Imm(16) }),
+            // add sp, #508
+            (0xb07f, CondInstr::ADD { s: false, rd: R13, rn: R13, shift_op: BarrelShiftOp::Imm(508) }),
+            // sub sp, #4
+            (0xb081, CondInstr::SUB { s: false, rd: R13, rn: R13, shift_op: BarrelShiftOp::Imm(4) }),
+        ];
+
+        for (code, expected_instr) in decodings {
+            assert_eq!(decode(code, 0, false).unwrap(),
+                       Instruction::Cond(expected_instr, Condition::AL),
+                       "decoding {:#06x}", code);
+        }
+
+        // adr r5, #8
+        assert_eq!(decode(0xa502, 0, false), Some(Instruction::Thumb(ThumbInstr::ADR { rd: R5, offset: 8 })));
+    }
+
+    #[test]
+    fn decode_loads_and_stores() {
+        let decodings = vec![
+            // ldr r2, [pc, #16]
+            (0x4a04, CondInstr::LDR { rd: R2, addr_ref: word_immed(R15, 16) }),
+            // str r0, [r1, r2]
+            (0x5088,
+             CondInstr::STR {
+                 rd: R0,
+                 addr_ref: WordOrUnsignedByte::PreIndex {
+                     offset: AddressingOffset12::Register { base_addr: R1, offset: R2 },
+                     positive: true,
+                     writeback: false,
+                 },
+             }),
+            // ldrsh r3, [r4, r5]
+            (0x5f63,
+             CondInstr::LDRSH {
+                 rd: R3,
+                 addr_ref: HalfwordOrSigned::PreIndex {
+                     offset: AddressingOffset8::Register { base_addr: R4, offset: R5 },
+                     positive: true,
+                     writeback: false,
+                 },
+             }),
+            // ldrb r6, [r7, #31]
+            (0x7ffe, CondInstr::LDRB { rd: R6, addr_ref: word_immed(R7, 31) }),
+            // str r0, [r1, #124]
+            (0x67c8, CondInstr::STR { rd: R0, addr_ref: word_immed(R1, 124) }),
+            // strh r2, [r3, #62]
+            (0x87da, CondInstr::STRH { rd: R2, addr_ref: halfword_immed(R3, 62) }),
+            // ldr r4, [sp, #1020]
+            (0x9cff, CondInstr::LDR { rd: R4, addr_ref: word_immed(R13, 1020) }),
+            // push {r4-r7, lr}
+            (0xb5f0,
+             CondInstr::STM {
+                 mode: LoadStoreMultiple::DB,
+                 carrot: false,
+                 w: true,
+                 rn: R13,
+                 reg_list: vec![R4, R5, R6, R7, R14],
+             }),
+            // pop {r0, pc}
+            (0xbd01, CondInstr::LDM { mode: LoadStoreMultiple::IA, carrot: false, w: true, rn: R13, reg_list: vec![R0, R15] }),
+            // stmia r0!, {r1, r2}
+            (0xc006, CondInstr::STM { mode: LoadStoreMultiple::IA, carrot: false, w: true, rn: R0, reg_list: vec![R1, R2] }),
+            // ldmia r1!, {r2, r3}
+            (0xc90c, CondInstr::LDM { mode: LoadStoreMultiple::IA, carrot: false, w: true, rn: R1, reg_list: vec![R2, R3] }),
+            // ldmia r1, {r1, r2}
+            (0xc906, CondInstr::LDM { mode: LoadStoreMultiple::IA, carrot: false, w: false, rn: R1, reg_list: vec![R1, R2] }),
+        ];
+
+        for (code, expected_instr) in decodings {
+            assert_eq!(decode(code, 0, false).unwrap(),
+                       Instruction::Cond(expected_instr, Condition::AL),
+                       "decoding {:#06x}", code);
+        }
+
+        // Empty register lists are undefined.
+        for &code in [0xb400, 0xbc00, 0xc000].iter() {
+            assert_eq!(decode(code, 0, false), None, "decoding {:#06x}", code);
+        }
+    }
+
+    #[test]
+    fn decode_branches() {
+        // beq #-4
+        assert_eq!(decode(0xd0fe, 0, false), Some(Instruction::Cond(CondInstr::B(-4), Condition::EQ)));
+        // svc #42
+        assert_eq!(decode(0xdf2a, 0, false), Some(Instruction::Cond(CondInstr::SWI(42), Condition::AL)));
+        // b #2046
+        assert_eq!(decode(0xe3ff, 0, false), Some(Instruction::Cond(CondInstr::B(2046), Condition::AL)));
+        // Condition 0b1110 is undefined.
+        assert_eq!(decode(0xdefe, 0, false), None);
+
+        // bl #-4194304
+        assert!(is_wide(0xf400));
+        assert_eq!(decode(0xf400, 0xf800, false), Some(Instruction::Cond(CondInstr::BL(-4194304), Condition::AL)));
+        // blx #8
+        assert_eq!(decode(0xf000, 0xe804, false), Some(Instruction::Uncond(UncondInstr::BLX(8))));
+        // A BLX offset has to be word aligned.
+        assert_eq!(decode(0xf000, 0xe805, false), None);
+    }
+
+    #[test]
+    fn decode_thumb2_narrow() {
+        // ite eq
+        assert_eq!(decode(0xbf0c, 0, false), Some(Instruction::Thumb(ThumbInstr::IT { firstcond: Condition::EQ, mask: 0xc })));
+        // itet gt
+        assert_eq!(decode(0xbfca, 0, false), Some(Instruction::Thumb(ThumbInstr::IT { firstcond: Condition::GT, mask: 0xa })));
+        // nop
+        assert_eq!(decode(0xbf00, 0, false), Some(Instruction::Thumb(ThumbInstr::NOP)));
+        // cbz r3, #8
+        assert_eq!(decode(0xb123, 0, false), Some(Instruction::Thumb(ThumbInstr::CBZ { rn: R3, offset: 8 })));
+        // cbnz r0, #126
+        assert_eq!(decode(0xbbf8, 0, false), Some(Instruction::Thumb(ThumbInstr::CBNZ { rn: R0, offset: 126 })));
+        // uxtb r1, r2
+        assert_eq!(decode(0xb2d1, 0, false), Some(Instruction::Cond(CondInstr::UXTB { rd: R1, rm: R2, rotate: 0 }, Condition::AL)));
+        // sxth r3, r4
+        assert_eq!(decode(0xb223, 0, false), Some(Instruction::Cond(CondInstr::SXTH { rd: R3, rm: R4, rotate: 0 }, Condition::AL)));
+
+        // adds r0, r0, r1 sets the flags only outside an IT block.
+        let add = |s| Some(Instruction::Cond(CondInstr::ADD { s: s, rd: R0, rn: R0, shift_op: BarrelShiftOp::Reg(R1) }, Condition::AL));
+        assert_eq!(decode(0x1840, 0, false), add(true));
+        assert_eq!(decode(0x1840, 0, true), add(false));
+    }
+
+    #[test]
+    fn disassemble_thumb() {
+        let disassemblies = vec![
+            (0x100, 0xbf0c, 0, "ite eq"),
+            (0x100, 0xbfca, 0, "itet gt"),
+            (0x100, 0xbf00, 0, "nop"),
+            (0x100, 0xbeab, 0, "bkpt #0xab"),
+            (0x100, 0xb123, 0, "cbz r3, 0x10c"),
+            (0x102, 0xa004, 0, "adr r0, 0x114"),
+            (0x100, 0xe7fe, 0, "b 0x100"),
+            (0x100, 0x25c8, 0, "movs r5, #0xc8"),
+            (0x100, 0x1888, 0, "adds r0, r1, r2"),
+            (0x100, 0x4800, 0, "ldr r0, [pc, #0] @ 0x104"),
+            (0x100, 0xe8d0, 0xf001, "tbb [r0, r1]"),
+            (0x100, 0xe8d0, 0xf011, "tbh [r0, r1, lsl #1]"),
+            (0x100, 0xe9d2, 0x0102, "ldrd r0, r1, [r2, #8]"),
+            (0x100, 0xf8b5, 0x1002, "ldrh r1, [r5, #2]"),
+            (0x100, 0xea61, 0x0002, "orn r0, r1, r2"),
+        ];
+        for (addr, first, second, expected) in disassemblies {
+            let instr = decode(first, second, false).unwrap_or_else(|| panic!("{:#06x} didn't decode", first));
+            assert_eq!(instr.as_thumb_str(addr), expected, "{:#06x} {:#06x}", first, second);
+        }
+    }
+}
@@ -0,0 +1,589 @@
+//! The 32-bit Thumb instructions added by Thumb-2 (ARMv6T2 and ARMv7),
+//! along with the BL and BLX pairs of earlier Thumb. Each is written
+//! as two halfwords, the first of which `thumb::is_wide` recognizes.
+
+use processor::{
+    bits,
+    AddressingOffset12,
+    BarrelShiftOp,
+    Condition,
+    CondInstr,
+    Decodable,
+    Instruction,
+    LoadStoreMultiple,
+    ShiftSize,
+    UncondInstr,
+    WordOrUnsignedByte,
+};
+use registers::RegisterBank;
+use thumb::{sign_extend, ThumbInstr};
+
+/// Decode a 32-bit Thumb instruction from its two halfwords.
+pub fn decode(first: u16, second: u16) -> Option<Instruction> {
+    let hw1 = first as u32;
+    let hw2 = second as u32;
+    match bits(hw1, 12, 11) {
+        0b01 => {
+            match bits(hw1, 10, 9) {
+                0b00 if bits(hw1, 6, 6) == 0 => decode_load_store_multiple(hw1, hw2),
+                0b00 => decode_load_store_dual(hw1, hw2),
+                0b01 => decode_shifted_register(hw1, hw2),
+                _ => None, // TODO: coprocessors
+            }
+        },
+        0b10 => {
+            if bits(hw2, 15, 15) == 1 {
+                decode_branch(hw1, hw2)
+            } else if bits(hw1, 9, 9) == 0 {
+                decode_modified_immediate(hw1, hw2)
+            } else {
+                decode_plain_immediate(hw1, hw2)
+            }
+        },
+        0b11 => {
+            if bits(hw1, 10, 9) == 0b00 && (bits(hw1, 8, 8) == 0 || bits(hw1, 4, 4) == 1) {
+                decode_load_store_single(hw1, hw2)
+            } else if bits(hw1, 10, 8) == 0b010 {
+                decode_data_processing_register(hw1, hw2)
+            } else if bits(hw1, 10, 7) == 0b0110 {
+                decode_multiply(hw1, hw2)
+            } else if bits(hw1, 10, 7) == 0b0111 {
+                decode_long_multiply(hw1, hw2)
+            } else {
+                None // TODO: coprocessors and SIMD
+            }
+        },
+        _ => None,
+    }
+}
+
+fn always(instr: CondInstr) -> Instruction {
+    Instruction::Cond(instr, Condition::AL)
+}
+
+fn register(code: u32, lo: u16) -> RegisterBank {
+    RegisterBank::decode(bits(code, lo + 3, lo))
+}
+
+/// Decode LDM, STM, and the PUSH and POP of any registers.
+fn decode_load_store_multiple(hw1: u32, hw2: u32) -> Option<Instruction> {
+    let mode = match bits(hw1, 8, 7) {
+        0b01 => LoadStoreMultiple::IA,
+        0b10 => LoadStoreMultiple::DB,
+        _ => return None, // TODO: SRS and RFE
+    };
+    let w = bits(hw1, 5, 5) == 1;
+    let rn = register(hw1, 0);
+    let reg_list: Vec<RegisterBank> = (0..16)
+        .filter(|&i| hw2 & (1 << i) != 0)
+        .map(RegisterBank::decode)
+        .collect();
+    if reg_list.is_empty() {
+        return None;
+    }
+    if bits(hw1, 4, 4) == 1 {
+        Some(always(CondInstr::LDM { mode: mode, carrot: false, w: w, rn: rn, reg_list: reg_list }))
+    } else {
+        Some(always(CondInstr::STM { mode: mode, carrot: false, w: w, rn: rn, reg_list: reg_list }))
+    }
+}
+
+/// Decode LDRD, STRD, TBB and TBH.
+fn decode_load_store_dual(hw1: u32, hw2: u32) -> Option<Instruction> {
+    let p = bits(hw1, 8, 8) == 1;
+    let u = bits(hw1, 7, 7) == 1;
+    let w = bits(hw1, 5, 5) == 1;
+    let rn = register(hw1, 0);
+
+    if !p && !w {
+        let table_branch = bits(hw1, 7, 4) == 0b1101 && bits(hw2, 15, 5) == 0b11110000000;
+        if !table_branch {
+            return None; // TODO: exclusives
+        }
+        let rm = register(hw2, 0);
+        return if bits(hw2, 4, 4) == 0 {
+            Some(Instruction::Thumb(ThumbInstr::TBB { rn: rn, rm: rm }))
+        } else {
+            Some(Instruction::Thumb(ThumbInstr::TBH { rn: rn, rm: rm }))
+        };
+    }
+
+    let rd = register(hw2, 12);
+    let rd2 = register(hw2, 8);
+    let addr_ref = immediate_address(rn, bits(hw2, 7, 0) << 2, p, u, w);
+    if bits(hw1, 4, 4) == 1 {
+        Some(Instruction::Thumb(ThumbInstr::LDRD { rd: rd, rd2: rd2, addr_ref: addr_ref }))
+    } else {
+        Some(Instruction::Thumb(ThumbInstr::STRD { rd: rd, rd2: rd2, addr_ref: addr_ref }))
+    }
+}
+
+/// Decode the data-processing instructions whose second operand is a
+/// register shifted by an immediate.
+fn decode_shifted_register(hw1: u32, hw2: u32) -> Option<Instruction> {
+    let imm5 = (bits(hw2, 14, 12) << 2) | bits(hw2, 7, 6);
+    let shift_op = BarrelShiftOp::decode(register(hw2, 0), bits(hw2, 5, 4), ShiftSize::Imm(imm5))?;
+    decode_data_processing(hw1, hw2, shift_op)
+}
+
+/// Decode the data-processing instructions whose second operand is a
+/// modified immediate.
+fn decode_modified_immediate(hw1: u32, hw2: u32) -> Option<Instruction> {
+    let imm12 = (bits(hw1, 10, 10) << 11) | (bits(hw2, 14, 12) << 8) | bits(hw2, 7, 0);
+    let shift_op = thumb_expand_imm(imm12)?;
+    decode_data_processing(hw1, hw2, shift_op)
+}
+
+/// Decode the shared opcodes of the shifted register and modified
+/// immediate forms. Comparisons are the flag-setting forms that
+/// write to the PC, and MOV and MVN the ones that read it.
+fn decode_data_processing(hw1: u32, hw2: u32, shift_op: BarrelShiftOp) -> Option<Instruction> {
+    let s = bits(hw1, 4, 4) == 1;
+    let rn = register(hw1, 0);
+    let rd = register(hw2, 8);
+    let compare = s && rd == RegisterBank::R15;
+    let move_ = rn == RegisterBank::R15;
+    let instr = match bits(hw1, 8, 5) {
+        0b0000 if compare => CondInstr::TST { rn: rn, shift_op: shift_op },
+        0b0000 => CondInstr::AND { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b0001 => CondInstr::BIC { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b0010 if move_ => CondInstr::MOV { s: s, rd: rd, shift_op: shift_op },
+        0b0010 => CondInstr::ORR { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b0011 if move_ => CondInstr::MVN { s: s, rd: rd, shift_op: shift_op },
+        0b0011 => return Some(Instruction::Thumb(ThumbInstr::ORN { s: s, rd: rd, rn: rn, shift_op: shift_op })),
+        0b0100 if compare => CondInstr::TEQ { rn: rn, shift_op: shift_op },
+        0b0100 => CondInstr::EOR { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b1000 if compare => CondInstr::CMN { rn: rn, shift_op: shift_op },
+        0b1000 => CondInstr::ADD { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b1010 => CondInstr::ADC { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b1011 => CondInstr::SBC { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b1101 if compare => CondInstr::CMP { rn: rn, shift_op: shift_op },
+        0b1101 => CondInstr::SUB { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        0b1110 => CondInstr::RSB { s: s, rd: rd, rn: rn, shift_op: shift_op },
+        _ => return None, // TODO: PKHBT and PKHTB
+    };
+    Some(always(instr))
+}
+
+/// Expand a modified immediate: either a byte repeated in a pattern,
+/// which leaves the carry flag alone, or a byte with its top bit set
+/// rotated by 8 to 31 bits, which sets it to bit 31.
+fn thumb_expand_imm(imm12: u32) -> Option<BarrelShiftOp> {
+    let imm8 = bits(imm12, 7, 0);
+    if bits(imm12, 11, 10) != 0b00 {
+        let unrotated = 0x80 | bits(imm12, 6, 0);
+        let rotation = bits(imm12, 11, 7);
+        // RotateImmed only rotates by even amounts, but shifting the
+        // immediate left makes up an odd one.
+        return if rotation.is_multiple_of(2) {
+            Some(BarrelShiftOp::RotateImmed { immed: unrotated, rotate: rotation / 2 })
+        } else {
+            Some(BarrelShiftOp::RotateImmed { immed: unrotated << 1, rotate: (rotation + 1) / 2 })
+        };
+    }
+    let value = match bits(imm12, 9, 8) {
+        0b00 => imm8,
+        _ if imm8 == 0 => return None,
+        0b01 => (imm8 << 16) | imm8,
+        0b10 => (imm8 << 24) | (imm8 << 8),
+        _ => imm8 * 0x01010101,
+    };
+    Some(BarrelShiftOp::Imm(value))
+}
+
+/// Decode the instructions with a plain 12- or 16-bit immediate, and
+/// the bitfield instructions.
+fn decode_plain_immediate(hw1: u32, hw2: u32) -> Option<Instruction> {
+    let rn = register(hw1, 0);
+    let rd = register(hw2, 8);
+    let imm12 = (bits(hw1, 10, 10) << 11) | (bits(hw2, 14, 12) << 8) | bits(hw2, 7, 0);
+    let imm16 = (bits(hw1, 3, 0) << 12) | imm12;
+    let lsb = (bits(hw2, 14, 12) << 2) | bits(hw2, 7, 6);
+    let msb_or_widthm1 = bits(hw2, 4, 0);
+    let pc = RegisterBank::R15;
+
+    let instr = match bits(hw1, 8, 4) {
+        0b00000 if rn == pc => return Some(Instruction::Thumb(ThumbInstr::ADR { rd: rd, offset: imm12 as i32 })),
+        0b00000 => CondInstr::ADD { s: false, rd: rd, rn: rn, shift_op: BarrelShiftOp::Imm(imm12) },
+        0b01010 if rn == pc => return Some(Instruction::Thumb(ThumbInstr::ADR { rd: rd, offset: -(imm12 as i32) })),
+        0b01010 => CondInstr::SUB { s: false, rd: rd, rn: rn, shift_op: BarrelShiftOp::Imm(imm12) },
+        0b00100 => CondInstr::MOV { s: false, rd: rd, shift_op: BarrelShiftOp::Imm(imm16) },
+        0b01100 => CondInstr::MOVT { rd: rd, imm16: imm16 },
+        0b10100 | 0b11100 if lsb + msb_or_widthm1 > 31 => return None,
+        0b10100 => CondInstr::SBFX { rd: rd, rn: rn, lsb: lsb, widthm1: msb_or_widthm1 },
+        0b11100 => CondInstr::UBFX { rd: rd, rn: rn, lsb: lsb, widthm1: msb_or_widthm1 },
+        0b10110 if msb_or_widthm1 < lsb => return None,
+        0b10110 if rn == pc => CondInstr::BFC { rd: rd, lsb: lsb, msb: msb_or_widthm1 },
+        0b10110 => CondInstr::BFI { rd: rd, rn: rn, lsb: lsb, msb: msb_or_widthm1 },
+        _ => return None, // TODO: SSAT and USAT
+    };
+    Some(always(instr))
+}
+
+/// Decode the wide branches, BL and BLX.
+fn decode_branch(hw1: u32, hw2: u32) -> Option<Instruction> {
+    let s = bits(hw1, 10, 10);
+    let j1 = bits(hw2, 13, 13);
+    let j2 = bits(hw2, 11, 11);
+    // The J bits hold the next offset bits inverted unless they match
+    // the sign, so that Thumb's old BL pairs keep their meaning.
+    let i1 = !(j1 ^ s) & 1;
+    let i2 = !(j2 ^ s) & 1;
+    let offset = sign_extend(
+        (s << 24) | (i1 << 23) | (i2 << 22) | (bits(hw1, 9, 0) << 12) | (bits(hw2, 10, 0) << 1),
+        25);
+
+    match (bits(hw2, 14, 14), bits(hw2, 12, 12)) {
+        (0, 0) => {
+            if bits(hw1, 9, 7) != 0b111 {
+                let imm = (s << 20) | (j2 << 19) | (j1 << 18) | (bits(hw1, 5, 0) << 12) | (bits(hw2, 10, 0) << 1);
+                Condition::decode(bits(hw1, 9, 6)).map(|cond| {
+                    Instruction::Cond(CondInstr::B(sign_extend(imm, 21)), cond)
+                })
+            } else if hw1 == 0xf3af && hw2 == 0x8000 {
+                Some(Instruction::Thumb(ThumbInstr::NOP))
+            } else {
+                None // TODO: MSR, MRS, hints and barriers
+            }
+        },
+        (0, _) => Some(always(CondInstr::B(offset))),
+        (_, 0) if bits(hw2, 0, 0) == 1 => None,
+        (_, 0) => Some(Instruction::Uncond(UncondInstr::BLX(offset))),
+        (_, _) => Some(always(CondInstr::BL(offset))),
+    }
+}
+
+/// Decode the single loads and stores, by a 12-bit immediate, an
+/// 8-bit one with any indexing, or a register shifted left by up to
+/// 3 bits. Loads can also be relative to the word-aligned PC.
+fn decode_load_store_single(hw1: u32, hw2: u32) -> Option<Instruction> {
+    let load = bits(hw1, 4, 4) == 1;
+    let signed = bits(hw1, 8, 8) == 1;
+    let size = bits(hw1, 6, 5);
+    let rn = register(hw1, 0);
+    let rd = register(hw2, 12);
+    if size == 0b11 {
+        return None;
+    }
+    if load && size != 0b10 && rd == RegisterBank::R15 {
+        return None; // TODO: PLD and PLI
+    }
+
+    let addr_ref = if bits(hw1, 7, 7) == 1 || (load && rn == RegisterBank::R15) {
+        immediate_address(rn, bits(hw2, 11, 0), true, bits(hw1, 7, 7) == 1, false)
+    } else if bits(hw2, 11, 11) == 1 {
+        let p = bits(hw2, 10, 10) == 1;
+        let w = bits(hw2, 8, 8) == 1;
+        if !p && !w {
+            return None;
+        }
+        // The unprivileged forms behave the same without an MMU.
+        immediate_address(rn, bits(hw2, 7, 0), p, bits(hw2, 9, 9) == 1, w)
+    } else if bits(hw2, 11, 6) == 0 {
+        let rm = register(hw2, 0);
+        let offset = if bits(hw2, 5, 4) == 0 {
+            AddressingOffset12::Register { base_addr: rn, offset: rm }
+        } else {
+            AddressingOffset12::ScaledRegister {
+                base_addr: rn,
+                shift_op: BarrelShiftOp::LSL(rm, ShiftSize::Imm(bits(hw2, 5, 4))),
+            }
+        };
+        WordOrUnsignedByte::PreIndex { offset: offset, positive: true, writeback: false }
+    } else {
+        return None;
+    };
+
+    match (load, signed, size) {
+        (false, false, 0b00) => Some(always(CondInstr::STRB { rd: rd, addr_ref: addr_ref })),
+        (false, false, 0b01) => Some(Instruction::Thumb(ThumbInstr::STRH { rd: rd, addr_ref: addr_ref })),
+        (false, false, 0b10) => Some(always(CondInstr::STR { rd: rd, addr_ref: addr_ref })),
+        (true, false, 0b00) => Some(always(CondInstr::LDRB { rd: rd, addr_ref: addr_ref })),
+        (true, true, 0b00) => Some(Instruction::Thumb(ThumbInstr::LDRSB { rd: rd, addr_ref: addr_ref })),
+        (true, false, 0b01) => Some(Instruction::Thumb(ThumbInstr::LDRH { rd: rd, addr_ref: addr_ref })),
+        (true, true, 0b01) => Some(Instruction::Thumb(ThumbInstr::LDRSH { rd: rd, addr_ref: addr_ref })),
+        (true, false, 0b10) => Some(always(CondInstr::LDR { rd: rd, addr_ref: addr_ref })),
+        _ => None,
+    }
+}
+
+fn immediate_address(rn: RegisterBank, offset: u32, p: bool, u: bool, w: bool) -> WordOrUnsignedByte {
+    let offset = AddressingOffset12::Immed { base_addr: rn, offset12: offset as u16 };
+    if p {
+        WordOrUnsignedByte::PreIndex { offset: offset, positive: u, writeback: w }
+    } else {
+        WordOrUnsignedByte::PostIndex { offset: offset, positive: u }
+    }
+}
+
+/// Decode the shifts by a register and the extends.
+fn decode_data_processing_register(hw1: u32, hw2: u32) -> Option<Instruction> {
+    if bits(hw2, 15, 12) != 0b1111 {
+        return None;
+    }
+    let rn = register(hw1, 0);
+    let rd = register(hw2, 8);
+    let rm = register(hw2, 0);
+    let op1 = bits(hw1, 7, 4);
+    let op2 = bits(hw2, 7, 4);
+
+    if op1 & 0b1000 == 0 && op2 == 0 {
+        let s = bits(hw1, 4, 4) == 1;
+        return BarrelShiftOp::decode(rn, bits(hw1, 6, 5), ShiftSize::Reg(rm)).map(|shift_op| {
+            always(CondInstr::MOV { s: s, rd: rd, shift_op: shift_op })
+        });
+    }
+    if op2 & 0b1000 == 0 || rn != RegisterBank::R15 {
+        return None; // TODO: extend and add, parallel arithmetic, CLZ and REV
+    }
+    let rotate = bits(hw2, 5, 4) * 8;
+    match op1 {
+        0b0000 => Some(always(CondInstr::SXTH { rd: rd, rm: rm, rotate: rotate })),
+        0b0001 => Some(always(CondInstr::UXTH { rd: rd, rm: rm, rotate: rotate })),
+        0b0100 => Some(always(CondInstr::SXTB { rd: rd, rm: rm, rotate: rotate })),
+        0b0101 => Some(always(CondInstr::UXTB { rd: rd, rm: rm, rotate: rotate })),
+        _ => None,
+    }
+}
+
+/// Decode MUL, MLA and MLS, none of which set flags.
+fn decode_multiply(hw1: u32, hw2: u32) -> Option<Instruction> {
+    if bits(hw1, 6, 4) != 0 {
+        return None; // TODO: halfword and dual multiplies
+    }
+    let rn = register(hw1, 0);
+    let ra = register(hw2, 12);
+    let rd = register(hw2, 8);
+    let rm = register(hw2, 0);
+    match bits(hw2, 7, 4) {
+        0b0000 if ra == RegisterBank::R15 => Some(always(CondInstr::MUL { s: false, rd: rd, rm: rn, rs: rm })),
+        0b0000 => Some(always(CondInstr::MLA { s: false, rd: rd, rm: rn, rs: rm, rn: ra })),
+        0b0001 => Some(always(CondInstr::MLS { rd: rd, rm: rn, rs: rm, rn: ra })),
+        _ => None,
+    }
+}
+
+/// Decode the 64-bit multiplies, none of which set flags.
+fn decode_long_multiply(hw1: u32, hw2: u32) -> Option<Instruction> {
+    if bits(hw2, 7, 4) != 0 {
+        return None; // TODO: SDIV, UDIV and the halfword multiplies
+    }
+    let rn = register(hw1, 0);
+    let rdlo = register(hw2, 12);
+    let rdhi = register(hw2, 8);
+    let rm = register(hw2, 0);
+    let instr = match bits(hw1, 6, 4) {
+        0b000 => CondInstr::SMULL { s: false, rdlo: rdlo, rdhi: rdhi, rm: rn, rs: rm },
+        0b010 => CondInstr::UMULL { s: false, rdlo: rdlo, rdhi: rdhi, rm: rn, rs: rm },
+        0b100 => CondInstr::SMLAL { s: false, rdlo: rdlo, rdhi: rdhi, rm: rn, rs: rm },
+        0b110 => CondInstr::UMLAL { s: false, rdlo: rdlo, rdhi: rdhi, rm: rn, rs: rm },
+        _ => return None,
+    };
+    Some(always(instr))
+}
+
+#[cfg(test)]
+mod test {
+    use super::decode;
+    use processor::{
+        AddressingOffset12,
+        BarrelShiftOp,
+        Condition,
+        CondInstr,
+        Instruction,
+        LoadStoreMultiple,
+        ShiftSize,
+        UncondInstr,
+        WordOrUnsignedByte,
+    };
+    use registers::RegisterBank::*;
+    use registers::RegisterBank;
+    use thumb::ThumbInstr;
+
+    fn immed(rn: RegisterBank, offset12: u16, positive: bool, writeback: bool) -> WordOrUnsignedByte {
+        WordOrUnsignedByte::PreIndex {
+            offset: AddressingOffset12::Immed { base_addr: rn, offset12: offset12 },
+            positive: positive,
+            writeback: writeback,
+        }
+    }
+
+    fn post_immed(rn: RegisterBank, offset12: u16, positive: bool) -> WordOrUnsignedByte {
+        WordOrUnsignedByte::PostIndex {
+            offset: AddressingOffset12::Immed { base_addr: rn, offset12: offset12 },
+            positive: positive,
+        }
+    }
+
+    fn check(decodings: Vec<(u16, u16, Instruction)>) {
+        for (first, second, expected_instr) in decodings {
+            assert_eq!(decode(first, second), Some(expected_instr),
+                       "decoding {:#06x} {:#06x}", first, second);
+        }
+    }
+
+    fn always(instr: CondInstr) -> Instruction {
+        Instruction::Cond(instr, Condition::AL)
+    }
+
+    #[test]
+    fn decode_data_processing() {
+        check(vec![
+            // add.w r0, r1, #0x00ab00ab
+            (0xf101, 0x10ab, always(CondInstr::ADD { s: false, rd: R0, rn: R1, shift_op: BarrelShiftOp::Imm(0x00ab00ab) })),
+            // ands r2, r3, #0xff000000
+            (0xf013, 0x427f,
+             always(CondInstr::AND { s: true, rd: R2, rn: R3, shift_op: BarrelShiftOp::RotateImmed { immed: 0xff, rotate: 4 } })),
+            // orrs r4, r5, #0x40000000, an odd rotation
+            (0xf055, 0x4480,
+             always(CondInstr::ORR { s: true, rd: R4, rn: R5, shift_op: BarrelShiftOp::RotateImmed { immed: 0x100, rotate: 5 } })),
+            // mov.w r0, #0x1fc00
+            (0xf44f, 0x30fe,
+             always(CondInstr::MOV { s: false, rd: R0, shift_op: BarrelShiftOp::RotateImmed { immed: 0x1fc, rotate: 12 } })),
+            // cmp.w r1, #256
+            (0xf5b1, 0x7f80, always(CondInstr::CMP { rn: R1, shift_op: BarrelShiftOp::RotateImmed { immed: 0x100, rotate: 16 } })),
+            // sub.w r2, r3, r4, lsl #3
+            (0xeba3, 0x02c4,
+             always(CondInstr::SUB { s: false, rd: R2, rn: R3, shift_op: BarrelShiftOp::LSL(R4, ShiftSize::Imm(3)) })),
+            // orn r5, r6, r7, asr #2
+            (0xea66, 0x05a7,
+             Instruction::Thumb(ThumbInstr::ORN { s: false, rd: R5, rn: R6, shift_op: BarrelShiftOp::ASR(R7, ShiftSize::Imm(2)) })),
+            // lsrs.w r0, r1, #1
+            (0xea5f, 0x0051, always(CondInstr::MOV { s: true, rd: R0, shift_op: BarrelShiftOp::LSR(R1, ShiftSize::Imm(1)) })),
+            // teq.w r2, r3
+            (0xea92, 0x0f03, always(CondInstr::TEQ { rn: R2, shift_op: BarrelShiftOp::LSL(R3, ShiftSize::Imm(0)) })),
+            // movw r1, #0xbeef
+            (0xf64b, 0x61ef, always(CondInstr::MOV { s: false, rd: R1, shift_op: BarrelShiftOp::Imm(0xbeef) })),
+            // movt r1, #0xdead
+            (0xf6cd, 0x61ad, always(CondInstr::MOVT { rd: R1, imm16: 0xdead })),
+            // addw r2, r3, #4095
+            (0xf603, 0x72ff, always(CondInstr::ADD { s: false, rd: R2, rn: R3, shift_op: BarrelShiftOp::Imm(4095) })),
+            // subw sp, sp, #8
+            (0xf2ad, 0x0d08, always(CondInstr::SUB { s: false, rd: R13, rn: R13, shift_op: BarrelShiftOp::Imm(8) })),
+            // adr.w r0, #-20
+            (0xf2af, 0x0014, Instruction::Thumb(ThumbInstr::ADR { rd: R0, offset: -20 })),
+            // bfi r0, r1, #4, #8
+            (0xf361, 0x100b, always(CondInstr::BFI { rd: R0, rn: R1, lsb: 4, msb: 11 })),
+            // bfc r2, #0, #16
+            (0xf36f, 0x020f, always(CondInstr::BFC { rd: R2, lsb: 0, msb: 15 })),
+            // ubfx r3, r4, #8, #4
+            (0xf3c4, 0x2303, always(CondInstr::UBFX { rd: R3, rn: R4, lsb: 8, widthm1: 3 })),
+            // sbfx r5, r6, #31, #1
+            (0xf346, 0x75c0, always(CondInstr::SBFX { rd: R5, rn: R6, lsb: 31, widthm1: 0 })),
+            // lsl.w r0, r1, r2
+            (0xfa01, 0xf002, always(CondInstr::MOV { s: false, rd: R0, shift_op: BarrelShiftOp::LSL(R1, ShiftSize::Reg(R2)) })),
+            // asrs.w r3, r4, r5
+            (0xfa54, 0xf305, always(CondInstr::MOV { s: true, rd: R3, shift_op: BarrelShiftOp::ASR(R4, ShiftSize::Reg(R5)) })),
+            // uxth.w r0, r1, ror #8
+            (0xfa1f, 0xf091, always(CondInstr::UXTH { rd: R0, rm: R1, rotate: 8 })),
+            // sxtb.w r2, r3, ror #16
+            (0xfa4f, 0xf2a3, always(CondInstr::SXTB { rd: R2, rm: R3, rotate: 16 })),
+            // mul r0, r1, r2
+            (0xfb01, 0xf002, always(CondInstr::MUL { s: false, rd: R0, rm: R1, rs: R2 })),
+            // mla r3, r4, r5, r6
+            (0xfb04, 0x6305, always(CondInstr::MLA { s: false, rd: R3, rm: R4, rs: R5, rn: R6 })),
+            // mls r7, r8, r9, r10
+            (0xfb08, 0xa719, always(CondInstr::MLS { rd: R7, rm: R8, rs: R9, rn: R10 })),
+            // umull r0, r1, r2, r3
+            (0xfba2, 0x0103, always(CondInstr::UMULL { s: false, rdlo: R0, rdhi: R1, rm: R2, rs: R3 })),
+            // smlal r4, r5, r6, r7
+            (0xfbc6, 0x4507, always(CondInstr::SMLAL { s: false, rdlo: R4, rdhi: R5, rm: R6, rs: R7 })),
+            // nop.w
+            (0xf3af, 0x8000, Instruction::Thumb(ThumbInstr::NOP)),
+        ]);
+    }
+
+    #[test]
+    fn decode_loads_and_stores() {
+        check(vec![
+            // ldr.w r0, [r1, #4095]
+            (0xf8d1, 0x0fff, always(CondInstr::LDR { rd: R0, addr_ref: immed(R1, 4095, true, false) })),
+            // ldr r2, [r3, #-255]
+            (0xf853, 0x2cff, always(CondInstr::LDR { rd: R2, addr_ref: immed(R3, 255, false, false) })),
+            // ldr r4, [r5], #4
+            (0xf855, 0x4b04, always(CondInstr::LDR { rd: R4, addr_ref: post_immed(R5, 4, true) })),
+            // ldr r6, [r7, #8]!
+            (0xf857, 0x6f08, always(CondInstr::LDR { rd: R6, addr_ref: immed(R7, 8, true, true) })),
+            // ldr.w r8, [r9, r10, lsl #2]
+            (0xf859, 0x802a,
+             always(CondInstr::LDR {
+                 rd: R8,
+                 addr_ref: WordOrUnsignedByte::PreIndex {
+                     offset: AddressingOffset12::ScaledRegister {
+                         base_addr: R9,
+                         shift_op: BarrelShiftOp::LSL(R10, ShiftSize::Imm(2)),
+                     },
+                     positive: true,
+                     writeback: false,
+                 },
+             })),
+            // ldr.w r11, [pc, #-8]
+            (0xf85f, 0xb008, always(CondInstr::LDR { rd: R11, addr_ref: immed(R15, 8, false, false) })),
+            // strh.w r0, [r1, #2]
+            (0xf8a1, 0x0002, Instruction::Thumb(ThumbInstr::STRH { rd: R0, addr_ref: immed(R1, 2, true, false) })),
+            // ldrsb.w r2, [r3, r4]
+            (0xf913, 0x2004,
+             Instruction::Thumb(ThumbInstr::LDRSB {
+                 rd: R2,
+                 addr_ref: WordOrUnsignedByte::PreIndex {
+                     offset: AddressingOffset12::Register { base_addr: R3, offset: R4 },
+                     positive: true,
+                     writeback: false,
+                 },
+             })),
+            // ldrsh r5, [r6], #-2
+            (0xf936, 0x5902, Instruction::Thumb(ThumbInstr::LDRSH { rd: R5, addr_ref: post_immed(R6, 2, false) })),
+            // strb.w r7, [r8, #1]
+            (0xf888, 0x7001, always(CondInstr::STRB { rd: R7, addr_ref: immed(R8, 1, true, false) })),
+            // ldrd r0, r1, [r2, #-8]!
+            (0xe972, 0x0102, Instruction::Thumb(ThumbInstr::LDRD { rd: R0, rd2: R1, addr_ref: immed(R2, 8, false, true) })),
+            // strd r4, r6, [sp], #16
+            (0xe8ed, 0x4604, Instruction::Thumb(ThumbInstr::STRD { rd: R4, rd2: R6, addr_ref: post_immed(R13, 16, true) })),
+            // ldrd r2, r3, [pc, #16]
+            (0xe9df, 0x2304, Instruction::Thumb(ThumbInstr::LDRD { rd: R2, rd2: R3, addr_ref: immed(R15, 16, true, false) })),
+            // push.w {r4-r11, lr}
+            (0xe92d, 0x4ff0,
+             always(CondInstr::STM {
+                 mode: LoadStoreMultiple::DB,
+                 carrot: false,
+                 w: true,
+                 rn: R13,
+                 reg_list: vec![R4, R5, R6, R7, R8, R9, R10, R11, R14],
+             })),
+            // pop.w {r4-r11, pc}
+            (0xe8bd, 0x8ff0,
+             always(CondInstr::LDM {
+                 mode: LoadStoreMultiple::IA,
+                 carrot: false,
+                 w: true,
+                 rn: R13,
+                 reg_list: vec![R4, R5, R6, R7, R8, R9, R10, R11, R15],
+             })),
+            // ldmdb r0!, {r1, r2}
+            (0xe930, 0x0006,
+             always(CondInstr::LDM { mode: LoadStoreMultiple::DB, carrot: false, w: true, rn: R0, reg_list: vec![R1, R2] })),
+        ]);
+
+        // Empty register lists are undefined.
+        assert_eq!(decode(0xe92d, 0x0000), None);
+        assert_eq!(decode(0xe8bd, 0x0000), None);
+    }
+
+    #[test]
+    fn decode_branches() {
+        check(vec![
+            // b.w #-1048576
+            (0xf700, 0xb800, always(CondInstr::B(-1048576))),
+            // bne.w #1048574
+            (0xf07f, 0xafff, Instruction::Cond(CondInstr::B(1048574), Condition::NE)),
+            // bl #16777214
+            (0xf3ff, 0xd7ff, always(CondInstr::BL(16777214))),
+            // blx #-16777216
+            (0xf400, 0xc000, Instruction::Uncond(UncondInstr::BLX(-16777216))),
+            // tbb [pc, r0]
+            (0xe8df, 0xf000, Instruction::Thumb(ThumbInstr::TBB { rn: R15, rm: R0 })),
+            // tbh [r1, r2, lsl #1]
+            (0xe8d1, 0xf012, Instruction::Thumb(ThumbInstr::TBH { rn: R1, rm: R2 })),
+        ]);
+    }
+}